@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE todo ADD COLUMN due_date TIMESTAMP;
+CREATE INDEX IF NOT EXISTS idx_todo_due_date ON todo(due_date);
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE todo ADD COLUMN completed_at TIMESTAMP;
+UPDATE todo SET completed_at = updated_at WHERE completed = 1;
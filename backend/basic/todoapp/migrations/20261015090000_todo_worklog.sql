@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS todo_worklog (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    todo_id INTEGER NOT NULL REFERENCES todo(id) ON DELETE CASCADE,
+    started_at TIMESTAMP NOT NULL,
+    ended_at TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS idx_todo_worklog_todo_id ON todo_worklog(todo_id);
+-- At most one running timer per todo
+CREATE UNIQUE INDEX IF NOT EXISTS idx_todo_worklog_open ON todo_worklog(todo_id) WHERE ended_at IS NULL;
@@ -0,0 +1,14 @@
+-- Add migration script here
+-- Accent- and case-folded copy of each todo's title and description, filled in from Rust
+CREATE TABLE IF NOT EXISTS todo_search (
+    todo_id INTEGER PRIMARY KEY REFERENCES todo(id) ON DELETE CASCADE,
+    content TEXT NOT NULL
+);
+
+-- Stale entries are dropped on edit and rebuilt before the next search
+CREATE TRIGGER IF NOT EXISTS invalidate_todo_search
+AFTER UPDATE OF title, description ON todo
+FOR EACH ROW
+BEGIN
+    DELETE FROM todo_search WHERE todo_id = OLD.id;
+END;
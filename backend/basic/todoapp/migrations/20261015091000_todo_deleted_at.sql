@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE todo ADD COLUMN deleted_at TIMESTAMP;
+CREATE INDEX IF NOT EXISTS idx_todo_deleted_at ON todo(deleted_at);
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE todo ADD COLUMN reminder_offset_minutes INTEGER;
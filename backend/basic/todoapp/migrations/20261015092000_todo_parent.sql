@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE todo ADD COLUMN parent_id INTEGER REFERENCES todo(id) ON DELETE SET NULL;
+CREATE INDEX IF NOT EXISTS idx_todo_parent_id ON todo(parent_id);
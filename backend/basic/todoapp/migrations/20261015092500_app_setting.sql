@@ -0,0 +1,5 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS app_setting (
+    key TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE todo ADD COLUMN important BOOLEAN NOT NULL DEFAULT FALSE;
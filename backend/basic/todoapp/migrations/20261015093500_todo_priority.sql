@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE todo ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+-- Add migration script here
+CREATE VIEW IF NOT EXISTS todo_with_tags AS
+SELECT todo.*,
+    (SELECT json_group_array(tags.name ORDER BY tags.name) FROM todo_tags
+        JOIN tags ON tags.id = todo_tags.tag_id WHERE todo_tags.todo_id = todo.id) AS tags
+FROM todo;
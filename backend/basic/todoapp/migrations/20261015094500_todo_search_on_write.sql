@@ -0,0 +1,3 @@
+-- Add migration script here
+-- todo_search rows are now written in the same transaction as the todo itself
+DROP TRIGGER IF EXISTS invalidate_todo_search;
@@ -0,0 +1,248 @@
+use std::fmt;
+
+use axum::{
+    Json,
+    extract::Request,
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+
+/// A failed request. Responds with the variant's status and a `{"error": "..."}` JSON body.
+#[derive(Debug)]
+pub enum AppError {
+    /// 400: the request can't be understood, e.g. an unparsable parameter
+    BadRequest(String),
+    /// 400: a well-formed request with a value that breaks a rule, e.g. a title that is too long
+    Validation(String),
+    NotFound(String),
+    Conflict(String),
+    Unauthorized(String),
+    Forbidden(String),
+    UnsupportedMediaType(String),
+    /// 503: a query ran longer than the configured limit and the request gave up on it
+    QueryTimeout(std::time::Duration),
+    /// 404 for `RowNotFound`, 503 for timeouts and a locked database, 500 otherwise
+    Database(sqlx::Error),
+}
+
+/// The JSON body of every error response.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ErrorBody {
+    pub error: String,
+}
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// An RFC 9457 problem details body, sent instead of [`ErrorBody`] to clients that accept
+/// `application/problem+json`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProblemDetails {
+    /// Always `about:blank`: the status code alone says what kind of problem it is
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+}
+
+impl ProblemDetails {
+    pub fn new(status: StatusCode, detail: String) -> Self {
+        Self {
+            problem_type: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            status: status.as_u16(),
+            detail,
+        }
+    }
+}
+
+impl IntoResponse for ProblemDetails {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        (status, [(header::CONTENT_TYPE, PROBLEM_JSON)], Json(self)).into_response()
+    }
+}
+
+/// Rewrites `AppError` responses as problem details when the request's `Accept` header
+/// asks for `application/problem+json`. Other headers, such as `Retry-After`, are kept.
+pub async fn negotiate_problem_details(request: Request, next: Next) -> Response {
+    let wants_problem = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains(PROBLEM_JSON));
+    let response = next.run(request).await;
+    if !wants_problem {
+        return response;
+    }
+    let Some(problem) = response.extensions().get::<ProblemDetails>().cloned() else {
+        return response;
+    };
+    let (mut parts, _) = response.into_parts();
+    let (problem_parts, body) = problem.into_response().into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.extend(problem_parts.headers);
+    Response::from_parts(parts, body)
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) | AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::QueryTimeout(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Database(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND,
+            AppError::Database(sqlx::Error::PoolTimedOut) => StatusCode::SERVICE_UNAVAILABLE,
+            // SQLITE_BUSY: busy_timeout elapsed while waiting for a lock
+            AppError::Database(sqlx::Error::Database(e)) if e.code().as_deref() == Some("5") => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            AppError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::BadRequest(message)
+            | AppError::Validation(message)
+            | AppError::NotFound(message)
+            | AppError::Conflict(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::UnsupportedMediaType(message) => f.write_str(message),
+            AppError::QueryTimeout(limit) => {
+                write!(f, "Query timed out after {} ms", limit.as_millis())
+            }
+            AppError::Database(sqlx::Error::RowNotFound) => f.write_str("Not found"),
+            AppError::Database(e) => write!(f, "Database error: {e}"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        AppError::Database(e)
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            tracing::error!("Request failed: {self}");
+        }
+        let body = ErrorBody {
+            error: self.to_string(),
+        };
+        let problem = ProblemDetails::new(status, body.error.clone());
+        let mut response = (status, Json(body)).into_response();
+        response.extensions_mut().insert(problem);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_error_response_status_and_body() {
+        let response = AppError::Validation("Title cannot be empty".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: ErrorBody = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body.error, "Title cannot be empty");
+
+        assert_eq!(
+            AppError::from(sqlx::Error::RowNotFound).status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            AppError::from(sqlx::Error::PoolTimedOut).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            AppError::from(sqlx::Error::PoolClosed).status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn test_problem_details_negotiation() {
+        use tower::ServiceExt;
+
+        let app = axum::Router::new()
+            .route(
+                "/missing",
+                axum::routing::get(|| async {
+                    Err::<(), _>(AppError::NotFound("Todo item not found".to_string()))
+                }),
+            )
+            .route(
+                "/invalid",
+                axum::routing::get(|| async {
+                    Err::<(), _>(AppError::Validation("Title cannot be empty".to_string()))
+                }),
+            )
+            .layer(axum::middleware::from_fn(negotiate_problem_details));
+        let get = |uri: &str, accept: Option<&str>| {
+            let mut request = axum::http::Request::builder().uri(uri);
+            if let Some(accept) = accept {
+                request = request.header(header::ACCEPT, accept);
+            }
+            app.clone()
+                .oneshot(request.body(axum::body::Body::empty()).unwrap())
+        };
+        let problem = |response: Response| async move {
+            assert_eq!(response.headers()[header::CONTENT_TYPE], PROBLEM_JSON);
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+
+        let response = get("/missing", Some("application/problem+json"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            problem(response).await,
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Not Found",
+                "status": 404,
+                "detail": "Todo item not found"
+            })
+        );
+        let response = get(
+            "/invalid",
+            Some("application/json, application/problem+json"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = problem(response).await;
+        assert_eq!(body["title"], "Bad Request");
+        assert_eq!(body["status"], 400);
+        assert_eq!(body["detail"], "Title cannot be empty");
+
+        // Without asking for it, the usual error body is sent
+        let response = get("/missing", None).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: ErrorBody = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body.error, "Todo item not found");
+    }
+}
@@ -0,0 +1,135 @@
+use std::fmt;
+
+use crate::storage::{Comparison, TodoFilter};
+
+/// A syntax error in a `?filter=` string, with the 0-based byte position it was found at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+fn error(position: usize, message: impl Into<String>) -> FilterError {
+    FilterError {
+        position,
+        message: message.into(),
+    }
+}
+
+/// Parses the compact filter grammar used by `GET /todos?filter=`: comma-separated
+/// `key:value` clauses that must all match, e.g. `completed:false,priority:>=2,tag:work`.
+///
+/// Supported keys are `completed` and `important` (`true`/`false`), `priority` (an integer,
+/// optionally prefixed by `=`, `!=`, `<`, `<=`, `>` or `>=`) and `tag` (a tag name).
+pub fn parse_filter(input: &str) -> Result<Vec<TodoFilter>, FilterError> {
+    let mut filters = Vec::new();
+    let mut offset = 0;
+    for clause in input.split(',') {
+        filters.push(parse_clause(clause, offset)?);
+        offset += clause.len() + 1;
+    }
+    Ok(filters)
+}
+
+fn parse_clause(clause: &str, offset: usize) -> Result<TodoFilter, FilterError> {
+    if clause.is_empty() {
+        return Err(error(offset, "expected a key:value clause"));
+    }
+    let Some((key, value)) = clause.split_once(':') else {
+        return Err(error(offset + clause.len(), "expected ':' after key"));
+    };
+    let value_at = offset + key.len() + 1;
+    match key {
+        "completed" => parse_bool(value, value_at).map(TodoFilter::Completed),
+        "important" => parse_bool(value, value_at).map(TodoFilter::Important),
+        "priority" => {
+            let (comparison, number) = split_comparison(value);
+            let number_at = value_at + value.len() - number.len();
+            let priority = number
+                .parse::<i64>()
+                .map_err(|_| error(number_at, "expected an integer priority"))?;
+            Ok(TodoFilter::Priority(comparison, priority))
+        }
+        "tag" if value.is_empty() => Err(error(value_at, "expected a tag name")),
+        "tag" => Ok(TodoFilter::Tag(value.to_string())),
+        _ => Err(error(offset, format!("unknown filter key '{key}'"))),
+    }
+}
+
+fn parse_bool(value: &str, position: usize) -> Result<bool, FilterError> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(error(position, "expected true or false")),
+    }
+}
+
+/// Splits a leading comparison operator off `value`; no operator means equality.
+fn split_comparison(value: &str) -> (Comparison, &str) {
+    // Two-character operators first so ">=" isn't read as ">" followed by "=2"
+    const OPERATORS: [(&str, Comparison); 6] = [
+        (">=", Comparison::Ge),
+        ("<=", Comparison::Le),
+        ("!=", Comparison::Ne),
+        (">", Comparison::Gt),
+        ("<", Comparison::Lt),
+        ("=", Comparison::Eq),
+    ];
+    OPERATORS
+        .iter()
+        .find_map(|(operator, comparison)| {
+            value.strip_prefix(operator).map(|rest| (*comparison, rest))
+        })
+        .unwrap_or((Comparison::Eq, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_multi_clause_filter() {
+        assert_eq!(
+            parse_filter("completed:false,priority:>=2,tag:work,important:true").unwrap(),
+            [
+                TodoFilter::Completed(false),
+                TodoFilter::Priority(Comparison::Ge, 2),
+                TodoFilter::Tag("work".to_string()),
+                TodoFilter::Important(true),
+            ]
+        );
+        assert_eq!(
+            parse_filter("priority:3").unwrap(),
+            [TodoFilter::Priority(Comparison::Eq, 3)]
+        );
+        assert_eq!(
+            parse_filter("priority:!=-1").unwrap(),
+            [TodoFilter::Priority(Comparison::Ne, -1)]
+        );
+    }
+
+    #[test]
+    fn test_parse_malformed_filters() {
+        let position = |input: &str| parse_filter(input).unwrap_err().position;
+        assert_eq!(position(""), 0);
+        assert_eq!(position("completed:false,"), 16);
+        assert_eq!(position("completed"), 9);
+        assert_eq!(position("completed:maybe"), 10);
+        assert_eq!(position("tag:work,colour:red"), 9);
+        assert_eq!(position("priority:>=high"), 11);
+        assert_eq!(position("priority:=>2"), 10);
+        assert_eq!(position("tag:"), 4);
+        assert_eq!(
+            parse_filter("completed:false,owner:me")
+                .unwrap_err()
+                .to_string(),
+            "unknown filter key 'owner' at position 16"
+        );
+    }
+}
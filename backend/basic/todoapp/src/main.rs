@@ -1,17 +1,34 @@
+mod error;
+mod filter;
+mod seed;
 mod storage;
 
+use std::collections::{HashMap, HashSet};
+use std::future::IntoFuture;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
+use crate::error::AppError;
 use crate::storage::DB_URL;
 use axum::{
     Extension, Json, Router,
-    extract::Path,
-    http::StatusCode,
+    error_handling::HandleErrorLayer,
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::IntoResponse,
     routing::{delete, get, post, put},
 };
 use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tower::{BoxError, ServiceBuilder, limit::GlobalConcurrencyLimitLayer};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::limit::RequestBodyLimitLayer;
 use tower_http::trace::{DefaultMakeSpan, TraceLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use unicode_segmentation::UnicodeSegmentation;
 
 #[tokio::main]
 async fn main() {
@@ -24,59 +41,683 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let database = storage::init_db(DB_URL).await.unwrap();
+    let bind_addr = parse_bind_addr(std::env::var("BIND_ADDR").ok().as_deref())
+        .unwrap_or_else(|e| panic!("{e}"));
+    let cors = cors_layer(std::env::var("ALLOWED_ORIGIN").ok().as_deref())
+        .unwrap_or_else(|e| panic!("{e}"));
+    let pool_config = pool_config_from_env();
+    let config = Arc::new(AppConfig::from_env(&pool_config));
+    let database = storage::init_db(&db_url(), &pool_config).await.unwrap();
+    if let Ok(path) = std::env::var("SEED_FILE") {
+        let validate = |todo: &storage::NewTodo| {
+            validate_todo_fields(&todo.title, todo.description.as_deref(), &config)
+        };
+        match seed::seed_if_empty(&database, &path, validate).await {
+            Ok(0) => tracing::info!("Database already has todos, skipping SEED_FILE {path}"),
+            Ok(created) => tracing::info!("Seeded {created} todo(s) from {path}"),
+            Err(e) => panic!("Failed to load SEED_FILE {path}: {e}"),
+        }
+    }
     let state: Arc<sqlx::Pool<sqlx::Sqlite>> = Arc::new(database);
+    let in_flight = Arc::new(AtomicUsize::new(0));
+
+    let app = limit_concurrency(
+        routes(),
+        env_or("MAX_CONCURRENT_REQUESTS", DEFAULT_MAX_CONCURRENT_REQUESTS),
+    )
+    .layer(RequestBodyLimitLayer::new(env_or(
+        "MAX_BODY_BYTES",
+        DEFAULT_MAX_BODY_BYTES,
+    )))
+    .layer(axum::middleware::from_fn(error::negotiate_problem_details))
+    .layer(axum::middleware::from_fn(pretty_json))
+    .layer(axum::middleware::from_fn_with_state(
+        Arc::new(security_headers(|key| std::env::var(key).ok())),
+        add_security_headers,
+    ))
+    .layer(Extension(state.clone()))
+    .layer(Extension(config))
+    .layer(axum::middleware::from_fn_with_state(
+        in_flight.clone(),
+        track_in_flight,
+    ))
+    .layer(cors)
+    .layer(
+        TraceLayer::new_for_http()
+            // Customize the level for different events
+            .make_span_with(DefaultMakeSpan::new().level(tracing::Level::INFO))
+            .on_request(|request: &axum::extract::Request, _span: &tracing::Span| {
+                tracing::info!(
+                    "Incoming request: {} {}",
+                    request.method(),
+                    request.uri().path()
+                );
+            })
+            .on_response(
+                |response: &axum::response::Response,
+                 latency: std::time::Duration,
+                 _span: &tracing::Span| {
+                    tracing::info!("Response: {} (latency: {:?})", response.status(), latency);
+                },
+            )
+            .on_failure(
+                |error: tower_http::classify::ServerErrorsFailureClass,
+                 latency: std::time::Duration,
+                 _span: &tracing::Span| {
+                    tracing::error!("Request failed: {:?} (latency: {:?})", error, latency);
+                },
+            ),
+    );
+
+    let listener = tokio::net::TcpListener::bind(bind_addr).await.unwrap();
+
+    tracing::info!("Server running on http://{bind_addr}");
+    let grace_period = shutdown_grace_period();
+    let shutdown = Arc::new(Notify::new());
+    let server = axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal(shutdown.clone()))
+        .into_future();
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => result.unwrap(),
+        _ = shutdown.notified() => {
+            tracing::info!(
+                "Shutdown signal received with {} request(s) in flight, waiting up to {:?}",
+                in_flight.load(Ordering::SeqCst),
+                grace_period
+            );
+            match wait_with_grace_period(&mut server, grace_period).await {
+                Some(result) => result.unwrap(),
+                None => tracing::warn!(
+                    "Grace period elapsed with {} request(s) still in flight, forcing shutdown",
+                    in_flight.load(Ordering::SeqCst)
+                ),
+            }
+        }
+    }
+
+    state.close().await;
+}
 
-    let app = Router::new()
-        .route("/", get(|| async { "Hello, World!" }))
-        .route("/health", get(|| async { "OK" }))
-        .route("/todos", get(get_todos))
+/// The application's route table. Unsupported methods on a known path get a
+/// `405 Method Not Allowed` with an `Allow` header instead of the not-found fallback.
+fn routes() -> Router {
+    Router::new()
+        .route("/", get(root))
+        .route("/health", get(health))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/todos", get(get_todos_negotiated))
+        .route(
+            "/settings/default-sort",
+            get(get_default_sort).put(set_default_sort),
+        )
         .route("/todos", post(create_todo))
         .route("/todos/{id}", get(get_todo_by_id))
         .route("/todos/{id}", put(update_todo))
         .route("/todos/{id}", delete(delete_todo))
+        .route("/todos/{id}/restore", post(restore_todo))
+        .route("/todos/{id}/purge", delete(purge_todo))
         .route("/todos/complete", get(get_complete_todos))
         .route("/todos/incomplete", get(get_incomplete_todos))
+        .route("/todos/grouped", get(get_grouped_todos))
+        .route("/todos/calendar.ics", get(get_calendar))
+        .route("/todos/export.csv", get(export_todos_csv))
+        .route("/todos/search", get(search_todos))
+        .route("/todos/duplicates", get(get_duplicate_todos))
+        .route("/todos/recent", get(get_recent_todos))
+        .route("/todos/age-buckets", get(get_age_buckets))
+        .route("/todos/reminders", get(get_reminders_due))
+        .route("/todos/due-before", get(get_todos_due_before))
         .route("/todos/time-range", post(get_todos_by_time_range))
+        .route("/todos/cleanup", delete(cleanup_completed_archived))
+        .route("/todos/bulk-due", post(set_bulk_due_date))
+        .route("/todos/bulk-restore", post(bulk_restore_todos))
+        .route("/todos/bulk-complete", post(bulk_complete_todos))
+        .route("/todos/complete-range", post(complete_todos_in_range))
+        .route("/todos/import-markdown", post(import_markdown))
+        .route("/todos/{id}/tags", put(replace_todo_tags))
+        .route("/todos/{id}/tags", post(add_todo_tag))
+        .route("/todos/{id}/tags/{tag}", delete(remove_todo_tag))
+        .route("/todos/by-tag/{tag}", get(get_todos_by_tag))
+        .route("/todos/stats", get(get_completion_stats))
+        .route("/tags/stats", get(get_tag_stats))
+        .route("/todos/{id}/reminder-offset", put(set_reminder_offset))
+        .route("/todos/{id}/parent", put(set_todo_parent))
+        .route("/todos/{id}/important", put(set_todo_important))
+        .route("/todos/{id}/priority", put(set_todo_priority))
+        .route("/todos/inconsistent", get(get_inconsistent_parents))
+        .route("/todos/tree", get(get_todo_tree))
+        .route("/todos/{id}/attachments", get(get_todo_attachments))
+        .route("/todos/{id}/attachments", post(add_todo_attachment))
+        .route(
+            "/todos/{id}/attachments/{attachment_id}",
+            delete(delete_todo_attachment),
+        )
+        .route("/todos/{id}/timer/start", post(start_todo_timer))
+        .route("/todos/{id}/timer/stop", post(stop_todo_timer))
+        .route("/todos/{id}/time-spent", get(get_todo_time_spent))
+        .route("/admin/db-stats", get(get_db_stats))
         .fallback(|| async { (StatusCode::NOT_FOUND, "Route not found") })
-        .layer(Extension(state))
-        .layer(
-            TraceLayer::new_for_http()
-                // Customize the level for different events
-                .make_span_with(DefaultMakeSpan::new().level(tracing::Level::INFO))
-                .on_request(|request: &axum::extract::Request, _span: &tracing::Span| {
-                    tracing::info!(
-                        "Incoming request: {} {}",
-                        request.method(),
-                        request.uri().path()
-                    );
-                })
-                .on_response(
-                    |response: &axum::response::Response,
-                     latency: std::time::Duration,
-                     _span: &tracing::Span| {
-                        tracing::info!("Response: {} (latency: {:?})", response.status(), latency);
-                    },
+        .method_not_allowed_fallback(|| async { StatusCode::METHOD_NOT_ALLOWED })
+}
+
+/// Runtime settings read from the environment at startup.
+#[derive(Debug, Default)]
+struct AppConfig {
+    admin_token: Option<String>,
+    reminder_offset_minutes: i64,
+    min_title_length: usize,
+    /// Lowercased terms rejected as whole words in titles
+    title_blocklist: Vec<String>,
+    /// Trim titles and collapse runs of whitespace before storing them
+    normalize_titles: bool,
+    /// With `normalize_titles`, also capitalize the first letter of every word
+    title_case: bool,
+    /// Most tags a single todo may have; 0 means no limit
+    max_tags_per_todo: usize,
+    query_limits: storage::QueryLimits,
+}
+
+impl AppConfig {
+    fn from_env(pool_config: &storage::PoolConfig) -> Self {
+        Self {
+            admin_token: std::env::var("ADMIN_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+            reminder_offset_minutes: env_or(
+                "REMINDER_OFFSET_MINUTES",
+                DEFAULT_REMINDER_OFFSET_MINUTES,
+            ),
+            min_title_length: env_or("MIN_TITLE_LENGTH", DEFAULT_MIN_TITLE_LENGTH),
+            title_blocklist: title_blocklist_from_env(),
+            normalize_titles: env_or("NORMALIZE_TITLES", false),
+            title_case: env_or("TITLE_CASE", false),
+            max_tags_per_todo: env_or("MAX_TAGS_PER_TODO", DEFAULT_MAX_TAGS_PER_TODO),
+            query_limits: pool_config.query_limits(),
+        }
+    }
+}
+
+/// Blocked title terms from `TITLE_BLOCKLIST_FILE` (one per line, `#` starts a comment) or,
+/// failing that, the comma-separated `TITLE_BLOCKLIST`.
+fn title_blocklist_from_env() -> Vec<String> {
+    let (raw, separator) = match std::env::var("TITLE_BLOCKLIST_FILE") {
+        Ok(path) => (
+            std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("Failed to read TITLE_BLOCKLIST_FILE {path}: {e}")),
+            '\n',
+        ),
+        Err(_) => (std::env::var("TITLE_BLOCKLIST").unwrap_or_default(), ','),
+    };
+    raw.split(separator)
+        .filter(|term| !term.trim_start().starts_with('#'))
+        .map(|term| term.trim().to_lowercase())
+        .filter(|term| !term.is_empty())
+        .collect()
+}
+
+/// The title as it should be stored: unchanged unless `normalize_titles` is on, in which case
+/// it is trimmed, single-spaced and, with `title_case`, capitalized word by word.
+fn normalize_title(title: &str, config: &AppConfig) -> String {
+    if !config.normalize_titles {
+        return title.to_string();
+    }
+    let words = title.split_whitespace().map(|word| {
+        if !config.title_case {
+            return word.to_string();
+        }
+        let mut chars = word.chars();
+        chars
+            .next()
+            .map(|first| first.to_uppercase().chain(chars).collect())
+            .unwrap_or_default()
+    });
+    words.collect::<Vec<_>>().join(" ")
+}
+
+fn title_words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Length as a reader would count it: user-perceived characters (grapheme clusters), so an
+/// emoji with modifiers or a CJK character counts once however many bytes it takes.
+fn grapheme_count(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+/// Rejects titles shorter than `min_title_length` characters or containing a blocked term.
+/// Terms match case-insensitively on whole words, so "class" doesn't trip on "ass".
+fn validate_title(title: &str, config: &AppConfig) -> Result<(), AppError> {
+    let length = grapheme_count(title.trim());
+    if length == 0 {
+        return Err(AppError::Validation(
+            "Title cannot be empty or only whitespace".to_string(),
+        ));
+    }
+    if length < config.min_title_length {
+        return Err(AppError::Validation(format!(
+            "Title must be at least {} characters",
+            config.min_title_length
+        )));
+    }
+    if length > MAX_TITLE_LENGTH {
+        return Err(AppError::Validation(format!(
+            "Title must be at most {MAX_TITLE_LENGTH} characters"
+        )));
+    }
+    let words = title_words(title);
+    for term in &config.title_blocklist {
+        let term_words = title_words(term);
+        if !term_words.is_empty() && words.windows(term_words.len()).any(|w| w == term_words) {
+            return Err(AppError::Validation(format!(
+                "Title contains blocked term \"{term}\""
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn validate_description(description: &str) -> Result<(), AppError> {
+    if grapheme_count(description) > MAX_DESCRIPTION_LENGTH {
+        return Err(AppError::Validation(format!(
+            "Description must be at most {MAX_DESCRIPTION_LENGTH} characters"
+        )));
+    }
+    Ok(())
+}
+
+/// The checks every inserted todo goes through, whether it comes from the API, an import
+/// or a seed file.
+fn validate_todo_fields(
+    title: &str,
+    description: Option<&str>,
+    config: &AppConfig,
+) -> Result<(), AppError> {
+    validate_title(title, config)?;
+    description.map_or(Ok(()), validate_description)
+}
+
+/// Admin routes require `Authorization: Bearer <ADMIN_TOKEN>` and are disabled when no token is set.
+fn check_admin_token(headers: &HeaderMap, config: &AppConfig) -> Result<(), AppError> {
+    let Some(expected) = config.admin_token.as_deref() else {
+        return Err(AppError::Forbidden(
+            "Admin endpoints are disabled".to_string(),
+        ));
+    };
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if provided != Some(expected) {
+        return Err(AppError::Unauthorized("Invalid admin token".to_string()));
+    }
+    Ok(())
+}
+
+const DEFAULT_BIND_ADDR: &str = "0.0.0.0:3000";
+const DEFAULT_SHUTDOWN_GRACE_SECS: u64 = 30;
+const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 256;
+const DEFAULT_MAX_BODY_BYTES: usize = 64 * 1024;
+const DEFAULT_REMINDER_OFFSET_MINUTES: i64 = 0;
+const DEFAULT_MIN_TITLE_LENGTH: usize = 0;
+const DEFAULT_MAX_TAGS_PER_TODO: usize = 20;
+const MAX_TITLE_LENGTH: usize = 255;
+const MAX_DESCRIPTION_LENGTH: usize = 2000;
+
+/// The socket address to listen on from `BIND_ADDR`, e.g. `127.0.0.1:8080`.
+fn parse_bind_addr(value: Option<&str>) -> Result<SocketAddr, String> {
+    let value = value.unwrap_or(DEFAULT_BIND_ADDR);
+    value.parse().map_err(|e| {
+        format!("Invalid BIND_ADDR '{value}', expected host:port such as {DEFAULT_BIND_ADDR}: {e}")
+    })
+}
+
+/// The database URL from `DB_URL`, falling back to the built-in SQLite file.
+fn db_url() -> String {
+    std::env::var("DB_URL").unwrap_or_else(|_| DB_URL.to_string())
+}
+
+/// Reads and parses an environment variable, falling back to `default` when unset or invalid.
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+fn shutdown_grace_period() -> Duration {
+    Duration::from_secs(env_or("SHUTDOWN_GRACE_SECS", DEFAULT_SHUTDOWN_GRACE_SECS))
+}
+
+/// Pool settings from `DB_MIN_CONNECTIONS`, `DB_IDLE_TIMEOUT_SECS` and `DB_MAX_LIFETIME_SECS`.
+fn pool_config_from_env() -> storage::PoolConfig {
+    let defaults = storage::PoolConfig::default();
+    storage::PoolConfig {
+        min_connections: env_or("DB_MIN_CONNECTIONS", defaults.min_connections),
+        idle_timeout: Duration::from_secs(env_or(
+            "DB_IDLE_TIMEOUT_SECS",
+            defaults.idle_timeout.as_secs(),
+        )),
+        max_lifetime: Duration::from_secs(env_or(
+            "DB_MAX_LIFETIME_SECS",
+            defaults.max_lifetime.as_secs(),
+        )),
+        query_timeout: Duration::from_millis(env_or(
+            "DB_QUERY_TIMEOUT_MS",
+            defaults.query_timeout.as_millis() as u64,
+        )),
+        slow_query_threshold: Duration::from_millis(env_or(
+            "SLOW_QUERY_MS",
+            defaults.slow_query_threshold.as_millis() as u64,
+        )),
+    }
+}
+
+/// CORS for browser frontends. With `ALLOWED_ORIGIN` set only that origin may call the API;
+/// without it debug builds allow any origin and release builds send no CORS headers.
+fn cors_layer(allowed_origin: Option<&str>) -> Result<CorsLayer, String> {
+    let origin = match allowed_origin {
+        Some(origin) => AllowOrigin::list([origin
+            .parse()
+            .map_err(|_| format!("Invalid ALLOWED_ORIGIN '{origin}'"))?]),
+        None if cfg!(debug_assertions) => AllowOrigin::any(),
+        None => return Ok(CorsLayer::new()),
+    };
+    Ok(CorsLayer::new()
+        .allow_origin(origin)
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::AUTHORIZATION,
+            header::ACCEPT,
+            header::IF_NONE_MATCH,
+        ])
+        .expose_headers([TOTAL_COUNT_HEADER, header::ETAG]))
+}
+
+/// Sheds requests with `503 Service Unavailable` once `max` requests are already being handled.
+fn limit_concurrency(router: Router, max: usize) -> Router {
+    router.layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(|_: BoxError| async {
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Server is busy, try again later",
                 )
-                .on_failure(
-                    |error: tower_http::classify::ServerErrorsFailureClass,
-                     latency: std::time::Duration,
-                     _span: &tracing::Span| {
-                        tracing::error!("Request failed: {:?} (latency: {:?})", error, latency);
-                    },
-                ),
-        );
+            }))
+            .load_shed()
+            .layer(GlobalConcurrencyLimitLayer::new(max)),
+    )
+}
+
+/// Resolves once Ctrl-C or SIGTERM is received, notifying `notify` so the caller can start the grace period.
+async fn shutdown_signal(notify: Arc<Notify>) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl-C handler");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    notify.notify_one();
+}
+
+/// Waits for `future` for at most `grace_period`, returning `None` if it had to be abandoned.
+async fn wait_with_grace_period<F: Future>(future: F, grace_period: Duration) -> Option<F::Output> {
+    tokio::time::timeout(grace_period, future).await.ok()
+}
+
+/// Decrements the in-flight counter when a request finishes or is dropped.
+struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+async fn track_in_flight(
+    State(in_flight): State<Arc<AtomicUsize>>,
+    request: axum::extract::Request,
+    next: Next,
+) -> axum::response::Response {
+    in_flight.fetch_add(1, Ordering::SeqCst);
+    let _guard = InFlightGuard(in_flight);
+    next.run(request).await
+}
+
+#[derive(Deserialize, Debug)]
+struct PrettyQuery {
+    #[serde(default)]
+    pretty: bool,
+}
+
+/// Whether the client asked for indented JSON with `?pretty=true` or `X-Pretty: true`.
+fn wants_pretty(request: &axum::extract::Request) -> bool {
+    let header = request
+        .headers()
+        .get("x-pretty")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("true"));
+    header
+        || Query::<PrettyQuery>::try_from_uri(request.uri()).is_ok_and(|Query(query)| query.pretty)
+}
+
+/// Re-serializes JSON responses with `serde_json::to_vec_pretty` when the client asked for
+/// it; everything else, and every response by default, passes through untouched.
+async fn pretty_json(request: axum::extract::Request, next: Next) -> axum::response::Response {
+    let pretty = wants_pretty(&request);
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    if !pretty || !is_json {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let body = match serde_json::from_slice::<serde_json::Value>(&bytes)
+        .and_then(|value| serde_json::to_vec_pretty(&value))
+    {
+        Ok(pretty) => axum::body::Body::from(pretty),
+        Err(_) => axum::body::Body::from(bytes),
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    axum::response::Response::from_parts(parts, body)
+}
+
+/// Headers added to every response. `X-Content-Type-Options` and `X-Frame-Options` default
+/// to `nosniff` and `DENY`; `Content-Security-Policy` is only sent when configured. Setting
+/// a variable to an empty string drops that header.
+fn security_headers(env: impl Fn(&str) -> Option<String>) -> Vec<(HeaderName, HeaderValue)> {
+    [
+        (
+            header::X_CONTENT_TYPE_OPTIONS,
+            "X_CONTENT_TYPE_OPTIONS",
+            Some("nosniff"),
+        ),
+        (header::X_FRAME_OPTIONS, "X_FRAME_OPTIONS", Some("DENY")),
+        (
+            header::CONTENT_SECURITY_POLICY,
+            "CONTENT_SECURITY_POLICY",
+            None,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(name, key, default)| {
+        let value = env(key)
+            .or(default.map(String::from))
+            .filter(|value| !value.is_empty())?;
+        match HeaderValue::from_str(&value) {
+            Ok(value) => Some((name, value)),
+            Err(_) => {
+                tracing::warn!("Ignoring invalid {} header value", key);
+                None
+            }
+        }
+    })
+    .collect()
+}
+
+async fn add_security_headers(
+    State(headers): State<Arc<Vec<(HeaderName, HeaderValue)>>>,
+    request: axum::extract::Request,
+    next: Next,
+) -> axum::response::Response {
+    let mut response = next.run(request).await;
+    for (name, value) in headers.iter() {
+        response
+            .headers_mut()
+            .entry(name.clone())
+            .or_insert_with(|| value.clone());
+    }
+    response
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ServiceInfo {
+    service: &'static str,
+    version: &'static str,
+    status: &'static str,
+}
+
+/// Returns service metadata to clients that ask for JSON and a plain greeting otherwise.
+async fn root(headers: HeaderMap) -> axum::response::Response {
+    let wants_json = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("application/json"));
+    if wants_json {
+        Json(ServiceInfo {
+            service: "todoapp",
+            version: env!("CARGO_PKG_VERSION"),
+            status: "ok",
+        })
+        .into_response()
+    } else {
+        "Hello, World!".into_response()
+    }
+}
+
+/// What a create endpoint responds with, chosen by `?return=full|minimal`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ReturnMode {
+    /// The whole created record
+    Full,
+    /// Only the new record's id
+    Minimal,
+}
+
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Health {
+    status: String,
+}
+
+/// Health check for load balancers: `200 {"status":"ok"}` only if the database answers
+/// `SELECT 1` within `HEALTH_CHECK_TIMEOUT`, `503 {"status":"degraded"}` otherwise.
+async fn health(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+) -> (StatusCode, Json<Health>) {
+    let healthy = matches!(
+        tokio::time::timeout(HEALTH_CHECK_TIMEOUT, storage::ping(&pool)).await,
+        Ok(Ok(()))
+    );
+    let (status, label) = if healthy {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "degraded")
+    };
+    (
+        status,
+        Json(Health {
+            status: label.to_string(),
+        }),
+    )
+}
+
+/// Liveness probe: answering at all means the process is up.
+async fn healthz() -> StatusCode {
+    StatusCode::OK
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Readiness {
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+/// Readiness probe: `503` with a reason until the database answers and every migration is applied.
+async fn readyz(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+) -> (StatusCode, Json<Readiness>) {
+    let reason = match run_query(
+        &config.query_limits,
+        "pending_migrations",
+        storage::pending_migrations(&pool),
+    )
+    .await
+    {
+        Ok(pending) if pending.is_empty() => None,
+        Ok(pending) => Some(format!("Pending migrations: {pending:?}")),
+        Err(e) => Some(format!("Database unavailable: {e}")),
+    };
+    match reason {
+        None => (
+            StatusCode::OK,
+            Json(Readiness {
+                status: "ready".to_string(),
+                reason: None,
+            }),
+        ),
+        Some(reason) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(Readiness {
+                status: "not_ready".to_string(),
+                reason: Some(reason),
+            }),
+        ),
+    }
+}
 
-    axum::serve(listener, app).await.unwrap();
-    println!("Server running on http://0.0.0.0:3000");
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct CreateQuery {
+    #[serde(rename = "return")]
+    return_mode: Option<ReturnMode>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct CreateTodoBody {
     title: String,
     description: Option<String>,
+    completed: Option<bool>,
+    #[serde(default, with = "storage::rfc3339::option")]
+    due_date: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -84,144 +725,3880 @@ struct UpdateTodoBody {
     title: Option<String>,
     description: Option<String>,
     completed: Option<bool>,
+    archived: Option<bool>,
+    #[serde(default, with = "storage::rfc3339::option")]
+    due_date: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct UpdateQuery {
+    /// Required to complete an important todo; bulk completions skip them without it
+    #[serde(default)]
+    confirm: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CleanupQuery {
+    #[serde(default)]
+    confirm: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GroupedQuery {
+    incomplete_limit: Option<i64>,
+    complete_limit: Option<i64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct TimeRange {
-    start: String, // ISO 8601 format
-    end: String,   // ISO 8601 format
+    start: String, // RFC 3339, or ISO 8601 without an offset for UTC
+    end: String,   // RFC 3339, or ISO 8601 without an offset for UTC
 }
 
-async fn get_todos(
-    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
-) -> Json<Vec<storage::Todo>> {
-    let todos = storage::get_todos(&pool).await;
+const DEFAULT_RECENT_LIMIT: i64 = 10;
+const MAX_RECENT_LIMIT: i64 = 50;
 
-    todos.map(Json).unwrap_or_else(|_| Json(vec![])) // Return an empty vector on error
+#[derive(Serialize, Deserialize, Debug)]
+struct RecentQuery {
+    limit: Option<i64>,
 }
 
-async fn create_todo(
-    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
-    Json(payload): Json<CreateTodoBody>,
-) -> Result<Json<storage::Todo>, (StatusCode, String)> {
-    let todo = storage::create_todo(&pool, payload.title, payload.description).await;
+#[derive(Serialize, Deserialize, Debug)]
+struct SearchQuery {
+    q: String,
+}
 
-    match todo {
-        Ok(todo) => Ok(Json(todo)),
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to create todo item: {e}"),
-        )),
+#[derive(Serialize, Deserialize, Debug)]
+struct DueBeforeQuery {
+    before: String, // RFC 3339, or ISO 8601 without an offset for UTC
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BulkDueBody {
+    ids: Vec<i64>,
+    due_date: Option<String>, // ISO 8601 format
+    due_in_days: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BulkIdsBody {
+    ids: Vec<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BulkCompleteBody {
+    ids: Vec<i64>,
+    completed: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ReminderOffsetBody {
+    minutes: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ParentBody {
+    parent_id: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ImportantBody {
+    important: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PriorityBody {
+    priority: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct InconsistentQuery {
+    #[serde(default)]
+    fix: bool,
+}
+
+/// Subtasks nested deeper than this are left out of `GET /todos/tree`.
+const MAX_TREE_DEPTH: usize = 16;
+
+#[derive(Serialize, Debug)]
+struct TodoNode {
+    #[serde(flatten)]
+    todo: storage::Todo,
+    children: Vec<TodoNode>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TagsBody {
+    tags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TagBody {
+    tag: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct AttachmentBody {
+    name: String,
+    url: String,
+}
+
+/// Accepts absolute http(s) links with a non-empty host and no whitespace.
+fn validate_attachment_url(url: &str) -> Result<(), String> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .ok_or_else(|| "Attachment URL must start with http:// or https://".to_string())?;
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    if host.is_empty() || url.chars().any(char::is_whitespace) {
+        return Err("Attachment URL is not a well-formed link".to_string());
     }
+    Ok(())
 }
 
-async fn update_todo(
-    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
-    Path(id): Path<i64>,
-    Json(payload): Json<UpdateTodoBody>,
-) -> Result<Json<storage::Todo>, (StatusCode, String)> {
-    let todo = storage::update_todo(
-        &pool,
-        id,
-        payload.title,
-        payload.description,
-        payload.completed,
-    )
-    .await;
+/// Parses `- [ ] title` / `- [x] title` checklist lines (also `*` and `+` bullets) into
+/// `(title, completed)` pairs. Indentation is ignored, so nested items are imported flat.
+fn parse_markdown_checklist(text: &str) -> Vec<(String, bool)> {
+    text.lines()
+        .filter_map(|line| {
+            let item = line.trim_start().strip_prefix(['-', '*', '+'])?;
+            let item = item.strip_prefix(' ')?.trim_start();
+            let (completed, title) = if let Some(title) = item.strip_prefix("[ ]") {
+                (false, title)
+            } else if let Some(title) = item
+                .strip_prefix("[x]")
+                .or_else(|| item.strip_prefix("[X]"))
+            {
+                (true, title)
+            } else {
+                return None;
+            };
+            let title = title.trim();
+            (!title.is_empty()).then(|| (title.to_string(), completed))
+        })
+        .collect()
+}
 
-    match todo {
-        Ok(todo) => Ok(Json(todo)),
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to update todo item: {e}"),
-        )),
+/// Escapes TEXT property values per RFC 5545 section 3.3.11.
+fn ical_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
     }
+    escaped
 }
 
-async fn delete_todo(
-    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
-    Path(id): Path<i64>,
-) -> Result<StatusCode, (StatusCode, String)> {
-    let result = storage::delete_todo(&pool, id).await;
+/// Folds a content line to at most 75 octets per physical line, as RFC 5545 requires.
+fn ical_fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 2);
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
 
-    match result {
-        Ok(_) => Ok(StatusCode::OK),
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to delete todo item: {e}"),
-        )),
+const ICAL_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Renders todos as a VCALENDAR with one VEVENT per todo that has a due date.
+fn render_calendar(todos: &[storage::Todo], now: chrono::NaiveDateTime) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//practice_checklist//todoapp//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+    ];
+    for todo in todos {
+        let (Some(id), Some(due_date)) = (todo.id, todo.due_date) else {
+            continue;
+        };
+        lines.push("BEGIN:VEVENT".to_string());
+        lines.push(format!("UID:todo-{id}@todoapp"));
+        lines.push(format!("DTSTAMP:{}", now.format(ICAL_TIME_FORMAT)));
+        lines.push(format!("DTSTART:{}", due_date.format(ICAL_TIME_FORMAT)));
+        lines.push(format!("SUMMARY:{}", ical_escape(&todo.title)));
+        if let Some(description) = &todo.description {
+            lines.push(format!("DESCRIPTION:{}", ical_escape(description)));
+        }
+        lines.push("END:VEVENT".to_string());
     }
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|line| ical_fold(line)).collect()
 }
 
-async fn get_todo_by_id(
-    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
-    Path(id): Path<i64>,
-) -> Result<Json<storage::Todo>, (StatusCode, String)> {
-    let todo = storage::get_todo_by_id(&pool, id).await;
+const CSV_HEADER: &str =
+    "id,title,description,completed,priority,tags,due_date,created_at,completed_at";
 
-    match todo {
-        Ok(todo) => Ok(Json(todo)),
-        Err(e) => Err((StatusCode::NOT_FOUND, format!("Todo item not found: {e}"))),
+/// Quotes a CSV field when it contains a delimiter, quote or line break (RFC 4180).
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
     }
 }
 
-async fn get_complete_todos(
-    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
-) -> Result<Json<Vec<storage::Todo>>, (StatusCode, String)> {
-    let todos = storage::get_todos_by_completion(&pool, true).await;
+/// Renders todos as CSV with a header row. Tags are joined with `;`, missing values are empty.
+fn render_csv(todos: &[storage::Todo]) -> String {
+    let time = |time: Option<chrono::NaiveDateTime>| {
+        time.as_ref()
+            .map(storage::rfc3339::format)
+            .unwrap_or_default()
+    };
+    let mut csv = format!("{CSV_HEADER}\r\n");
+    for todo in todos {
+        let fields = [
+            todo.id.map(|id| id.to_string()).unwrap_or_default(),
+            todo.title.clone(),
+            todo.description.clone().unwrap_or_default(),
+            todo.completed.to_string(),
+            todo.priority.to_string(),
+            todo.tags.0.join(";"),
+            time(todo.due_date),
+            time(todo.created_at),
+            time(todo.completed_at),
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| csv_escape(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
 
-    match todos {
-        Ok(todos) => Ok(Json(todos)),
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to fetch todos: {e}"),
-        )),
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum TimeFormat {
+    #[default]
+    Rfc3339,
+    EpochMs,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TimeFormatQuery {
+    #[serde(default)]
+    time_format: TimeFormat,
+}
+
+const TIMESTAMP_FIELDS: [&str; 5] = [
+    "created_at",
+    "updated_at",
+    "due_date",
+    "completed_at",
+    "deleted_at",
+];
+
+/// Serializes `value`, rewriting timestamp fields as epoch milliseconds when requested.
+fn format_times<T: Serialize>(value: T, format: TimeFormat) -> Json<serde_json::Value> {
+    let mut value = serde_json::to_value(value).unwrap_or_default();
+    if format == TimeFormat::EpochMs {
+        timestamps_to_epoch_ms(&mut value);
     }
+    Json(value)
 }
 
-async fn get_incomplete_todos(
-    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
-) -> Result<Json<Vec<storage::Todo>>, (StatusCode, String)> {
-    let todos = storage::get_todos_by_completion(&pool, false).await;
+fn timestamps_to_epoch_ms(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Array(items) => items.iter_mut().for_each(timestamps_to_epoch_ms),
+        serde_json::Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                if !TIMESTAMP_FIELDS.contains(&key.as_str()) {
+                    timestamps_to_epoch_ms(field);
+                    continue;
+                }
+                let millis = field
+                    .as_str()
+                    .and_then(storage::rfc3339::parse)
+                    .map(|time| time.and_utc().timestamp_millis());
+                if let Some(millis) = millis {
+                    *field = millis.into();
+                }
+            }
+        }
+        _ => {}
+    }
+}
 
-    match todos {
-        Ok(todos) => Ok(Json(todos)),
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to fetch todos: {e}"),
-        )),
+const TOTAL_COUNT_HEADER: HeaderName = HeaderName::from_static("x-total-count");
+
+/// Sorts `GET /todos` explicitly; when neither field is given the persisted default applies,
+/// falling back to newest first.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct TodoSortQuery {
+    sort_by: Option<storage::TodoSortBy>,
+    order: Option<storage::SortOrder>,
+}
+
+const DEFAULT_PAGE_LIMIT: i64 = 50;
+const MAX_PAGE_LIMIT: i64 = 500;
+
+fn validate_page(limit: i64, offset: i64) -> Result<(), AppError> {
+    if !(1..=MAX_PAGE_LIMIT).contains(&limit) || offset < 0 {
+        return Err(AppError::BadRequest(format!(
+            "limit must be between 1 and {MAX_PAGE_LIMIT} and offset not negative"
+        )));
     }
+    Ok(())
 }
 
-async fn get_todos_by_time_range(
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct PageQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+    /// Only list todos with this completion state
+    completed: Option<bool>,
+    /// Compact filter grammar, see `filter::parse_filter`
+    filter: Option<String>,
+}
+
+/// `GET /todos` response: one page of items plus the size of the whole filtered list.
+#[derive(Serialize, Deserialize, Debug)]
+struct TodoPage {
+    items: Vec<storage::Todo>,
+    total: i64,
+    limit: i64,
+    offset: i64,
+}
+
+/// The list filters given by `?completed=` and `?filter=`, shared by `GET /todos` and its export.
+fn list_filters(
+    completed: Option<bool>,
+    filter: Option<&str>,
+) -> Result<Vec<storage::TodoFilter>, AppError> {
+    let mut filters = match filter.map(filter::parse_filter) {
+        Some(Ok(filters)) => filters,
+        Some(Err(e)) => return Err(AppError::BadRequest(format!("Invalid filter: {e}"))),
+        None => Vec::new(),
+    };
+    filters.extend(completed.map(storage::TodoFilter::Completed));
+    Ok(filters)
+}
+
+async fn resolve_sort(
+    pool: &sqlx::SqlitePool,
+    limits: &storage::QueryLimits,
+    sort: TodoSortQuery,
+) -> Result<storage::TodoSort, AppError> {
+    Ok(match (sort.sort_by, sort.order) {
+        (None, None) => run_query(limits, "get_default_sort", storage::get_default_sort(pool))
+            .await?
+            .unwrap_or_default(),
+        (sort_by, order) => storage::TodoSort {
+            sort_by: sort_by.unwrap_or_default(),
+            order: order.unwrap_or_default(),
+        },
+    })
+}
+
+/// Runs `query` for at most `timeout`, failing with `AppError::QueryTimeout` when it takes
+/// longer. Only the request gives up: SQLite can't be interrupted from here, so the
+/// statement runs to completion on its worker thread and holds its connection until then.
+/// `busy_timeout` separately bounds how long a statement waits for a lock.
+async fn with_query_timeout<T>(
+    timeout: Duration,
+    query: impl std::future::Future<Output = Result<T, sqlx::Error>>,
+) -> Result<T, AppError> {
+    match tokio::time::timeout(timeout, query).await {
+        Ok(result) => result.map_err(AppError::from),
+        Err(_) => Err(AppError::QueryTimeout(timeout)),
+    }
+}
+
+/// [`with_query_timeout`] with `limits.timeout`, also logging the query at WARN as
+/// `operation` when it is slower than `limits.slow_query_threshold`. Handlers make every
+/// storage call through this, so the limits apply to all of them alike.
+async fn run_query<T>(
+    limits: &storage::QueryLimits,
+    operation: &str,
+    query: impl std::future::Future<Output = Result<T, sqlx::Error>>,
+) -> Result<T, AppError> {
+    storage::timed(
+        operation,
+        limits.slow_query_threshold,
+        with_query_timeout(limits.timeout, query),
+    )
+    .await
+}
+
+async fn list_todos(
+    pool: &sqlx::SqlitePool,
+    limits: &storage::QueryLimits,
+    sort: TodoSortQuery,
+    filters: &[storage::TodoFilter],
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<TodoPage, AppError> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+    let offset = offset.unwrap_or(0);
+    validate_page(limit, offset)?;
+    let sort = resolve_sort(pool, limits, sort).await?;
+    let items = run_query(
+        limits,
+        "get_todos_page",
+        storage::get_todos_page(pool, sort, filters, limit, offset),
+    )
+    .await?;
+    let total = run_query(limits, "count_todos", storage::count_todos(pool, filters)).await?;
+    Ok(TodoPage {
+        items,
+        total,
+        limit,
+        offset,
+    })
+}
+
+async fn get_todos(
     Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
-    Json(time_range): Json<TimeRange>,
-) -> Result<Json<Vec<storage::Todo>>, (StatusCode, String)> {
-    let start_time = time_range
-        .start
-        .parse::<chrono::NaiveDateTime>()
-        .map_err(|_| {
-            (
-                StatusCode::BAD_REQUEST,
-                "Invalid start time format".to_string(),
-            )
-        })?;
-    let end_time = time_range
-        .end
-        .parse::<chrono::NaiveDateTime>()
-        .map_err(|_| {
-            (
-                StatusCode::BAD_REQUEST,
-                "Invalid end time format".to_string(),
-            )
-        })?;
-    let todos = storage::get_todos_by_time_range(&pool, start_time, end_time).await;
+    Extension(config): Extension<Arc<AppConfig>>,
+    Query(query): Query<TimeFormatQuery>,
+    Query(sort): Query<TodoSortQuery>,
+    Query(page): Query<PageQuery>,
+) -> Result<([(HeaderName, String); 1], Json<serde_json::Value>), AppError> {
+    let filters = list_filters(page.completed, page.filter.as_deref())?;
 
-    match todos {
-        Ok(todos) => Ok(Json(todos)),
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to fetch todos: {e}"),
-        )),
+    let page = list_todos(
+        &pool,
+        &config.query_limits,
+        sort,
+        &filters,
+        page.limit,
+        page.offset,
+    )
+    .await?;
+    Ok((
+        [(TOTAL_COUNT_HEADER, page.total.to_string())],
+        format_times(page, query.time_format),
+    ))
+}
+
+/// The `q` an `Accept` header gives `media_type`, from its most specific matching range
+/// (`text/csv` over `text/*` over `*/*`). 0 when nothing matches.
+fn accept_quality(accept: &str, media_type: &str) -> f32 {
+    let main_type = media_type.split('/').next().unwrap_or_default();
+    let mut best: Option<(u8, f32)> = None;
+    for range in accept.split(',') {
+        let mut params = range.split(';').map(str::trim);
+        let range = params.next().unwrap_or_default();
+        let specificity = if range.eq_ignore_ascii_case(media_type) {
+            2
+        } else if range.eq_ignore_ascii_case(&format!("{main_type}/*")) {
+            1
+        } else if range == "*/*" {
+            0
+        } else {
+            continue;
+        };
+        let quality = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .map_or(1.0, |(_, value)| value.trim().parse().unwrap_or(0.0));
+        if best.is_none_or(|(best, _)| specificity > best) {
+            best = Some((specificity, quality));
+        }
+    }
+    best.map_or(0.0, |(_, quality)| quality)
+}
+
+/// `GET /todos` with content negotiation: `Accept: text/csv` returns the same page as CSV rows
+/// (see `render_csv`), anything else the JSON page.
+async fn get_todos_negotiated(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    headers: HeaderMap,
+    Query(query): Query<TimeFormatQuery>,
+    Query(sort): Query<TodoSortQuery>,
+    Query(page): Query<PageQuery>,
+) -> Result<axum::response::Response, AppError> {
+    // CSV only when it's acceptable and not less preferred than JSON
+    let wants_csv = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| {
+            let csv = accept_quality(accept, "text/csv");
+            csv > 0.0 && csv >= accept_quality(accept, "application/json")
+        });
+    let vary = [(header::VARY, "accept")];
+    if !wants_csv {
+        let response = get_todos(
+            Extension(pool),
+            Extension(config),
+            Query(query),
+            Query(sort),
+            Query(page),
+        )
+        .await?;
+        return Ok((vary, response).into_response());
+    }
+    let filters = list_filters(page.completed, page.filter.as_deref())?;
+    let page = list_todos(
+        &pool,
+        &config.query_limits,
+        sort,
+        &filters,
+        page.limit,
+        page.offset,
+    )
+    .await?;
+    Ok((
+        vary,
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (TOTAL_COUNT_HEADER, page.total.to_string()),
+        ],
+        render_csv(&page.items),
+    )
+        .into_response())
+}
+
+/// Filters of `GET /todos/export.csv`, the same as `GET /todos` without paging.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct ExportQuery {
+    completed: Option<bool>,
+    filter: Option<String>,
+}
+
+/// Every todo matching the `GET /todos` filters and sort as CSV.
+async fn export_todos_csv(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Query(sort): Query<TodoSortQuery>,
+    Query(query): Query<ExportQuery>,
+) -> Result<([(HeaderName, &'static str); 2], String), AppError> {
+    let filters = list_filters(query.completed, query.filter.as_deref())?;
+    let sort = resolve_sort(&pool, &config.query_limits, sort).await?;
+    // A negative LIMIT means no limit in SQLite
+    let todos = run_query(
+        &config.query_limits,
+        "get_todos_page",
+        storage::get_todos_page(&pool, sort, &filters, -1, 0),
+    )
+    .await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"todos.csv\"",
+            ),
+        ],
+        render_csv(&todos),
+    ))
+}
+
+async fn create_todo(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Query(query): Query<CreateQuery>,
+    Json(payload): Json<CreateTodoBody>,
+) -> Result<axum::response::Response, AppError> {
+    let title = normalize_title(&payload.title, &config);
+    validate_todo_fields(&title, payload.description.as_deref(), &config)?;
+    let todo = run_query(
+        &config.query_limits,
+        "create_todo",
+        storage::create_todo(
+            &pool,
+            title,
+            payload.description,
+            payload.completed.unwrap_or(false),
+            payload.due_date,
+        ),
+    )
+    .await;
+
+    match todo {
+        Ok(todo) => {
+            let location = format!("/todos/{}", todo.id.unwrap_or_default());
+            let body = match query.return_mode.unwrap_or(ReturnMode::Full) {
+                ReturnMode::Full => Json(todo).into_response(),
+                ReturnMode::Minimal => Json(serde_json::json!({ "id": todo.id })).into_response(),
+            };
+            Ok((StatusCode::CREATED, [(header::LOCATION, location)], body).into_response())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn update_todo(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(id): Path<i64>,
+    Query(query): Query<UpdateQuery>,
+    Json(payload): Json<UpdateTodoBody>,
+) -> Result<Json<storage::Todo>, AppError> {
+    let title = payload
+        .title
+        .as_deref()
+        .map(|title| normalize_title(title, &config));
+    if let Some(title) = &title {
+        validate_title(title, &config)?;
+    }
+    if let Some(description) = &payload.description {
+        validate_description(description)?;
+    }
+    if payload.completed == Some(true) && !query.confirm {
+        match run_query(
+            &config.query_limits,
+            "get_todo_by_id",
+            storage::get_todo_by_id(&pool, id),
+        )
+        .await
+        {
+            Ok(todo) if todo.important && !todo.completed => {
+                return Err(AppError::Conflict(
+                    "Confirmation required: pass ?confirm=true to complete an important todo item"
+                        .to_string(),
+                ));
+            }
+            Ok(_) | Err(AppError::Database(sqlx::Error::RowNotFound)) => {}
+            Err(e) => {
+                return Err(e);
+            }
+        }
+    }
+    let todo = run_query(
+        &config.query_limits,
+        "update_todo",
+        storage::update_todo(
+            &pool,
+            id,
+            title,
+            payload.description,
+            payload.completed,
+            payload.archived,
+            payload.due_date,
+        ),
+    )
+    .await;
+
+    match todo {
+        Ok(todo) => Ok(Json(todo)),
+        Err(AppError::Database(sqlx::Error::RowNotFound)) => {
+            Err(AppError::NotFound("Todo item not found".to_string()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn delete_todo(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let deleted = run_query(
+        &config.query_limits,
+        "delete_todo",
+        storage::delete_todo(&pool, id),
+    )
+    .await;
+
+    match deleted {
+        Ok(0) => Err(AppError::NotFound("Todo item not found".to_string())),
+        Ok(_) => Ok(StatusCode::OK),
+        Err(e) => Err(e),
+    }
+}
+
+async fn restore_todo(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(id): Path<i64>,
+) -> Result<Json<storage::Todo>, AppError> {
+    let todo = run_query(
+        &config.query_limits,
+        "restore_todo",
+        storage::restore_todo(&pool, id),
+    )
+    .await;
+
+    match todo {
+        Ok(todo) => Ok(Json(todo)),
+        Err(AppError::Database(sqlx::Error::RowNotFound)) => Err(AppError::NotFound(
+            "Deleted todo item not found".to_string(),
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+async fn purge_todo(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let purged = run_query(
+        &config.query_limits,
+        "purge_todo",
+        storage::purge_todo(&pool, id),
+    )
+    .await;
+
+    match purged {
+        Ok(0) => Err(AppError::NotFound("Todo item not found".to_string())),
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(e) => Err(e),
+    }
+}
+
+async fn cleanup_completed_archived(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Query(query): Query<CleanupQuery>,
+) -> Result<Json<u64>, AppError> {
+    if !query.confirm {
+        return Err(AppError::BadRequest(
+            "Pass ?confirm=true to permanently delete completed and archived todos".to_string(),
+        ));
+    }
+    let count = run_query(
+        &config.query_limits,
+        "delete_completed_archived",
+        storage::delete_completed_archived(&pool),
+    )
+    .await?;
+    Ok(Json(count))
+}
+
+async fn get_default_sort(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+) -> Result<Json<Option<storage::TodoSort>>, AppError> {
+    let sort = run_query(
+        &config.query_limits,
+        "get_default_sort",
+        storage::get_default_sort(&pool),
+    )
+    .await?;
+    Ok(Json(sort))
+}
+
+async fn set_default_sort(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Json(sort): Json<storage::TodoSort>,
+) -> Result<Json<storage::TodoSort>, AppError> {
+    let saved = run_query(
+        &config.query_limits,
+        "set_default_sort",
+        storage::set_default_sort(&pool, sort),
+    )
+    .await;
+
+    match saved {
+        Ok(()) => Ok(Json(sort)),
+        Err(e) => Err(e),
+    }
+}
+
+/// A JSON body tagged with a strong `ETag`, or an empty `304 Not Modified` when the
+/// client's `If-None-Match` already names that tag.
+enum Cached {
+    Fresh {
+        etag: String,
+        body: Json<serde_json::Value>,
+    },
+    NotModified {
+        etag: String,
+    },
+}
+
+impl Cached {
+    fn new(body: Json<serde_json::Value>, headers: &HeaderMap) -> Self {
+        let etag = etag_for(&body.0);
+        let matches = headers
+            .get_all(header::IF_NONE_MATCH)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|tag| tag.trim())
+            // Weak comparison, as If-None-Match calls for
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+        if matches {
+            Cached::NotModified { etag }
+        } else {
+            Cached::Fresh { etag, body }
+        }
+    }
+}
+
+impl IntoResponse for Cached {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Cached::Fresh { etag, body } => ([(header::ETAG, etag)], body).into_response(),
+            Cached::NotModified { etag } => {
+                (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response()
+            }
+        }
+    }
+}
+
+/// A quoted hash of the serialized body, so any change to the row, or a different
+/// `time_format`, gives a new tag.
+fn etag_for(value: &serde_json::Value) -> String {
+    use sha2::{Digest, Sha256};
+
+    // SHA-256 rather than `DefaultHasher`, whose output may change between Rust releases
+    let digest = Sha256::digest(value.to_string().as_bytes());
+    let prefix = u64::from_be_bytes(digest[..8].try_into().unwrap());
+    format!("\"{prefix:016x}\"")
+}
+
+async fn get_todo_by_id(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(id): Path<i64>,
+    Query(query): Query<TimeFormatQuery>,
+    headers: HeaderMap,
+) -> Result<Cached, AppError> {
+    let todo = run_query(
+        &config.query_limits,
+        "get_todo_by_id",
+        storage::get_todo_by_id(&pool, id),
+    )
+    .await;
+
+    match todo {
+        Ok(todo) => Ok(Cached::new(format_times(todo, query.time_format), &headers)),
+        Err(AppError::Database(sqlx::Error::RowNotFound)) => {
+            Err(AppError::NotFound("Todo item not found".to_string()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn get_complete_todos(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Query(query): Query<TimeFormatQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let todos = run_query(
+        &config.query_limits,
+        "get_todos_by_completion",
+        storage::get_todos_by_completion(&pool, true),
+    )
+    .await?;
+    Ok(format_times(todos, query.time_format))
+}
+
+async fn search_todos(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Query(search): Query<SearchQuery>,
+    Query(query): Query<TimeFormatQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    if search.q.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "Search term cannot be empty".to_string(),
+        ));
+    }
+    let todos = run_query(
+        &config.query_limits,
+        "search_todos",
+        storage::search_todos(&pool, search.q.trim()),
+    )
+    .await?;
+    Ok(format_times(todos, query.time_format))
+}
+
+async fn get_recent_todos(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Query(recent): Query<RecentQuery>,
+    Query(query): Query<TimeFormatQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let limit = recent.limit.unwrap_or(DEFAULT_RECENT_LIMIT);
+    if limit < 1 {
+        return Err(AppError::BadRequest("limit must be at least 1".to_string()));
+    }
+    let todos = run_query(
+        &config.query_limits,
+        "get_recent_todos",
+        storage::get_recent_todos(&pool, limit.min(MAX_RECENT_LIMIT)),
+    )
+    .await?;
+    Ok(format_times(todos, query.time_format))
+}
+
+async fn get_age_buckets(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+) -> Result<Json<storage::AgeBuckets>, AppError> {
+    let now = chrono::Utc::now().naive_utc();
+    let buckets = run_query(
+        &config.query_limits,
+        "get_age_buckets",
+        storage::get_age_buckets(&pool, now),
+    )
+    .await?;
+    Ok(Json(buckets))
+}
+
+async fn get_duplicate_todos(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+) -> Result<Json<Vec<storage::DuplicateGroup>>, AppError> {
+    let groups = run_query(
+        &config.query_limits,
+        "get_duplicate_todos",
+        storage::get_duplicate_todos(&pool),
+    )
+    .await?;
+    Ok(Json(groups))
+}
+
+async fn get_calendar(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+) -> Result<([(HeaderName, &'static str); 1], String), AppError> {
+    let todos = run_query(
+        &config.query_limits,
+        "get_due_todos",
+        storage::get_due_todos(&pool),
+    )
+    .await?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        render_calendar(&todos, chrono::Utc::now().naive_utc()),
+    ))
+}
+
+async fn get_incomplete_todos(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Query(query): Query<TimeFormatQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let todos = run_query(
+        &config.query_limits,
+        "get_todos_by_completion",
+        storage::get_todos_by_completion(&pool, false),
+    )
+    .await?;
+    Ok(format_times(todos, query.time_format))
+}
+
+async fn get_grouped_todos(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Query(query): Query<GroupedQuery>,
+    Query(format): Query<TimeFormatQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let todos = run_query(
+        &config.query_limits,
+        "get_todos_grouped",
+        storage::get_todos_grouped(&pool, query.incomplete_limit, query.complete_limit),
+    )
+    .await;
+
+    match todos {
+        Ok(todos) => Ok(format_times(todos, format.time_format)),
+        Err(e) => Err(e),
+    }
+}
+
+/// Parses an RFC 3339 timestamp, converted to UTC, or a naive one taken as UTC. The error
+/// names the `field` that failed.
+fn parse_timestamp(value: &str, field: &str) -> Result<chrono::NaiveDateTime, AppError> {
+    storage::rfc3339::parse(value).ok_or_else(|| {
+        AppError::BadRequest(format!(
+            "Invalid {field} time format, expected RFC 3339 such as 2024-01-02T03:04:05Z"
+        ))
+    })
+}
+
+fn parse_time_range(
+    time_range: &TimeRange,
+) -> Result<(chrono::NaiveDateTime, chrono::NaiveDateTime), AppError> {
+    let start_time = parse_timestamp(&time_range.start, "start")?;
+    let end_time = parse_timestamp(&time_range.end, "end")?;
+    Ok((start_time, end_time))
+}
+
+async fn get_todos_by_time_range(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Query(format): Query<TimeFormatQuery>,
+    Json(time_range): Json<TimeRange>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let (start_time, end_time) = parse_time_range(&time_range)?;
+    let todos = run_query(
+        &config.query_limits,
+        "get_todos_by_time_range",
+        storage::get_todos_by_time_range(&pool, start_time, end_time),
+    )
+    .await?;
+    Ok(format_times(todos, format.time_format))
+}
+
+async fn complete_todos_in_range(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Query(query): Query<UpdateQuery>,
+    Json(time_range): Json<TimeRange>,
+) -> Result<Json<u64>, AppError> {
+    let (start_time, end_time) = parse_time_range(&time_range)?;
+    if start_time > end_time {
+        return Err(AppError::BadRequest(
+            "start must not be after end".to_string(),
+        ));
+    }
+    let count = run_query(
+        &config.query_limits,
+        "complete_todos_in_range",
+        storage::complete_todos_in_range(&pool, start_time, end_time, query.confirm),
+    )
+    .await?;
+    Ok(Json(count))
+}
+
+async fn get_todos_due_before(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Query(due): Query<DueBeforeQuery>,
+    Query(query): Query<TimeFormatQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let before = parse_timestamp(&due.before, "before")?;
+    let todos = run_query(
+        &config.query_limits,
+        "get_todos_due_before",
+        storage::get_todos_due_before(&pool, before),
+    )
+    .await?;
+    Ok(format_times(todos, query.time_format))
+}
+
+async fn set_bulk_due_date(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Json(payload): Json<BulkDueBody>,
+) -> Result<Json<u64>, AppError> {
+    let due_date = match (payload.due_date, payload.due_in_days) {
+        (Some(due_date), None) => storage::rfc3339::parse(&due_date)
+            .ok_or_else(|| AppError::BadRequest("Invalid due date format".to_string()))?,
+        (None, Some(days)) => chrono::Utc::now()
+            .naive_utc()
+            .checked_add_signed(chrono::Duration::days(days))
+            .ok_or(AppError::BadRequest(
+                "due_in_days is out of range".to_string(),
+            ))?,
+        _ => {
+            return Err(AppError::BadRequest(
+                "Provide exactly one of due_date or due_in_days".to_string(),
+            ));
+        }
+    };
+    let count = run_query(
+        &config.query_limits,
+        "set_due_date_bulk",
+        storage::set_due_date_bulk(&pool, &payload.ids, due_date),
+    )
+    .await?;
+    Ok(Json(count))
+}
+
+async fn import_markdown(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<u64>, AppError> {
+    let is_markdown = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/markdown"));
+    if !is_markdown {
+        return Err(AppError::UnsupportedMediaType(
+            "Expected Content-Type: text/markdown".to_string(),
+        ));
+    }
+    let items = parse_markdown_checklist(&body)
+        .into_iter()
+        .enumerate()
+        .map(|(index, (title, completed))| {
+            let title = normalize_title(&title, &config);
+            match validate_todo_fields(&title, None, &config) {
+                Ok(()) => Ok((title, completed)),
+                Err(e) => Err(AppError::Validation(format!("Item {}: {e}", index + 1))),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let count = run_query(
+        &config.query_limits,
+        "create_todos_bulk",
+        storage::create_todos_bulk(&pool, &items),
+    )
+    .await?;
+    Ok(Json(count))
+}
+
+async fn bulk_restore_todos(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Json(payload): Json<BulkIdsBody>,
+) -> Result<Json<u64>, AppError> {
+    let count = run_query(
+        &config.query_limits,
+        "restore_todos",
+        storage::restore_todos(&pool, &payload.ids),
+    )
+    .await?;
+    Ok(Json(count))
+}
+
+async fn bulk_complete_todos(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Query(query): Query<UpdateQuery>,
+    Json(payload): Json<BulkCompleteBody>,
+) -> Result<Json<u64>, AppError> {
+    let count = run_query(
+        &config.query_limits,
+        "set_completion_bulk",
+        storage::set_completion_bulk(&pool, &payload.ids, payload.completed, query.confirm),
+    )
+    .await?;
+    Ok(Json(count))
+}
+
+async fn set_reminder_offset(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(id): Path<i64>,
+    Json(payload): Json<ReminderOffsetBody>,
+) -> Result<Json<storage::Todo>, AppError> {
+    if payload.minutes.is_some_and(|minutes| minutes < 0) {
+        return Err(AppError::BadRequest(
+            "Reminder offset cannot be negative".to_string(),
+        ));
+    }
+    let todo = run_query(
+        &config.query_limits,
+        "set_reminder_offset",
+        storage::set_reminder_offset(&pool, id, payload.minutes),
+    )
+    .await;
+
+    match todo {
+        Ok(todo) => Ok(Json(todo)),
+        Err(AppError::Database(sqlx::Error::RowNotFound)) => {
+            Err(AppError::NotFound("Todo item not found".to_string()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn set_todo_parent(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(id): Path<i64>,
+    Json(payload): Json<ParentBody>,
+) -> Result<Json<storage::Todo>, AppError> {
+    if let Some(parent_id) = payload.parent_id {
+        match run_query(
+            &config.query_limits,
+            "get_todo_by_id",
+            storage::get_todo_by_id(&pool, parent_id),
+        )
+        .await
+        {
+            Ok(_) => {}
+            Err(AppError::Database(sqlx::Error::RowNotFound)) => {
+                return Err(AppError::BadRequest(
+                    "Parent todo item not found".to_string(),
+                ));
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        }
+        match run_query(
+            &config.query_limits,
+            "is_ancestor_or_self",
+            storage::is_ancestor_or_self(&pool, id, parent_id),
+        )
+        .await
+        {
+            Ok(false) => {}
+            Ok(true) => {
+                return Err(AppError::BadRequest(
+                    "A todo item cannot be a subtask of itself or its subtasks".to_string(),
+                ));
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        }
+    }
+    let todo = run_query(
+        &config.query_limits,
+        "set_parent",
+        storage::set_parent(&pool, id, payload.parent_id),
+    )
+    .await;
+
+    match todo {
+        Ok(todo) => Ok(Json(todo)),
+        Err(AppError::Database(sqlx::Error::RowNotFound)) => {
+            Err(AppError::NotFound("Todo item not found".to_string()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn set_todo_important(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(id): Path<i64>,
+    Json(payload): Json<ImportantBody>,
+) -> Result<Json<storage::Todo>, AppError> {
+    let todo = run_query(
+        &config.query_limits,
+        "set_important",
+        storage::set_important(&pool, id, payload.important),
+    )
+    .await;
+
+    match todo {
+        Ok(todo) => Ok(Json(todo)),
+        Err(AppError::Database(sqlx::Error::RowNotFound)) => {
+            Err(AppError::NotFound("Todo item not found".to_string()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn set_todo_priority(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(id): Path<i64>,
+    Json(payload): Json<PriorityBody>,
+) -> Result<Json<storage::Todo>, AppError> {
+    let todo = run_query(
+        &config.query_limits,
+        "set_priority",
+        storage::set_priority(&pool, id, payload.priority),
+    )
+    .await;
+
+    match todo {
+        Ok(todo) => Ok(Json(todo)),
+        Err(AppError::Database(sqlx::Error::RowNotFound)) => {
+            Err(AppError::NotFound("Todo item not found".to_string()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Nests `todos` under their parents. Todos whose parent isn't in the list (e.g. because it
+/// was deleted) become roots. Every todo is attached at most once and only below a root, so
+/// a parent cycle can't loop: its members are simply never reached.
+fn build_todo_tree(todos: Vec<storage::Todo>, max_depth: usize) -> Vec<TodoNode> {
+    let ids: HashSet<i64> = todos.iter().filter_map(|todo| todo.id).collect();
+    let mut children: HashMap<i64, Vec<storage::Todo>> = HashMap::new();
+    let mut roots = Vec::new();
+    for todo in todos {
+        match todo.parent_id.filter(|parent_id| ids.contains(parent_id)) {
+            Some(parent_id) => children.entry(parent_id).or_default().push(todo),
+            None => roots.push(todo),
+        }
+    }
+    roots
+        .into_iter()
+        .map(|todo| attach_children(todo, &mut children, 1, max_depth))
+        .collect()
+}
+
+fn attach_children(
+    todo: storage::Todo,
+    children: &mut HashMap<i64, Vec<storage::Todo>>,
+    depth: usize,
+    max_depth: usize,
+) -> TodoNode {
+    let direct = match todo.id {
+        Some(id) if depth < max_depth => children.remove(&id).unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    TodoNode {
+        children: direct
+            .into_iter()
+            .map(|child| attach_children(child, children, depth + 1, max_depth))
+            .collect(),
+        todo,
+    }
+}
+
+async fn get_todo_tree(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+) -> Result<Json<Vec<TodoNode>>, AppError> {
+    let todos = run_query(
+        &config.query_limits,
+        "get_todos_by_parent",
+        storage::get_todos_by_parent(&pool),
+    )
+    .await?;
+    Ok(Json(build_todo_tree(todos, MAX_TREE_DEPTH)))
+}
+
+async fn get_inconsistent_parents(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Query(inconsistent): Query<InconsistentQuery>,
+    Query(query): Query<TimeFormatQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let todos = if inconsistent.fix {
+        run_query(
+            &config.query_limits,
+            "reopen_inconsistent_parents",
+            storage::reopen_inconsistent_parents(&pool),
+        )
+        .await
+    } else {
+        run_query(
+            &config.query_limits,
+            "get_inconsistent_parents",
+            storage::get_inconsistent_parents(&pool),
+        )
+        .await
+    };
+
+    match todos {
+        Ok(todos) => Ok(format_times(todos, query.time_format)),
+        Err(e) => Err(e),
+    }
+}
+
+async fn get_reminders_due(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Query(query): Query<TimeFormatQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let now = chrono::Utc::now().naive_utc();
+    let todos = run_query(
+        &config.query_limits,
+        "get_reminders_due",
+        storage::get_reminders_due(&pool, now, config.reminder_offset_minutes),
+    )
+    .await?;
+    Ok(format_times(todos, query.time_format))
+}
+
+/// Rejects a todo ending up with `count` tags when that is over `max_tags_per_todo`.
+fn check_tag_count(count: usize, config: &AppConfig) -> Result<(), AppError> {
+    let max = config.max_tags_per_todo;
+    if max > 0 && count > max {
+        return Err(AppError::Conflict(format!(
+            "A todo can have at most {max} tags"
+        )));
+    }
+    Ok(())
+}
+
+async fn replace_todo_tags(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(id): Path<i64>,
+    Json(payload): Json<TagsBody>,
+) -> Result<Json<Vec<String>>, AppError> {
+    check_tag_count(storage::normalize_tags(&payload.tags).len(), &config)?;
+    let tags = run_query(
+        &config.query_limits,
+        "set_tags",
+        storage::set_tags(&pool, id, &payload.tags),
+    )
+    .await;
+
+    match tags {
+        Ok(tags) => Ok(Json(tags)),
+        Err(AppError::Database(sqlx::Error::RowNotFound)) => {
+            Err(AppError::NotFound("Todo item not found".to_string()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn add_todo_tag(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(id): Path<i64>,
+    Json(payload): Json<TagBody>,
+) -> Result<Json<Vec<String>>, AppError> {
+    if payload.tag.trim().is_empty() {
+        return Err(AppError::BadRequest("Tag must not be empty".to_string()));
+    }
+    let current = run_query(
+        &config.query_limits,
+        "get_tags_for_todo",
+        storage::get_tags_for_todo(&pool, id),
+    )
+    .await?;
+    if !current.iter().any(|tag| tag == payload.tag.trim()) {
+        check_tag_count(current.len() + 1, &config)?;
+    }
+    let tags = run_query(
+        &config.query_limits,
+        "add_tag",
+        storage::add_tag(&pool, id, &payload.tag),
+    )
+    .await;
+
+    match tags {
+        Ok(tags) => Ok(Json(tags)),
+        Err(AppError::Database(sqlx::Error::RowNotFound)) => {
+            Err(AppError::NotFound("Todo item not found".to_string()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn remove_todo_tag(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path((id, tag)): Path<(i64, String)>,
+) -> Result<Json<Vec<String>>, AppError> {
+    let tags = run_query(
+        &config.query_limits,
+        "remove_tag",
+        storage::remove_tag(&pool, id, &tag),
+    )
+    .await;
+
+    match tags {
+        Ok(tags) => Ok(Json(tags)),
+        Err(AppError::Database(sqlx::Error::RowNotFound)) => {
+            Err(AppError::NotFound("Todo item or tag not found".to_string()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn get_todos_by_tag(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(tag): Path<String>,
+    Query(query): Query<TimeFormatQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let todos = run_query(
+        &config.query_limits,
+        "get_todos_by_tag",
+        storage::get_todos_by_tag(&pool, &tag),
+    )
+    .await?;
+    Ok(format_times(todos, query.time_format))
+}
+
+async fn get_completion_stats(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+) -> Result<Json<storage::CompletionStats>, AppError> {
+    let stats = run_query(
+        &config.query_limits,
+        "get_completion_stats",
+        storage::get_completion_stats(&pool),
+    )
+    .await?;
+    Ok(Json(stats))
+}
+
+async fn get_tag_stats(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+) -> Result<Json<Vec<storage::TagStats>>, AppError> {
+    let stats = run_query(
+        &config.query_limits,
+        "get_tag_stats",
+        storage::get_tag_stats(&pool),
+    )
+    .await?;
+    Ok(Json(stats))
+}
+
+async fn add_todo_attachment(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(id): Path<i64>,
+    Json(payload): Json<AttachmentBody>,
+) -> Result<Json<storage::Attachment>, AppError> {
+    validate_attachment_url(&payload.url).map_err(AppError::Validation)?;
+    if payload.name.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "Attachment name cannot be empty".to_string(),
+        ));
+    }
+    let attachment = run_query(
+        &config.query_limits,
+        "add_attachment",
+        storage::add_attachment(&pool, id, payload.name, payload.url),
+    )
+    .await;
+
+    match attachment {
+        Ok(attachment) => Ok(Json(attachment)),
+        Err(AppError::Database(sqlx::Error::RowNotFound)) => {
+            Err(AppError::NotFound("Todo item not found".to_string()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn get_todo_attachments(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(id): Path<i64>,
+) -> Result<Json<Vec<storage::Attachment>>, AppError> {
+    match run_query(
+        &config.query_limits,
+        "get_todo_by_id",
+        storage::get_todo_by_id(&pool, id),
+    )
+    .await
+    {
+        Ok(_) => {}
+        Err(AppError::Database(sqlx::Error::RowNotFound)) => {
+            return Err(AppError::NotFound("Todo item not found".to_string()));
+        }
+        Err(e) => return Err(e),
+    }
+    let attachments = run_query(
+        &config.query_limits,
+        "get_attachments",
+        storage::get_attachments(&pool, id),
+    )
+    .await?;
+    Ok(Json(attachments))
+}
+
+async fn delete_todo_attachment(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path((id, attachment_id)): Path<(i64, i64)>,
+) -> Result<StatusCode, AppError> {
+    let result = run_query(
+        &config.query_limits,
+        "delete_attachment",
+        storage::delete_attachment(&pool, id, attachment_id),
+    )
+    .await;
+
+    match result {
+        Ok(0) => Err(AppError::NotFound("Attachment not found".to_string())),
+        Ok(_) => Ok(StatusCode::OK),
+        Err(e) => Err(e),
+    }
+}
+
+async fn start_todo_timer(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(id): Path<i64>,
+) -> Result<Json<storage::Worklog>, AppError> {
+    let worklog = run_query(
+        &config.query_limits,
+        "start_timer",
+        storage::start_timer(&pool, id),
+    )
+    .await;
+
+    match worklog {
+        Ok(worklog) => Ok(Json(worklog)),
+        Err(AppError::Database(sqlx::Error::RowNotFound)) => {
+            Err(AppError::NotFound("Todo item not found".to_string()))
+        }
+        Err(AppError::Database(sqlx::Error::Database(e))) if e.is_unique_violation() => Err(
+            AppError::Conflict("A timer is already running for this todo".to_string()),
+        ),
+        Err(e) => Err(e),
+    }
+}
+
+async fn stop_todo_timer(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(id): Path<i64>,
+) -> Result<Json<storage::Worklog>, AppError> {
+    let worklog = run_query(
+        &config.query_limits,
+        "stop_timer",
+        storage::stop_timer(&pool, id),
+    )
+    .await;
+
+    match worklog {
+        Ok(Some(worklog)) => Ok(Json(worklog)),
+        Ok(None) => Err(AppError::Conflict(
+            "No timer is running for this todo".to_string(),
+        )),
+        Err(AppError::Database(sqlx::Error::RowNotFound)) => {
+            Err(AppError::NotFound("Todo item not found".to_string()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn get_todo_time_spent(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Path(id): Path<i64>,
+) -> Result<Json<storage::TimeSpent>, AppError> {
+    let spent = run_query(
+        &config.query_limits,
+        "get_time_spent",
+        storage::get_time_spent(&pool, id),
+    )
+    .await;
+
+    match spent {
+        Ok(spent) => Ok(Json(spent)),
+        Err(AppError::Database(sqlx::Error::RowNotFound)) => {
+            Err(AppError::NotFound("Todo item not found".to_string()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn get_db_stats(
+    Extension(pool): Extension<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    headers: HeaderMap,
+) -> Result<Json<storage::DbStats>, AppError> {
+    check_admin_token(&headers, &config)?;
+    let stats = run_query(
+        &config.query_limits,
+        "get_db_stats",
+        storage::get_db_stats(&pool),
+    )
+    .await?;
+    Ok(Json(stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+
+    fn sample_todo() -> storage::Todo {
+        let time = "2024-01-02T03:04:05"
+            .parse::<chrono::NaiveDateTime>()
+            .unwrap();
+        storage::Todo {
+            id: Some(1),
+            title: "Sample".to_string(),
+            description: None,
+            completed: false,
+            created_at: Some(time),
+            updated_at: Some(time),
+            due_date: None,
+            completed_at: None,
+            archived: false,
+            deleted_at: None,
+            reminder_offset_minutes: None,
+            parent_id: None,
+            important: false,
+            priority: 0,
+            tags: storage::Tags::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_query_is_cut_off_with_503() {
+        let pool = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        let slow = sqlx::query_scalar::<_, i64>(
+            "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n WHERE x < 50000000) \
+             SELECT COUNT(*) FROM n",
+        )
+        .fetch_one(&pool);
+        let start = std::time::Instant::now();
+        let error = with_query_timeout(Duration::from_millis(50), slow)
+            .await
+            .unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(matches!(error, AppError::QueryTimeout(_)));
+        assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(error.to_string(), "Query timed out after 50 ms");
+    }
+
+    /// Collects formatted log output so tests can assert on it.
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_query_is_logged_at_warn() {
+        let pool = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::WARN)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        storage::timed("fast_query", Duration::from_secs(60), storage::ping(&pool))
+            .await
+            .unwrap();
+        storage::timed("slow_query", Duration::from_nanos(1), storage::ping(&pool))
+            .await
+            .unwrap();
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("WARN"));
+        assert!(output.contains("operation=\"slow_query\""));
+        assert!(output.contains("elapsed_ms="));
+        assert!(!output.contains("fast_query"));
+    }
+
+    #[tokio::test]
+    async fn test_health_checks_database() {
+        let pool = Arc::new(
+            storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        let (status, Json(health_status)) = health(Extension(pool.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(health_status.status, "ok");
+
+        pool.close().await;
+        let (status, Json(health_status)) = health(Extension(pool.clone())).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(health_status.status, "degraded");
+    }
+
+    #[tokio::test]
+    async fn test_healthz_and_readyz() {
+        let pool = Arc::new(
+            storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        assert_eq!(healthz().await, StatusCode::OK);
+        let (status, Json(ready)) = readyz(
+            Extension(pool.clone()),
+            Extension(Arc::new(AppConfig::default())),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ready.status, "ready");
+
+        // Forget the newest migration, as if it were still to run
+        sqlx::query(
+            "DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)",
+        )
+            .execute(&*pool)
+            .await
+            .unwrap();
+        let (status, Json(ready)) = readyz(
+            Extension(pool.clone()),
+            Extension(Arc::new(AppConfig::default())),
+        )
+        .await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(ready.reason.unwrap().starts_with("Pending migrations"));
+
+        pool.close().await;
+        let (status, Json(ready)) = readyz(
+            Extension(pool.clone()),
+            Extension(Arc::new(AppConfig::default())),
+        )
+        .await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(ready.status, "not_ready");
+        assert!(ready.reason.unwrap().starts_with("Database unavailable"));
+        // Liveness doesn't depend on the database
+        assert_eq!(healthz().await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_root_content_negotiation() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, "application/json".parse().unwrap());
+        let response = root(headers).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let info: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(info["service"], "todoapp");
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(info["status"], "ok");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, "text/html,*/*".parse().unwrap());
+        for headers in [headers, HeaderMap::new()] {
+            let response = root(headers).await;
+            assert!(
+                response.headers()[header::CONTENT_TYPE]
+                    .to_str()
+                    .unwrap()
+                    .starts_with("text/plain")
+            );
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, "Hello, World!");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight() {
+        let app = |allowed_origin: Option<&str>| {
+            Router::new()
+                .route("/items/{id}", axum::routing::delete(|| async { "deleted" }))
+                .layer(cors_layer(allowed_origin).unwrap())
+        };
+        let preflight = |origin: &str| {
+            axum::http::Request::builder()
+                .method(Method::OPTIONS)
+                .uri("/items/1")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "DELETE")
+                .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "if-none-match")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let app = app(Some("https://app.example.com"));
+        let response = app
+            .clone()
+            .oneshot(preflight("https://app.example.com"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert!(
+            headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+                .to_str()
+                .unwrap()
+                .contains("DELETE")
+        );
+        assert!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS]
+                .to_str()
+                .unwrap()
+                .contains("if-none-match")
+        );
+        let response = app
+            .oneshot(preflight("https://evil.example.com"))
+            .await
+            .unwrap();
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+
+        // Debug builds, which the tests are, allow any origin when none is configured
+        let response = Router::new()
+            .route("/items/{id}", axum::routing::delete(|| async { "deleted" }))
+            .layer(cors_layer(None).unwrap())
+            .oneshot(preflight("http://localhost:5173"))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(cors_layer(Some("not a\norigin")).is_err());
+    }
+
+    #[test]
+    fn test_parse_bind_addr() {
+        assert_eq!(
+            parse_bind_addr(None).unwrap(),
+            "0.0.0.0:3000".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            parse_bind_addr(Some("127.0.0.1:8080")).unwrap().port(),
+            8080
+        );
+        assert_eq!(
+            parse_bind_addr(Some("[::1]:3001")).unwrap(),
+            "[::1]:3001".parse::<SocketAddr>().unwrap()
+        );
+        for invalid in ["3000", "localhost:3000", "0.0.0.0:http", ""] {
+            let error = parse_bind_addr(Some(invalid)).unwrap_err();
+            assert!(error.contains("BIND_ADDR"), "{error}");
+        }
+    }
+
+    #[test]
+    fn test_format_times_default() {
+        let Json(value) = format_times(sample_todo(), TimeFormat::default());
+        assert_eq!(value["created_at"], "2024-01-02T03:04:05Z");
+        assert_eq!(value["updated_at"], "2024-01-02T03:04:05Z");
+        assert!(value["due_date"].is_null());
+    }
+
+    #[test]
+    fn test_format_times_epoch_ms() {
+        let Json(value) = format_times(vec![sample_todo()], TimeFormat::EpochMs);
+        assert_eq!(value[0]["created_at"], 1704164645000i64);
+        assert_eq!(value[0]["updated_at"], 1704164645000i64);
+        assert!(value[0]["due_date"].is_null());
+        assert_eq!(value[0]["title"], "Sample");
+    }
+
+    #[tokio::test]
+    async fn test_get_todos_total_count_header() {
+        let pool = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        for title in ["One", "Two", "Three"] {
+            storage::create_todo(&pool, title.to_string(), None, false, None)
+                .await
+                .unwrap();
+        }
+        let query = TimeFormatQuery {
+            time_format: TimeFormat::default(),
+        };
+        let ([(name, total)], Json(body)) = get_todos(
+            Extension(Arc::new(pool)),
+            Extension(Arc::new(AppConfig::default())),
+            Query(query),
+            Query(TodoSortQuery::default()),
+            Query(PageQuery::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(name, TOTAL_COUNT_HEADER);
+        assert_eq!(total, "3");
+        assert_eq!(body["items"].as_array().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_todos_page_envelope() {
+        let pool = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        for i in 0..7 {
+            storage::create_todo(&pool, format!("Todo {i}"), None, i % 2 == 0, None)
+                .await
+                .unwrap();
+        }
+        let pool = Arc::new(pool);
+        let page = |limit: Option<i64>, offset: Option<i64>, completed: Option<bool>| {
+            get_todos(
+                Extension(pool.clone()),
+                Extension(Arc::new(AppConfig::default())),
+                Query(TimeFormatQuery {
+                    time_format: TimeFormat::default(),
+                }),
+                Query(TodoSortQuery::default()),
+                Query(PageQuery {
+                    limit,
+                    offset,
+                    completed,
+                    filter: None,
+                }),
+            )
+        };
+        let titles = |body: &serde_json::Value| {
+            body["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|todo| todo["title"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let (_, Json(body)) = page(Some(3), Some(3), None).await.unwrap();
+        assert_eq!(titles(&body), ["Todo 3", "Todo 2", "Todo 1"]);
+        assert_eq!(body["total"], 7);
+        assert_eq!(body["limit"], 3);
+        assert_eq!(body["offset"], 3);
+
+        // The total counts the filtered list, not the whole table
+        let ([(_, total)], Json(body)) = page(Some(2), None, Some(true)).await.unwrap();
+        assert_eq!(titles(&body), ["Todo 6", "Todo 4"]);
+        assert_eq!(body["total"], 4);
+        assert_eq!(total, "4");
+        let (_, Json(body)) = page(None, None, Some(false)).await.unwrap();
+        assert_eq!(titles(&body), ["Todo 5", "Todo 3", "Todo 1"]);
+        assert_eq!(body["total"], 3);
+        assert_eq!(body["limit"], DEFAULT_PAGE_LIMIT);
+        assert_eq!(body["offset"], 0);
+
+        let (_, Json(body)) = page(Some(MAX_PAGE_LIMIT), None, None).await.unwrap();
+        assert_eq!(body["limit"], MAX_PAGE_LIMIT);
+        // A negative LIMIT would mean "no limit" to SQLite
+        for (limit, offset) in [
+            (Some(-1), None),
+            (Some(0), None),
+            (Some(MAX_PAGE_LIMIT + 1), None),
+            (None, Some(-1)),
+        ] {
+            let error = page(limit, offset, None).await.unwrap_err();
+            assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_todos_filter_grammar() {
+        let pool = Arc::new(
+            storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        for (title, completed, priority, tags) in [
+            ("Ship release", false, 3, vec!["work"]),
+            ("Write report", false, 1, vec!["work"]),
+            ("Old work task", true, 3, vec!["work"]),
+            ("Buy flowers", false, 2, vec!["home"]),
+        ] {
+            let todo = storage::create_todo(&pool, title.to_string(), None, completed, None)
+                .await
+                .unwrap();
+            let id = todo.id.unwrap();
+            let Json(todo) = set_todo_priority(
+                Extension(pool.clone()),
+                Extension(Arc::new(AppConfig::default())),
+                Path(id),
+                Json(PriorityBody { priority }),
+            )
+            .await
+            .unwrap();
+            assert_eq!(todo.priority, priority);
+            let tags: Vec<String> = tags.into_iter().map(String::from).collect();
+            storage::set_tags(&pool, id, &tags).await.unwrap();
+        }
+        let list = |filter: &str| {
+            get_todos(
+                Extension(pool.clone()),
+                Extension(Arc::new(AppConfig::default())),
+                Query(TimeFormatQuery {
+                    time_format: TimeFormat::default(),
+                }),
+                Query(TodoSortQuery::default()),
+                Query(PageQuery {
+                    filter: Some(filter.to_string()),
+                    ..PageQuery::default()
+                }),
+            )
+        };
+
+        let (_, Json(body)) = list("completed:false,priority:>=2,tag:work").await.unwrap();
+        assert_eq!(body["total"], 1);
+        assert_eq!(body["items"][0]["title"], "Ship release");
+        assert_eq!(body["items"][0]["tags"], serde_json::json!(["work"]));
+        let (_, Json(body)) = list("priority:<3").await.unwrap();
+        assert_eq!(body["total"], 2);
+
+        let error = list("completed:false,priority:lots").await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        assert!(error.to_string().contains("position 25"));
+    }
+
+    #[tokio::test]
+    async fn test_get_todo_tree() {
+        let pool = Arc::new(
+            storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        let mut ids = HashMap::new();
+        for (title, parent) in [
+            ("Move house", None),
+            ("Pack", Some("Move house")),
+            ("Pack books", Some("Pack")),
+            ("Book van", Some("Move house")),
+            ("Read", None),
+        ] {
+            let todo = storage::create_todo(&pool, title.to_string(), None, false, None)
+                .await
+                .unwrap();
+            let id = todo.id.unwrap();
+            if let Some(parent) = parent {
+                storage::set_parent(&pool, id, Some(ids[parent]))
+                    .await
+                    .unwrap();
+            }
+            ids.insert(title, id);
+        }
+
+        let Json(tree) = get_todo_tree(
+            Extension(pool.clone()),
+            Extension(Arc::new(AppConfig::default())),
+        )
+        .await
+        .unwrap();
+        let tree = serde_json::to_value(tree).unwrap();
+        let titles = |nodes: &serde_json::Value| -> Vec<String> {
+            nodes
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|node| node["title"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(titles(&tree), vec!["Move house", "Read"]);
+        assert_eq!(titles(&tree[0]["children"]), vec!["Pack", "Book van"]);
+        assert_eq!(
+            titles(&tree[0]["children"][0]["children"]),
+            vec!["Pack books"]
+        );
+        assert_eq!(tree[0]["children"][1]["children"], serde_json::json!([]));
+        assert_eq!(tree[1]["children"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_build_todo_tree_caps_depth_and_skips_cycles() {
+        let todo = |id: i64, parent_id: Option<i64>| storage::Todo {
+            id: Some(id),
+            parent_id,
+            ..sample_todo()
+        };
+        // 1 <- 2 <- 3, plus 4 and 5 parenting each other
+        let todos = vec![
+            todo(1, None),
+            todo(2, Some(1)),
+            todo(3, Some(2)),
+            todo(4, Some(5)),
+            todo(5, Some(4)),
+        ];
+        let tree = build_todo_tree(todos.clone(), MAX_TREE_DEPTH);
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree[0].children[0].children[0].todo.id, Some(3));
+
+        let tree = build_todo_tree(todos, 2);
+        assert_eq!(tree[0].children[0].todo.id, Some(2));
+        assert!(tree[0].children[0].children.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tags_per_todo_are_capped() {
+        let pool = Arc::new(
+            storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        let config = Arc::new(AppConfig {
+            max_tags_per_todo: 3,
+            ..AppConfig::default()
+        });
+        let todo = storage::create_todo(&pool, "Tag me".to_string(), None, false, None)
+            .await
+            .unwrap();
+        let id = todo.id.unwrap();
+        let add = |tag: &str| {
+            add_todo_tag(
+                Extension(pool.clone()),
+                Extension(config.clone()),
+                Path(id),
+                Json(TagBody {
+                    tag: tag.to_string(),
+                }),
+            )
+        };
+        let replace = |tags: &[&str]| {
+            replace_todo_tags(
+                Extension(pool.clone()),
+                Extension(config.clone()),
+                Path(id),
+                Json(TagsBody {
+                    tags: tags.iter().map(|tag| tag.to_string()).collect(),
+                }),
+            )
+        };
+
+        for tag in ["a", "b", "c"] {
+            let Json(tags) = add(tag).await.unwrap();
+            assert!(tags.contains(&tag.to_string()));
+        }
+        let error = add("d").await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::CONFLICT);
+        assert_eq!(error.to_string(), "A todo can have at most 3 tags");
+        // Re-adding a tag the todo has doesn't grow the set
+        let Json(tags) = add(" c ").await.unwrap();
+        assert_eq!(tags, vec!["a", "b", "c"]);
+
+        // Duplicates and blanks don't count towards the cap
+        let Json(tags) = replace(&["x", "y", "z", "x", " "]).await.unwrap();
+        assert_eq!(tags, vec!["x", "y", "z"]);
+        assert_eq!(
+            replace(&["w", "x", "y", "z"]).await.unwrap_err().status(),
+            StatusCode::CONFLICT
+        );
+        let tags = storage::get_tags_for_todo(&pool, id).await.unwrap();
+        assert_eq!(tags, vec!["x", "y", "z"]);
+    }
+
+    #[tokio::test]
+    async fn test_add_and_remove_todo_tag() {
+        let pool = Arc::new(
+            storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        let todo = storage::create_todo(&pool, "Tag me".to_string(), None, false, None)
+            .await
+            .unwrap();
+        let id = todo.id.unwrap();
+        let add = |tag: &str| {
+            add_todo_tag(
+                Extension(pool.clone()),
+                Extension(Arc::new(AppConfig::default())),
+                Path(id),
+                Json(TagBody {
+                    tag: tag.to_string(),
+                }),
+            )
+        };
+        let Json(tags) = add("work").await.unwrap();
+        assert_eq!(tags, vec!["work"]);
+        let Json(tags) = add("home").await.unwrap();
+        assert_eq!(tags, vec!["home", "work"]);
+        let status = add("  ").await.unwrap_err().status();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let by_tag = |time_format: TimeFormat| {
+            get_todos_by_tag(
+                Extension(pool.clone()),
+                Extension(Arc::new(AppConfig::default())),
+                Path("work".to_string()),
+                Query(TimeFormatQuery { time_format }),
+            )
+        };
+        let Json(todos) = by_tag(TimeFormat::Rfc3339).await.unwrap();
+        assert_eq!(todos.as_array().unwrap().len(), 1);
+        assert_eq!(todos[0]["tags"], serde_json::json!(["home", "work"]));
+        assert!(todos[0]["created_at"].is_string());
+        let Json(todos) = by_tag(TimeFormat::EpochMs).await.unwrap();
+        assert!(todos[0]["created_at"].is_i64());
+
+        let Json(tags) = remove_todo_tag(
+            Extension(pool.clone()),
+            Extension(Arc::new(AppConfig::default())),
+            Path((id, "work".to_string())),
+        )
+        .await
+        .unwrap();
+        assert_eq!(tags, vec!["home"]);
+        let status = remove_todo_tag(
+            Extension(pool.clone()),
+            Extension(Arc::new(AppConfig::default())),
+            Path((id, "work".to_string())),
+        )
+        .await
+        .unwrap_err()
+        .status();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let Json(todos) = by_tag(TimeFormat::Rfc3339).await.unwrap();
+        assert_eq!(todos, serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_default_sort_setting() {
+        let pool = Arc::new(
+            storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        for title in ["Banana", "Apple", "Cherry"] {
+            storage::create_todo(&pool, title.to_string(), None, false, None)
+                .await
+                .unwrap();
+        }
+        let titles = |sort_by: Option<storage::TodoSortBy>, order: Option<storage::SortOrder>| {
+            let pool = pool.clone();
+            async move {
+                let (_, Json(body)) = get_todos(
+                    Extension(pool),
+                    Extension(Arc::new(AppConfig::default())),
+                    Query(TimeFormatQuery {
+                        time_format: TimeFormat::default(),
+                    }),
+                    Query(TodoSortQuery { sort_by, order }),
+                    Query(PageQuery::default()),
+                )
+                .await
+                .unwrap();
+                body["items"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|todo| todo["title"].as_str().unwrap().to_string())
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let Json(unset) = get_default_sort(
+            Extension(pool.clone()),
+            Extension(Arc::new(AppConfig::default())),
+        )
+        .await
+        .unwrap();
+        assert_eq!(unset, None);
+        // Without a saved default the newest todos come first
+        assert_eq!(titles(None, None).await, ["Cherry", "Apple", "Banana"]);
+
+        let sort = storage::TodoSort {
+            sort_by: storage::TodoSortBy::Title,
+            order: storage::SortOrder::Asc,
+        };
+        let Json(echoed) = set_default_sort(
+            Extension(pool.clone()),
+            Extension(Arc::new(AppConfig::default())),
+            Json(sort),
+        )
+        .await
+        .unwrap();
+        assert_eq!(echoed, sort);
+        let Json(saved) = get_default_sort(
+            Extension(pool.clone()),
+            Extension(Arc::new(AppConfig::default())),
+        )
+        .await
+        .unwrap();
+        assert_eq!(saved, Some(sort));
+        assert_eq!(titles(None, None).await, ["Apple", "Banana", "Cherry"]);
+
+        // An explicit sort wins over the default
+        let explicit = titles(
+            Some(storage::TodoSortBy::Title),
+            Some(storage::SortOrder::Desc),
+        );
+        assert_eq!(explicit.await, ["Cherry", "Banana", "Apple"]);
+        let newest_first = titles(None, Some(storage::SortOrder::Desc));
+        assert_eq!(newest_first.await, ["Cherry", "Apple", "Banana"]);
+
+        // Only whitelisted columns are accepted
+        assert!(serde_json::from_str::<storage::TodoSort>(r#"{"sort_by":"id; DROP"}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_todos_database_failure_is_500() {
+        let pool = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        let query = || {
+            Query(TimeFormatQuery {
+                time_format: TimeFormat::default(),
+            })
+        };
+        let pool = Arc::new(pool);
+        let Ok((_, Json(body))) = get_todos(
+            Extension(pool.clone()),
+            Extension(Arc::new(AppConfig::default())),
+            query(),
+            Query(TodoSortQuery::default()),
+            Query(PageQuery::default()),
+        )
+        .await
+        else {
+            panic!("listing an empty database should succeed");
+        };
+        assert_eq!(body["items"], serde_json::json!([]));
+        assert_eq!(body["total"], 0);
+
+        pool.close().await;
+        let failed = get_todos(
+            Extension(pool),
+            Extension(Arc::new(AppConfig::default())),
+            query(),
+            Query(TodoSortQuery::default()),
+            Query(PageQuery::default()),
+        )
+        .await;
+        assert_eq!(
+            failed.unwrap_err().status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn test_todo_timer_cycle() {
+        let pool = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        let id = storage::create_todo(&pool, "Timed".to_string(), None, false, None)
+            .await
+            .unwrap()
+            .id
+            .unwrap();
+        let pool = Arc::new(pool);
+
+        let Json(started) = start_todo_timer(
+            Extension(pool.clone()),
+            Extension(Arc::new(AppConfig::default())),
+            Path(id),
+        )
+        .await
+        .unwrap();
+        assert!(started.ended_at.is_none());
+        let conflict = start_todo_timer(
+            Extension(pool.clone()),
+            Extension(Arc::new(AppConfig::default())),
+            Path(id),
+        )
+        .await;
+        assert_eq!(conflict.unwrap_err().status(), StatusCode::CONFLICT);
+
+        let Json(stopped) = stop_todo_timer(
+            Extension(pool.clone()),
+            Extension(Arc::new(AppConfig::default())),
+            Path(id),
+        )
+        .await
+        .unwrap();
+        assert_eq!(stopped.id, started.id);
+        assert!(stopped.ended_at.is_some());
+        let not_running = stop_todo_timer(
+            Extension(pool.clone()),
+            Extension(Arc::new(AppConfig::default())),
+            Path(id),
+        )
+        .await;
+        assert_eq!(not_running.unwrap_err().status(), StatusCode::CONFLICT);
+
+        let Json(spent) = get_todo_time_spent(
+            Extension(pool.clone()),
+            Extension(Arc::new(AppConfig::default())),
+            Path(id),
+        )
+        .await
+        .unwrap();
+        assert_eq!(spent.todo_id, id);
+        assert!(!spent.running);
+
+        let missing = start_todo_timer(
+            Extension(pool),
+            Extension(Arc::new(AppConfig::default())),
+            Path(-1),
+        )
+        .await;
+        assert_eq!(missing.unwrap_err().status(), StatusCode::NOT_FOUND);
+    }
+
+    const SAMPLE_CHECKLIST: &str = "# Groceries
+- [ ] Buy milk
+- [x] Done thing
+Some notes in between
+* [X]   Call mom
+  - [ ] Nested item
+- [ ]
+- plain bullet
+-[ ] missing space
+";
+
+    #[test]
+    fn test_parse_markdown_checklist() {
+        let items = parse_markdown_checklist(SAMPLE_CHECKLIST);
+        assert_eq!(
+            items,
+            vec![
+                ("Buy milk".to_string(), false),
+                ("Done thing".to_string(), true),
+                ("Call mom".to_string(), true),
+                ("Nested item".to_string(), false),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_markdown() {
+        let pool = Arc::new(
+            storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, "text/markdown".parse().unwrap());
+        let config = Extension(Arc::new(AppConfig::default()));
+        let Json(count) = import_markdown(
+            Extension(pool.clone()),
+            config.clone(),
+            headers.clone(),
+            SAMPLE_CHECKLIST.to_string(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(count, 4);
+
+        let completed = storage::get_todos_by_completion(&pool, true).await.unwrap();
+        assert_eq!(completed.len(), 2);
+        assert!(completed.iter().all(|todo| todo.completed_at.is_some()));
+        let incomplete = storage::get_todos_by_completion(&pool, false)
+            .await
+            .unwrap();
+        assert_eq!(incomplete.len(), 2);
+
+        let rejected = import_markdown(
+            Extension(pool.clone()),
+            config.clone(),
+            HeaderMap::new(),
+            SAMPLE_CHECKLIST.to_string(),
+        )
+        .await;
+        assert_eq!(
+            rejected.unwrap_err().status(),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+
+        // One bad title rejects the whole checklist
+        let long_title = "a".repeat(MAX_TITLE_LENGTH + 1);
+        let error = import_markdown(
+            Extension(pool.clone()),
+            config,
+            headers,
+            format!("- [ ] Fine\n- [ ] {long_title}\n"),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        assert!(
+            error
+                .to_string()
+                .starts_with("Item 2: Title must be at most")
+        );
+        assert_eq!(storage::get_todos(&pool).await.unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_ical_escape_and_fold() {
+        assert_eq!(ical_escape("a,b;c\\d\r\ne"), "a\\,b\\;c\\\\d\\ne");
+        let long = format!("SUMMARY:{}", "é".repeat(60));
+        let folded = ical_fold(&long);
+        assert!(folded.split("\r\n").all(|line| line.len() <= 75));
+        assert_eq!(folded.replace("\r\n ", ""), format!("{long}\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_get_todos_negotiates_csv_and_json() {
+        let pool = Arc::new(
+            storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        for (title, completed) in [("Alpha", false), ("Bravo", true), ("Charlie", false)] {
+            storage::create_todo(&pool, title.to_string(), None, completed, None)
+                .await
+                .unwrap();
+        }
+        let list = |accept: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT, accept.parse().unwrap());
+            get_todos_negotiated(
+                Extension(pool.clone()),
+                Extension(Arc::new(AppConfig::default())),
+                headers,
+                Query(TimeFormatQuery {
+                    time_format: TimeFormat::default(),
+                }),
+                Query(TodoSortQuery {
+                    sort_by: Some(storage::TodoSortBy::Title),
+                    order: Some(storage::SortOrder::Asc),
+                }),
+                Query(PageQuery {
+                    completed: Some(false),
+                    ..PageQuery::default()
+                }),
+            )
+        };
+        let read = |response: axum::response::Response| async move {
+            assert_eq!(response.headers()[header::VARY], "accept");
+            let content_type = response.headers()[header::CONTENT_TYPE].clone();
+            let total = response.headers()[TOTAL_COUNT_HEADER].clone();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (
+                content_type,
+                total,
+                String::from_utf8(bytes.to_vec()).unwrap(),
+            )
+        };
+
+        let (content_type, total, body) = read(list("application/json").await.unwrap()).await;
+        assert_eq!(content_type, "application/json");
+        assert_eq!(total, "2");
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let json_rows: Vec<(i64, String)> = body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|todo| {
+                (
+                    todo["id"].as_i64().unwrap(),
+                    todo["title"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect();
+
+        let (content_type, total, body) = read(list("text/csv").await.unwrap()).await;
+        assert!(content_type.to_str().unwrap().starts_with("text/csv"));
+        assert_eq!(total, "2");
+        let mut lines = body.lines();
+        assert_eq!(lines.next(), Some(CSV_HEADER));
+        let csv_rows: Vec<(i64, String)> = lines
+            .map(|line| {
+                let fields: Vec<&str> = line.split(',').collect();
+                (fields[0].parse().unwrap(), fields[1].to_string())
+            })
+            .collect();
+        assert_eq!(csv_rows, json_rows);
+        assert_eq!(
+            csv_rows,
+            [(1, "Alpha".to_string()), (3, "Charlie".to_string())]
+        );
+
+        for (accept, csv) in [
+            ("text/csv;q=0, application/json", false),
+            ("text/csv; q=0", false),
+            ("text/csv;q=0.2, application/json", false),
+            ("text/csv, */*;q=0.1", true),
+            ("application/json;q=0.5, text/*", true),
+        ] {
+            let (content_type, _, _) = read(list(accept).await.unwrap()).await;
+            assert_eq!(
+                content_type.to_str().unwrap().starts_with("text/csv"),
+                csv,
+                "{accept}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_todos_csv_applies_list_filters() {
+        let pool = Arc::new(
+            storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        for (title, completed, tags) in [
+            ("Pay rent, bills", true, vec!["home"]),
+            ("Say \"hi\"", true, vec!["home", "social"]),
+            ("Still open", false, vec!["home"]),
+            ("Done at work", true, vec!["work"]),
+        ] {
+            let todo = storage::create_todo(&pool, title.to_string(), None, completed, None)
+                .await
+                .unwrap();
+            let tags: Vec<String> = tags.into_iter().map(String::from).collect();
+            storage::set_tags(&pool, todo.id.unwrap(), &tags)
+                .await
+                .unwrap();
+        }
+        let export = |completed: Option<bool>, filter: Option<&str>| {
+            export_todos_csv(
+                Extension(pool.clone()),
+                Extension(Arc::new(AppConfig::default())),
+                Query(TodoSortQuery {
+                    sort_by: Some(storage::TodoSortBy::Title),
+                    order: Some(storage::SortOrder::Asc),
+                }),
+                Query(ExportQuery {
+                    completed,
+                    filter: filter.map(String::from),
+                }),
+            )
+        };
+        let titles = |csv: &str| -> Vec<String> {
+            csv.lines()
+                .skip(1)
+                .map(|line| line.split(',').nth(1).unwrap().to_string())
+                .collect()
+        };
+
+        let ([(_, content_type), (_, disposition)], csv) = export(Some(true), None).await.unwrap();
+        assert!(content_type.starts_with("text/csv"));
+        assert!(disposition.contains("todos.csv"));
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some(CSV_HEADER));
+        assert!(
+            lines
+                .next()
+                .unwrap()
+                .starts_with("4,Done at work,,true,0,work,")
+        );
+        assert!(
+            lines
+                .next()
+                .unwrap()
+                .starts_with("1,\"Pay rent, bills\",,true,0,home,")
+        );
+        assert!(
+            lines
+                .next()
+                .unwrap()
+                .starts_with("2,\"Say \"\"hi\"\"\",,true,0,home;social,")
+        );
+        assert_eq!(lines.next(), None);
+
+        let (_, csv) = export(Some(false), None).await.unwrap();
+        assert_eq!(titles(&csv), ["Still open"]);
+        let (_, csv) = export(Some(true), Some("tag:work")).await.unwrap();
+        assert_eq!(titles(&csv), ["Done at work"]);
+
+        let invalid = export(None, Some("color:red")).await.unwrap_err();
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_get_calendar() {
+        let pool = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        let due = "2026-11-01T09:30:00"
+            .parse::<chrono::NaiveDateTime>()
+            .unwrap();
+        let todo = storage::create_todo(
+            &pool,
+            "Pay rent, bills; etc".to_string(),
+            Some("Line one\nLine two".to_string()),
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+        let done = storage::create_todo(&pool, "Already done".to_string(), None, true, None)
+            .await
+            .unwrap();
+        storage::create_todo(&pool, "No due date".to_string(), None, false, None)
+            .await
+            .unwrap();
+        storage::set_due_date_bulk(&pool, &[todo.id.unwrap(), done.id.unwrap()], due)
+            .await
+            .unwrap();
+
+        let ([(name, content_type)], body) = get_calendar(
+            Extension(Arc::new(pool)),
+            Extension(Arc::new(AppConfig::default())),
+        )
+        .await
+        .unwrap();
+        assert_eq!(name, header::CONTENT_TYPE);
+        assert!(content_type.starts_with("text/calendar"));
+        assert!(body.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+        assert!(body.ends_with("END:VCALENDAR\r\n"));
+        assert!(body.split_terminator('\n').all(|line| line.ends_with('\r')));
+        assert_eq!(body.matches("BEGIN:VEVENT").count(), 1);
+        assert_eq!(body.matches("END:VEVENT").count(), 1);
+        assert!(body.contains(&format!("UID:todo-{}@todoapp\r\n", todo.id.unwrap())));
+        assert!(body.contains("DTSTART:20261101T093000Z\r\n"));
+        assert!(body.contains("SUMMARY:Pay rent\\, bills\\; etc\r\n"));
+        assert!(body.contains("DESCRIPTION:Line one\\nLine two\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_create_todo_return_modes() {
+        let pool = Arc::new(
+            storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        let body = || CreateTodoBody {
+            title: "Created".to_string(),
+            description: Some("Details".to_string()),
+            completed: None,
+            due_date: None,
+        };
+        let read_json = |response: axum::response::Response| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+
+        for return_mode in [None, Some(ReturnMode::Full)] {
+            let response = create_todo(
+                Extension(pool.clone()),
+                Extension(Arc::new(AppConfig::default())),
+                Query(CreateQuery { return_mode }),
+                Json(body()),
+            )
+            .await
+            .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+            let location = response.headers()[header::LOCATION].clone();
+            let todo = read_json(response).await;
+            assert_eq!(todo["title"], "Created");
+            assert_eq!(todo["description"], "Details");
+            assert_eq!(location, format!("/todos/{}", todo["id"].as_i64().unwrap()));
+        }
+
+        let response = create_todo(
+            Extension(pool.clone()),
+            Extension(Arc::new(AppConfig::default())),
+            Query(CreateQuery {
+                return_mode: Some(ReturnMode::Minimal),
+            }),
+            Json(body()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let location = response.headers()[header::LOCATION].clone();
+        let minimal = read_json(response).await;
+        let id = minimal["id"].as_i64().unwrap();
+        assert_eq!(location, format!("/todos/{id}"));
+        assert_eq!(minimal.as_object().unwrap().len(), 1);
+        assert_eq!(
+            storage::get_todo_by_id(&pool, id).await.unwrap().title,
+            "Created"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_recent_todos() {
+        let pool = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        for i in 0..55 {
+            storage::create_todo(
+                &pool,
+                format!("Todo {i}"),
+                Some("Details".to_string()),
+                false,
+                None,
+            )
+            .await
+            .unwrap();
+        }
+        let pool = Arc::new(pool);
+        let recent = |limit: Option<i64>| {
+            get_recent_todos(
+                Extension(pool.clone()),
+                Extension(Arc::new(AppConfig::default())),
+                Query(RecentQuery { limit }),
+                Query(TimeFormatQuery {
+                    time_format: TimeFormat::default(),
+                }),
+            )
+        };
+
+        let Json(body) = recent(Some(3)).await.unwrap();
+        let todos = body.as_array().unwrap();
+        let titles: Vec<_> = todos.iter().map(|todo| todo["title"].clone()).collect();
+        assert_eq!(titles, ["Todo 54", "Todo 53", "Todo 52"]);
+        for todo in todos {
+            let mut keys: Vec<_> = todo.as_object().unwrap().keys().cloned().collect();
+            keys.sort();
+            assert_eq!(keys, ["created_at", "id", "title"]);
+        }
+
+        let Json(body) = recent(None).await.unwrap();
+        assert_eq!(
+            body.as_array().unwrap().len(),
+            DEFAULT_RECENT_LIMIT as usize
+        );
+        let Json(body) = recent(Some(500)).await.unwrap();
+        assert_eq!(body.as_array().unwrap().len(), MAX_RECENT_LIMIT as usize);
+        assert_eq!(
+            recent(Some(0)).await.unwrap_err().status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_complete_range_rejects_inverted_range() {
+        let pool = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        let range = |start: &str, end: &str| {
+            complete_todos_in_range(
+                Extension(Arc::new(pool.clone())),
+                Extension(Arc::new(AppConfig::default())),
+                Query(UpdateQuery::default()),
+                Json(TimeRange {
+                    start: start.to_string(),
+                    end: end.to_string(),
+                }),
+            )
+        };
+
+        let inverted = range("2026-02-01T00:00:00", "2026-01-01T00:00:00").await;
+        assert_eq!(inverted.unwrap_err().status(), StatusCode::BAD_REQUEST);
+        let Json(count) = range("2026-01-01T00:00:00", "2026-02-01T00:00:00")
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_parse_time_range() {
+        let range = |start: &str, end: &str| {
+            parse_time_range(&TimeRange {
+                start: start.to_string(),
+                end: end.to_string(),
+            })
+        };
+        let expected = "2024-01-02T03:04:05"
+            .parse::<chrono::NaiveDateTime>()
+            .unwrap();
+        for start in [
+            "2024-01-02T03:04:05Z",
+            "2024-01-02T05:04:05+02:00",
+            "2024-01-02T03:04:05",
+        ] {
+            let (start, end) = range(start, "2024-01-02T03:04:05.000Z").unwrap();
+            assert_eq!(start, expected);
+            assert_eq!(end, expected);
+        }
+
+        let error = range("2024-01-02", "2024-01-03T00:00:00Z").unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        assert!(
+            error.to_string().starts_with("Invalid start time"),
+            "{error}"
+        );
+        let error = range("2024-01-02T00:00:00Z", "tomorrow").unwrap_err();
+        assert!(error.to_string().starts_with("Invalid end time"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn test_timestamps_are_rfc3339_utc() {
+        let pool = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        let due_date = storage::rfc3339::parse("2026-03-04T12:00:00+02:00");
+        assert_eq!(
+            due_date,
+            "2026-03-04T10:00:00".parse::<chrono::NaiveDateTime>().ok()
+        );
+        let todo = storage::create_todo(&pool, "Stamped".to_string(), None, false, due_date)
+            .await
+            .unwrap();
+        let value = serde_json::to_value(&todo).unwrap();
+        assert_eq!(value["due_date"], "2026-03-04T10:00:00Z");
+        let created_at = value["created_at"].as_str().unwrap().to_string();
+        assert!(created_at.ends_with('Z'), "{created_at}");
+        assert!(value["completed_at"].is_null());
+        let round_trip: storage::Todo = serde_json::from_value(value).unwrap();
+        assert_eq!(round_trip.created_at, todo.created_at);
+
+        let range = |start: &str, end: &str| {
+            get_todos_by_time_range(
+                Extension(Arc::new(pool.clone())),
+                Extension(Arc::new(AppConfig::default())),
+                Query(TimeFormatQuery {
+                    time_format: TimeFormat::default(),
+                }),
+                Json(TimeRange {
+                    start: start.to_string(),
+                    end: end.to_string(),
+                }),
+            )
+        };
+        let now = chrono::Utc::now();
+        let start =
+            (now - chrono::Duration::hours(1)).to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        let end =
+            (now + chrono::Duration::hours(1)).to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        let Json(body) = range(&start, &end).await.unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["created_at"], created_at);
+        let naive_end = (now + chrono::Duration::hours(1))
+            .naive_utc()
+            .format("%Y-%m-%dT%H:%M:%S")
+            .to_string();
+        let Json(body) = range(&start, &naive_end).await.unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(
+            range("yesterday", &end).await.unwrap_err().status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn test_validate_attachment_url() {
+        assert!(validate_attachment_url("https://example.com/spec.pdf").is_ok());
+        assert!(validate_attachment_url("http://example.com").is_ok());
+        assert!(validate_attachment_url("ftp://example.com/file").is_err());
+        assert!(validate_attachment_url("https://").is_err());
+        assert!(validate_attachment_url("https:///path").is_err());
+        assert!(validate_attachment_url("https://example.com/a file").is_err());
+        assert!(validate_attachment_url("example.com").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_set_todo_parent_rejects_cycles() {
+        let pool = Arc::new(
+            storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        let mut ids = Vec::new();
+        for title in ["Root", "Child", "Grandchild"] {
+            let todo = storage::create_todo(&pool, title.to_string(), None, false, None)
+                .await
+                .unwrap();
+            ids.push(todo.id.unwrap());
+        }
+        let set_parent = |id: i64, parent_id: Option<i64>| {
+            set_todo_parent(
+                Extension(pool.clone()),
+                Extension(Arc::new(AppConfig::default())),
+                Path(id),
+                Json(ParentBody { parent_id }),
+            )
+        };
+
+        let Json(child) = set_parent(ids[1], Some(ids[0])).await.unwrap();
+        assert_eq!(child.parent_id, Some(ids[0]));
+        assert!(set_parent(ids[2], Some(ids[1])).await.is_ok());
+
+        for (id, parent_id) in [(ids[0], ids[0]), (ids[0], ids[2]), (ids[1], -1)] {
+            let rejected = set_parent(id, Some(parent_id)).await.unwrap_err();
+            assert_eq!(rejected.status(), StatusCode::BAD_REQUEST);
+        }
+        assert_eq!(
+            set_parent(-1, Some(ids[0])).await.unwrap_err().status(),
+            StatusCode::NOT_FOUND
+        );
+        let Json(root) = set_parent(ids[1], None).await.unwrap();
+        assert_eq!(root.parent_id, None);
+    }
+
+    #[test]
+    fn test_validate_title() {
+        let config = AppConfig {
+            min_title_length: 4,
+            title_blocklist: vec!["darn".to_string(), "top secret".to_string()],
+            ..AppConfig::default()
+        };
+        let rejected = |title: &str| validate_title(title, &config).unwrap_err();
+
+        let error = rejected(" ab ");
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        assert!(error.to_string().contains("at least 4"));
+        let error = rejected("Fix the DARN printer");
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        assert!(error.to_string().contains("darn"));
+        assert!(
+            rejected("Shred the Top-Secret files")
+                .to_string()
+                .contains("top secret")
+        );
+
+        assert!(validate_title("Buy groceries", &config).is_ok());
+        // Whole words only
+        assert!(validate_title("Darned socks", &config).is_ok());
+        assert!(validate_title("Top up the secret stash", &config).is_ok());
+    }
+
+    #[test]
+    fn test_length_limits_count_graphemes() {
+        let config = AppConfig {
+            min_title_length: 2,
+            ..AppConfig::default()
+        };
+        // Family emoji: one grapheme, 7 chars, 25 bytes
+        let family = "👨\u{200d}👩\u{200d}👧\u{200d}👦";
+        assert_eq!(grapheme_count(family), 1);
+        let emoji_title = family.repeat(MAX_TITLE_LENGTH);
+        assert!(validate_title(&emoji_title, &config).is_ok());
+        assert!(validate_title(&format!("{emoji_title}!"), &config).is_err());
+        assert!(validate_title(&family.repeat(2), &config).is_ok());
+        assert!(validate_title(family, &config).is_err());
+
+        let cjk_title = "买菜".repeat(MAX_TITLE_LENGTH / 2) + "了";
+        assert_eq!(grapheme_count(&cjk_title), MAX_TITLE_LENGTH);
+        assert!(validate_title(&cjk_title, &config).is_ok());
+        let error = validate_title(&format!("{cjk_title}吗"), &config).unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        assert!(error.to_string().contains("at most 255"));
+
+        // Combining accents attach to their base letter
+        let accented = "e\u{301}".repeat(MAX_DESCRIPTION_LENGTH);
+        assert!(validate_description(&accented).is_ok());
+        assert!(validate_description(&format!("{accented}🎉")).is_err());
+        assert!(validate_description(&"日本".repeat(MAX_DESCRIPTION_LENGTH / 2)).is_ok());
+    }
+
+    #[test]
+    fn test_normalize_title() {
+        let mut config = AppConfig::default();
+        assert_eq!(
+            normalize_title("  keep   as is ", &config),
+            "  keep   as is "
+        );
+
+        config.normalize_titles = true;
+        assert_eq!(
+            normalize_title("  buy \t milk\n and   eggs ", &config),
+            "buy milk and eggs"
+        );
+        assert_eq!(normalize_title("   ", &config), "");
+
+        config.title_case = true;
+        assert_eq!(
+            normalize_title(" call  mum about éclairs ", &config),
+            "Call Mum About Éclairs"
+        );
+        // Only first letters change
+        assert_eq!(normalize_title("review iOS PR", &config), "Review IOS PR");
+    }
+
+    #[tokio::test]
+    async fn test_create_and_update_normalize_titles() {
+        let pool = Arc::new(
+            storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        let config = Arc::new(AppConfig {
+            normalize_titles: true,
+            title_case: true,
+            ..AppConfig::default()
+        });
+        let response = create_todo(
+            Extension(pool.clone()),
+            Extension(config.clone()),
+            Query(CreateQuery::default()),
+            Json(CreateTodoBody {
+                title: "  water   the plants ".to_string(),
+                description: None,
+                completed: None,
+                due_date: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let created: storage::Todo = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(created.title, "Water The Plants");
+
+        let Json(updated) = update_todo(
+            Extension(pool.clone()),
+            Extension(config.clone()),
+            Path(created.id.unwrap()),
+            Query(UpdateQuery::default()),
+            Json(UpdateTodoBody {
+                title: Some("feed\tthe   cat".to_string()),
+                description: None,
+                completed: None,
+                archived: None,
+                due_date: None,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated.title, "Feed The Cat");
+    }
+
+    #[tokio::test]
+    async fn test_create_and_update_reject_empty_titles() {
+        let pool = Arc::new(
+            storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        let config = Arc::new(AppConfig::default());
+        let create = |title: String| {
+            create_todo(
+                Extension(pool.clone()),
+                Extension(config.clone()),
+                Query(CreateQuery::default()),
+                Json(CreateTodoBody {
+                    title,
+                    description: None,
+                    completed: None,
+                    due_date: None,
+                }),
+            )
+        };
+        let too_long = "a".repeat(MAX_TITLE_LENGTH + 1);
+        for title in ["", "   ", "\t\n ", too_long.as_str()] {
+            let error = create(title.to_string()).await.unwrap_err();
+            assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        }
+        let error = create(" ".to_string()).await.unwrap_err();
+        assert!(error.to_string().contains("empty"));
+        assert_eq!(storage::count_todos(&pool, &[]).await.unwrap(), 0);
+
+        create("Keep me".to_string()).await.unwrap();
+        let id = storage::get_todos(&pool).await.unwrap()[0].id.unwrap();
+        let rename = |title: String| {
+            update_todo(
+                Extension(pool.clone()),
+                Extension(config.clone()),
+                Path(id),
+                Query(UpdateQuery::default()),
+                Json(UpdateTodoBody {
+                    title: Some(title),
+                    description: None,
+                    completed: None,
+                    archived: None,
+                    due_date: None,
+                }),
+            )
+        };
+        for title in ["", "  ", too_long.as_str()] {
+            let error = rename(title.to_string()).await.unwrap_err();
+            assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        }
+        assert_eq!(
+            storage::get_todo_by_id(&pool, id).await.unwrap().title,
+            "Keep me"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_and_update_reject_invalid_titles() {
+        let pool = Arc::new(
+            storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        let config = Arc::new(AppConfig {
+            min_title_length: 3,
+            title_blocklist: vec!["darn".to_string()],
+            ..AppConfig::default()
+        });
+        let create = |title: &str| {
+            create_todo(
+                Extension(pool.clone()),
+                Extension(config.clone()),
+                Query(CreateQuery::default()),
+                Json(CreateTodoBody {
+                    title: title.to_string(),
+                    description: None,
+                    completed: None,
+                    due_date: None,
+                }),
+            )
+        };
+
+        assert_eq!(
+            create("ab").await.unwrap_err().status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            create("darn it").await.unwrap_err().status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert!(create("Clean title").await.is_ok());
+        assert_eq!(storage::count_todos(&pool, &[]).await.unwrap(), 1);
+
+        let id = storage::get_todos(&pool).await.unwrap()[0].id.unwrap();
+        let rename = |title: &str| {
+            update_todo(
+                Extension(pool.clone()),
+                Extension(config.clone()),
+                Path(id),
+                Query(UpdateQuery::default()),
+                Json(UpdateTodoBody {
+                    title: Some(title.to_string()),
+                    description: None,
+                    completed: None,
+                    archived: None,
+                    due_date: None,
+                }),
+            )
+        };
+        assert_eq!(
+            rename("Darn").await.unwrap_err().status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(rename("Renamed").await.unwrap().0.title, "Renamed");
+
+        let missing = update_todo(
+            Extension(pool.clone()),
+            Extension(config.clone()),
+            Path(9999),
+            Query(UpdateQuery::default()),
+            Json(UpdateTodoBody {
+                title: Some("Nobody home".to_string()),
+                description: None,
+                completed: None,
+                archived: None,
+                due_date: None,
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        assert_eq!(missing.to_string(), "Todo item not found");
+    }
+
+    #[tokio::test]
+    async fn test_completing_important_todo_requires_confirmation() {
+        let pool = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        let important = storage::create_todo(&pool, "Pay taxes".to_string(), None, false, None)
+            .await
+            .unwrap()
+            .id
+            .unwrap();
+        let normal = storage::create_todo(&pool, "Water plants".to_string(), None, false, None)
+            .await
+            .unwrap()
+            .id
+            .unwrap();
+        let pool = Arc::new(pool);
+        let Json(todo) = set_todo_important(
+            Extension(pool.clone()),
+            Extension(Arc::new(AppConfig::default())),
+            Path(important),
+            Json(ImportantBody { important: true }),
+        )
+        .await
+        .unwrap();
+        assert!(todo.important);
+
+        let complete = |id: i64, confirm: bool| {
+            update_todo(
+                Extension(pool.clone()),
+                Extension(Arc::new(AppConfig::default())),
+                Path(id),
+                Query(UpdateQuery { confirm }),
+                Json(UpdateTodoBody {
+                    title: None,
+                    description: None,
+                    completed: Some(true),
+                    archived: None,
+                    due_date: None,
+                }),
+            )
+        };
+
+        let error = complete(important, false).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::CONFLICT);
+        assert!(error.to_string().contains("Confirmation required"));
+        assert!(
+            !storage::get_todo_by_id(&pool, important)
+                .await
+                .unwrap()
+                .completed
+        );
+
+        let Json(todo) = complete(important, true).await.unwrap();
+        assert!(todo.completed);
+
+        let Json(todo) = complete(normal, false).await.unwrap();
+        assert!(todo.completed);
+    }
+
+    #[tokio::test]
+    async fn test_complete_range_needs_confirmation_for_important_todos() {
+        let pool = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        let todo = storage::create_todo(&pool, "Pay taxes".to_string(), None, false, None)
+            .await
+            .unwrap();
+        let id = todo.id.unwrap();
+        storage::set_important(&pool, id, true).await.unwrap();
+        let pool = Arc::new(pool);
+        let created_at = storage::rfc3339::format(&todo.created_at.unwrap());
+        let complete_range = |confirm: bool| {
+            complete_todos_in_range(
+                Extension(pool.clone()),
+                Extension(Arc::new(AppConfig::default())),
+                Query(UpdateQuery { confirm }),
+                Json(TimeRange {
+                    start: created_at.clone(),
+                    end: created_at.clone(),
+                }),
+            )
+        };
+
+        let Json(count) = complete_range(false).await.unwrap();
+        assert_eq!(count, 0);
+        assert!(!storage::get_todo_by_id(&pool, id).await.unwrap().completed);
+        let Json(count) = complete_range(true).await.unwrap();
+        assert_eq!(count, 1);
+        assert!(storage::get_todo_by_id(&pool, id).await.unwrap().completed);
+    }
+
+    #[tokio::test]
+    async fn test_due_date_round_trip_and_due_before() {
+        let pool = Arc::new(
+            storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        let config = Arc::new(AppConfig::default());
+        let time = |s: &str| s.parse::<chrono::NaiveDateTime>().unwrap();
+        let create = |title: &str, due_date: Option<chrono::NaiveDateTime>| {
+            create_todo(
+                Extension(pool.clone()),
+                Extension(config.clone()),
+                Query(CreateQuery::default()),
+                Json(CreateTodoBody {
+                    title: title.to_string(),
+                    description: None,
+                    completed: None,
+                    due_date,
+                }),
+            )
+        };
+        assert!(
+            create("Soon", Some(time("2026-10-16T09:00:00")))
+                .await
+                .is_ok()
+        );
+        assert!(
+            create("Later", Some(time("2026-11-01T09:00:00")))
+                .await
+                .is_ok()
+        );
+        assert!(create("Someday", None).await.is_ok());
+
+        let later = storage::get_todos(&pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|todo| todo.title == "Later")
+            .unwrap();
+        assert_eq!(later.due_date, Some(time("2026-11-01T09:00:00")));
+        let Json(moved) = update_todo(
+            Extension(pool.clone()),
+            Extension(config.clone()),
+            Path(later.id.unwrap()),
+            Query(UpdateQuery::default()),
+            Json(UpdateTodoBody {
+                title: None,
+                description: None,
+                completed: None,
+                archived: None,
+                due_date: Some(time("2026-10-10T09:00:00")),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(moved.due_date, Some(time("2026-10-10T09:00:00")));
+
+        let due_before = |before: &str| {
+            get_todos_due_before(
+                Extension(pool.clone()),
+                Extension(Arc::new(AppConfig::default())),
+                Query(DueBeforeQuery {
+                    before: before.to_string(),
+                }),
+                Query(TimeFormatQuery {
+                    time_format: TimeFormat::default(),
+                }),
+            )
+        };
+        let Json(body) = due_before("2026-10-20T00:00:00").await.unwrap();
+        let titles: Vec<_> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|todo| todo["title"].as_str().unwrap().to_string())
+            .collect();
+        // Todos without a due date never match
+        assert_eq!(titles, ["Later", "Soon"]);
+        let Json(body) = due_before("2026-10-16T09:00:00").await.unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(
+            due_before("next week").await.unwrap_err().status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restore_and_purge_todo() {
+        let pool = Arc::new(
+            storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        let id = storage::create_todo(&pool, "Oops".to_string(), None, false, None)
+            .await
+            .unwrap()
+            .id
+            .unwrap();
+        let restore = |id: i64| {
+            restore_todo(
+                Extension(pool.clone()),
+                Extension(Arc::new(AppConfig::default())),
+                Path(id),
+            )
+        };
+        let purge = |id: i64| {
+            purge_todo(
+                Extension(pool.clone()),
+                Extension(Arc::new(AppConfig::default())),
+                Path(id),
+            )
+        };
+
+        // Only deleted todos can be restored
+        assert_eq!(
+            restore(id).await.unwrap_err().status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            delete_todo(
+                Extension(pool.clone()),
+                Extension(Arc::new(AppConfig::default())),
+                Path(id),
+            )
+            .await
+            .unwrap(),
+            StatusCode::OK
+        );
+        assert!(storage::get_todo_by_id(&pool, id).await.is_err());
+        // Deleting again, or deleting an id that never existed, finds nothing
+        for id in [id, 9999] {
+            let error = delete_todo(
+                Extension(pool.clone()),
+                Extension(Arc::new(AppConfig::default())),
+                Path(id),
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(error.status(), StatusCode::NOT_FOUND);
+            assert_eq!(error.to_string(), "Todo item not found");
+        }
+        let Json(restored) = restore(id).await.unwrap();
+        assert_eq!(restored.deleted_at, None);
+        assert_eq!(
+            storage::get_todo_by_id(&pool, id).await.unwrap().title,
+            "Oops"
+        );
+
+        assert_eq!(purge(id).await.unwrap(), StatusCode::NO_CONTENT);
+        let remaining = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM todo WHERE id = ?")
+            .bind(id)
+            .fetch_one(&*pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 0);
+        assert_eq!(purge(id).await.unwrap_err().status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            restore(id).await.unwrap_err().status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn test_check_admin_token() {
+        let config = AppConfig {
+            admin_token: Some("secret".to_string()),
+            ..AppConfig::default()
+        };
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            check_admin_token(&headers, &config).unwrap_err().status(),
+            StatusCode::UNAUTHORIZED
+        );
+        headers.insert(header::AUTHORIZATION, "Bearer wrong".parse().unwrap());
+        assert_eq!(
+            check_admin_token(&headers, &config).unwrap_err().status(),
+            StatusCode::UNAUTHORIZED
+        );
+        headers.insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        assert!(check_admin_token(&headers, &config).is_ok());
+        assert_eq!(
+            check_admin_token(&headers, &AppConfig::default())
+                .unwrap_err()
+                .status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_todo_etag() {
+        let pool = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        let todo = storage::create_todo(&pool, "Cached".to_string(), None, false, None)
+            .await
+            .unwrap();
+        let id = todo.id.unwrap();
+        // Tags stay the same across builds: the first 8 bytes of the body's SHA-256
+        assert_eq!(etag_for(&serde_json::json!({})), "\"44136fa355b3678a\"");
+        let pool = Arc::new(pool);
+        let app = routes()
+            .layer(Extension(pool.clone()))
+            .layer(Extension(Arc::new(AppConfig::default())));
+        let get = |uri: String, if_none_match: Option<&str>| {
+            let mut request = axum::http::Request::builder().uri(uri);
+            if let Some(etag) = if_none_match {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            app.clone()
+                .oneshot(request.body(axum::body::Body::empty()).unwrap())
+        };
+
+        let response = get(format!("/todos/{id}"), None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+
+        let response = get(format!("/todos/{id}"), Some(&etag)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag.as_str());
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(bytes.is_empty());
+        let listed = format!("\"stale\", W/{etag}");
+        let response = get(format!("/todos/{id}"), Some(&listed)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        // Another representation, or a changed row, gets a new tag
+        let response = get(format!("/todos/{id}?time_format=epoch_ms"), Some(&etag))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        storage::update_todo(
+            &pool,
+            id,
+            Some("Changed".to_string()),
+            None,
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        let response = get(format!("/todos/{id}"), Some(&etag)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()[header::ETAG], etag.as_str());
+
+        // Only a missing row is a 404; other database errors aren't reported as one
+        for uri in ["/todos/999", "/todos/999/attachments"] {
+            let response = get(uri.to_string(), None).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+        }
+        pool.close().await;
+        for uri in [format!("/todos/{id}"), format!("/todos/{id}/attachments")] {
+            let response = get(uri.clone(), None).await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "{uri}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pretty_json() {
+        let pool = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        storage::create_todo(&pool, "Pretty".to_string(), None, false, None)
+            .await
+            .unwrap();
+        let app = routes()
+            .layer(axum::middleware::from_fn(pretty_json))
+            .layer(Extension(Arc::new(pool)))
+            .layer(Extension(Arc::new(AppConfig::default())));
+        let body = |uri: &str, header: Option<&str>| {
+            let mut request = axum::http::Request::builder().uri(uri);
+            if let Some(value) = header {
+                request = request.header("x-pretty", value);
+            }
+            let response = app
+                .clone()
+                .oneshot(request.body(axum::body::Body::empty()).unwrap());
+            async move {
+                let bytes = axum::body::to_bytes(response.await.unwrap().into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                String::from_utf8(bytes.to_vec()).unwrap()
+            }
+        };
+
+        let compact = body("/todos", None).await;
+        assert!(!compact.contains('\n'));
+        let pretty = body("/todos?pretty=true", None).await;
+        assert!(pretty.starts_with("{\n  \"items\": [\n    {\n"));
+        assert_eq!(body("/todos", Some("true")).await, pretty);
+        assert_eq!(body("/todos?pretty=false", None).await, compact);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&pretty).unwrap(),
+            serde_json::from_str::<serde_json::Value>(&compact).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_request_body_limit() {
+        let pool = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        let app = routes()
+            .layer(RequestBodyLimitLayer::new(1024))
+            .layer(Extension(Arc::new(pool)))
+            .layer(Extension(Arc::new(AppConfig::default())));
+        let create = |description: String| {
+            let body = serde_json::json!({ "title": "Sized", "description": description });
+            let request = axum::http::Request::builder()
+                .method(Method::POST)
+                .uri("/todos")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(axum::body::Body::from(body.to_string()))
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        let response = create("x".repeat(2048)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let response = create("x".repeat(100)).await.unwrap();
+        assert!(response.status().is_success(), "{}", response.status());
+
+        let request = axum::http::Request::builder()
+            .uri("/todos")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[TOTAL_COUNT_HEADER], "1");
+    }
+
+    #[test]
+    fn test_security_headers_config() {
+        let defaults = security_headers(|_| None);
+        let names: Vec<_> = defaults.iter().map(|(name, _)| name.clone()).collect();
+        assert_eq!(
+            names,
+            [header::X_CONTENT_TYPE_OPTIONS, header::X_FRAME_OPTIONS]
+        );
+
+        let configured = security_headers(|key| match key {
+            "X_FRAME_OPTIONS" => Some(String::new()),
+            "CONTENT_SECURITY_POLICY" => Some("default-src 'none'".to_string()),
+            _ => None,
+        });
+        assert_eq!(
+            configured,
+            [
+                (
+                    header::X_CONTENT_TYPE_OPTIONS,
+                    HeaderValue::from_static("nosniff")
+                ),
+                (
+                    header::CONTENT_SECURITY_POLICY,
+                    HeaderValue::from_static("default-src 'none'")
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_security_headers_on_response() {
+        let headers = security_headers(|key| {
+            (key == "CONTENT_SECURITY_POLICY").then(|| "default-src 'none'".to_string())
+        });
+        let response = routes()
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(headers),
+                add_security_headers,
+            ))
+            .oneshot(
+                axum::http::Request::builder()
+                    .uri("/")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "DENY");
+        assert_eq!(
+            headers[header::CONTENT_SECURITY_POLICY],
+            "default-src 'none'"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_method_returns_405_with_allow() {
+        let response = routes()
+            .oneshot(
+                axum::http::Request::builder()
+                    .method("DELETE")
+                    .uri("/todos")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let allow = response.headers()[header::ALLOW].to_str().unwrap();
+        let mut methods: Vec<&str> = allow.split(',').map(str::trim).collect();
+        methods.sort();
+        assert_eq!(methods, ["GET", "HEAD", "POST"]);
+
+        let response = routes()
+            .oneshot(
+                axum::http::Request::builder()
+                    .uri("/no-such-route")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_limit_concurrency_sheds_excess_requests() {
+        let release = Arc::new(Notify::new());
+        let slow = {
+            let release = release.clone();
+            move || async move {
+                release.notified().await;
+                "done"
+            }
+        };
+        let router = limit_concurrency(Router::new().route("/slow", axum::routing::get(slow)), 1);
+        let request = || {
+            axum::http::Request::builder()
+                .uri("/slow")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let first = tokio::spawn(router.clone().oneshot(request()));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let second = router.clone().oneshot(request()).await.unwrap();
+        assert_eq!(second.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        release.notify_one();
+        let first = first.await.unwrap().unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_wait_with_grace_period_times_out() {
+        let grace_period = Duration::from_millis(50);
+        let start = std::time::Instant::now();
+        let result = wait_with_grace_period(std::future::pending::<()>(), grace_period).await;
+        assert!(result.is_none());
+        assert!(start.elapsed() >= grace_period);
+    }
+
+    #[tokio::test]
+    async fn test_wait_with_grace_period_completes() {
+        let result = wait_with_grace_period(async { 42 }, Duration::from_secs(5)).await;
+        assert_eq!(result, Some(42));
     }
 }
@@ -0,0 +1,130 @@
+use std::fmt;
+
+use crate::error::AppError;
+use crate::storage::{self, NewTodo};
+
+/// Why a seed file couldn't be loaded.
+#[derive(Debug)]
+pub enum SeedError {
+    Read(std::io::Error),
+    Parse(serde_json::Error),
+    /// An entry failed validation; `entry` counts from 1
+    Invalid {
+        entry: usize,
+        error: AppError,
+    },
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for SeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SeedError::Read(e) => write!(f, "cannot read file: {e}"),
+            SeedError::Parse(e) => write!(f, "invalid seed data: {e}"),
+            SeedError::Invalid { entry, error } => {
+                write!(f, "invalid seed data: entry {entry}: {error}")
+            }
+            SeedError::Database(e) => write!(f, "cannot insert seed data: {e}"),
+        }
+    }
+}
+
+/// Loads the todos in the JSON array at `path` when the `todo` table has no rows at all,
+/// returning how many were inserted. A database that already has data is left alone.
+///
+/// Each entry needs a `title` and may set `description`, `completed` and `due_date`. Every
+/// entry is checked with `validate` first, and nothing is inserted if any of them fails.
+pub async fn seed_if_empty(
+    pool: &sqlx::SqlitePool,
+    path: &str,
+    validate: impl Fn(&NewTodo) -> Result<(), AppError>,
+) -> Result<u64, SeedError> {
+    let raw = std::fs::read_to_string(path).map_err(SeedError::Read)?;
+    let todos: Vec<NewTodo> = serde_json::from_str(&raw).map_err(SeedError::Parse)?;
+    for (index, todo) in todos.iter().enumerate() {
+        validate(todo).map_err(|error| SeedError::Invalid {
+            entry: index + 1,
+            error,
+        })?;
+    }
+    storage::create_todos_if_empty(pool, &todos)
+        .await
+        .map_err(SeedError::Database)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept_all(_: &NewTodo) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    fn write_seed_file(name: &str, contents: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("todoapp-seed-{}-{name}.json", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_seed_if_empty() {
+        let pool = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        let path = write_seed_file(
+            "valid",
+            r#"[
+                {"title": "Try the demo"},
+                {"title": "Read the docs", "description": "README", "completed": true,
+                 "due_date": "2026-01-01T09:00:00"}
+            ]"#,
+        );
+
+        assert_eq!(seed_if_empty(&pool, &path, accept_all).await.unwrap(), 2);
+        let todos = storage::get_todos(&pool).await.unwrap();
+        assert_eq!(todos.len(), 2);
+        let docs = todos
+            .iter()
+            .find(|todo| todo.title == "Read the docs")
+            .unwrap();
+        assert!(docs.completed);
+        assert!(docs.completed_at.is_some());
+        assert_eq!(docs.description.as_deref(), Some("README"));
+        assert!(docs.due_date.is_some());
+
+        // The table has data now, so a second run is a no-op
+        assert_eq!(seed_if_empty(&pool, &path, accept_all).await.unwrap(), 0);
+        assert_eq!(storage::get_todos(&pool).await.unwrap().len(), 2);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_seed_if_empty_rejects_bad_files() {
+        let pool = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        let path = write_seed_file("malformed", r#"[{"titel": "Typo"}]"#);
+        let error = seed_if_empty(&pool, &path, accept_all).await.unwrap_err();
+        assert!(matches!(error, SeedError::Parse(_)));
+        assert!(error.to_string().starts_with("invalid seed data"));
+        std::fs::remove_file(path).unwrap();
+
+        let path = write_seed_file("invalid", r#"[{"title": "Fine"}, {"title": " "}]"#);
+        let reject_blank = |todo: &NewTodo| match todo.title.trim() {
+            "" => Err(AppError::Validation("Title cannot be empty".to_string())),
+            _ => Ok(()),
+        };
+        let error = seed_if_empty(&pool, &path, reject_blank).await.unwrap_err();
+        assert!(matches!(error, SeedError::Invalid { entry: 2, .. }));
+        assert_eq!(
+            error.to_string(),
+            "invalid seed data: entry 2: Title cannot be empty"
+        );
+        std::fs::remove_file(path).unwrap();
+
+        let missing = seed_if_empty(&pool, "/nonexistent/seed.json", accept_all).await;
+        assert!(matches!(missing, Err(SeedError::Read(_))));
+        assert!(storage::get_todos(&pool).await.unwrap().is_empty());
+    }
+}
@@ -1,114 +1,1599 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
 use chrono::NaiveDateTime;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, Sqlite, SqlitePool, migrate::MigrateDatabase};
+use sqlx::{
+    FromRow, QueryBuilder, Sqlite, SqlitePool,
+    migrate::MigrateDatabase,
+    sqlite::{SqliteConnectOptions, SqliteConnection, SqlitePoolOptions},
+};
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
 
 pub const DB_URL: &str = "sqlite://todoapp.db";
 
-pub async fn init_db(db_url: &str) -> Result<SqlitePool, sqlx::Error> {
+/// Connection pool tuning applied through `SqlitePoolOptions`.
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub min_connections: u32,
+    pub idle_timeout: Duration,
+    pub max_lifetime: Duration,
+    /// How long a statement may wait for a lock or run before it is abandoned
+    pub query_timeout: Duration,
+    /// Queries slower than this are logged at WARN
+    pub slow_query_threshold: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            min_connections: 1,
+            idle_timeout: Duration::from_secs(600),
+            max_lifetime: Duration::from_secs(1800),
+            query_timeout: Duration::from_millis(DEFAULT_QUERY_TIMEOUT_MS),
+            slow_query_threshold: Duration::from_millis(DEFAULT_SLOW_QUERY_MS),
+        }
+    }
+}
+
+pub async fn init_db(db_url: &str, config: &PoolConfig) -> Result<SqlitePool, sqlx::Error> {
     if !Sqlite::database_exists(db_url).await? {
         Sqlite::create_database(db_url).await?;
     }
-    let pool = SqlitePool::connect(db_url).await?;
+    let options = SqliteConnectOptions::from_str(db_url)?.busy_timeout(config.query_timeout);
+    let pool = SqlitePoolOptions::new()
+        .min_connections(config.min_connections)
+        .idle_timeout(config.idle_timeout)
+        .max_lifetime(config.max_lifetime)
+        .connect_with(options)
+        .await?;
     sqlx::migrate!("./migrations").run(&pool).await?;
+    index_missing_for_search(&pool).await?;
+    warm_up(&pool, config.min_connections).await?;
     Ok(pool)
 }
 
+const DEFAULT_QUERY_TIMEOUT_MS: u64 = 5000;
+
+/// Limits that handlers apply to the queries of one request, taken from the pool's
+/// [`PoolConfig`].
+#[derive(Debug, Clone, Copy)]
+pub struct QueryLimits {
+    /// How long a request waits for a list or search query before giving up on it
+    pub timeout: Duration,
+    /// Queries slower than this are logged at WARN by [`timed`]
+    pub slow_query_threshold: Duration,
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        PoolConfig::default().query_limits()
+    }
+}
+
+impl PoolConfig {
+    pub fn query_limits(&self) -> QueryLimits {
+        QueryLimits {
+            timeout: self.query_timeout,
+            slow_query_threshold: self.slow_query_threshold,
+        }
+    }
+}
+
+const DEFAULT_SLOW_QUERY_MS: u64 = 200;
+
+/// Awaits `query`, logging a warning with the `operation` name and duration when it takes
+/// longer than `threshold`.
+pub async fn timed<T>(operation: &str, threshold: Duration, query: impl Future<Output = T>) -> T {
+    let start = Instant::now();
+    let result = query.await;
+    let elapsed = start.elapsed();
+    if elapsed > threshold {
+        tracing::warn!(
+            operation,
+            elapsed_ms = elapsed.as_millis() as u64,
+            "Slow query: {operation} took {elapsed:?}"
+        );
+    }
+    result
+}
+
+/// Opens `min_connections` connections up front so the first requests don't pay for them.
+async fn warm_up(pool: &SqlitePool, min_connections: u32) -> Result<(), sqlx::Error> {
+    let mut connections = Vec::new();
+    for _ in 0..min_connections {
+        connections.push(pool.acquire().await?);
+    }
+    drop(connections);
+    Ok(())
+}
+
+/// Runs a trivial query to check the database answers.
+pub async fn ping(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT 1").execute(pool).await?;
+    Ok(())
+}
+
+/// Versions of the embedded migrations not yet applied to the database. Has to query the
+/// database, so an error also means it is unreachable.
+pub async fn pending_migrations(pool: &SqlitePool) -> Result<Vec<i64>, sqlx::Error> {
+    let applied: Vec<i64> =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success = 1")
+            .fetch_all(pool)
+            .await?;
+    let pending = sqlx::migrate!("./migrations")
+        .iter()
+        .map(|migration| migration.version)
+        .filter(|version| !applied.contains(version))
+        .collect();
+    Ok(pending)
+}
+
 #[derive(FromRow, Serialize, Deserialize, Debug, Clone)]
 pub struct Todo {
     pub id: Option<i64>,
     pub title: String,
     pub description: Option<String>,
     pub completed: bool,
+    #[serde(with = "rfc3339::option")]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339::option")]
     pub updated_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339::option")]
+    pub due_date: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339::option")]
+    pub completed_at: Option<NaiveDateTime>,
+    pub archived: bool,
+    #[serde(with = "rfc3339::option")]
+    pub deleted_at: Option<NaiveDateTime>,
+    /// Minutes before `due_date` that the reminder fires; `None` uses the configured default
+    pub reminder_offset_minutes: Option<i64>,
+    /// The todo this one is a subtask of
+    pub parent_id: Option<i64>,
+    /// Completing an important todo needs explicit confirmation
+    pub important: bool,
+    /// Higher is more urgent; 0 by default
+    pub priority: i64,
+    #[sqlx(try_from = "String")]
+    pub tags: Tags,
+}
+
+/// Tag names of a todo, sorted. Todo queries read them as a JSON array from the `tags` column
+/// of the `todo_with_tags` view.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct Tags(pub Vec<String>);
+
+impl From<String> for Tags {
+    fn from(json: String) -> Self {
+        Tags(serde_json::from_str(&json).unwrap_or_default())
+    }
+}
+
+/// Columns read through the `todo_with_tags` view aren't known to be non-null.
+impl From<Option<String>> for Tags {
+    fn from(json: Option<String>) -> Self {
+        json.map(Tags::from).unwrap_or_default()
+    }
+}
+
+/// Reads a todo back through `todo_with_tags` after a write, since `RETURNING` can't select
+/// from a view.
+async fn todo_with_tags(conn: &mut SqliteConnection, id: i64) -> Result<Todo, sqlx::Error> {
+    sqlx::query_as!(Todo, "SELECT * FROM todo_with_tags WHERE id = ?", id)
+        .fetch_one(conn)
+        .await
+}
+
+/// Serde helpers that write timestamps as RFC 3339 UTC strings, e.g. `2024-01-02T03:04:05Z`.
+/// Stored times are naive UTC; reading accepts any RFC 3339 offset as well as naive times.
+pub mod rfc3339 {
+    use chrono::{DateTime, NaiveDateTime, SecondsFormat};
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn format(time: &NaiveDateTime) -> String {
+        time.and_utc().to_rfc3339_opts(SecondsFormat::AutoSi, true)
+    }
+
+    pub fn parse(value: &str) -> Option<NaiveDateTime> {
+        DateTime::parse_from_rfc3339(value)
+            .map(|time| time.naive_utc())
+            .or_else(|_| value.parse::<NaiveDateTime>())
+            .ok()
+    }
+
+    pub fn serialize<S: Serializer>(
+        time: &NaiveDateTime,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format(time))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<NaiveDateTime, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        parse(&value).ok_or_else(|| D::Error::custom(format!("invalid timestamp '{value}'")))
+    }
+
+    /// The same for `Option<NaiveDateTime>`, writing `null` for `None`.
+    pub mod option {
+        use chrono::NaiveDateTime;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            time: &Option<NaiveDateTime>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match time {
+                Some(time) => super::serialize(time, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<NaiveDateTime>, D::Error> {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(with = "super")] NaiveDateTime);
+            let time = Option::<Wrapper>::deserialize(deserializer)?;
+            Ok(time.map(|Wrapper(time)| time))
+        }
+    }
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug, Clone)]
+pub struct Attachment {
+    pub id: i64,
+    pub todo_id: i64,
+    pub name: String,
+    pub url: String,
+    #[serde(with = "rfc3339::option")]
+    pub created_at: Option<NaiveDateTime>,
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug, Clone)]
+pub struct Worklog {
+    pub id: i64,
+    pub todo_id: i64,
+    #[serde(with = "rfc3339")]
+    pub started_at: NaiveDateTime,
+    #[serde(with = "rfc3339::option")]
+    pub ended_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimeSpent {
+    pub todo_id: i64,
+    pub total_seconds: i64,
+    pub running: bool,
 }
 
 pub async fn create_todo(
     pool: &SqlitePool,
     title: String,
     description: Option<String>,
+    completed: bool,
+    due_date: Option<NaiveDateTime>,
 ) -> Result<Todo, sqlx::Error> {
     let now = Utc::now();
-    let todo = sqlx::query_as!(
-        Todo,
+    let completed_at = completed.then_some(now);
+    let mut tx = pool.begin().await?;
+    let id = sqlx::query_scalar!(
         r#"
-        INSERT INTO todo (title, description, completed, created_at, updated_at)
-        VALUES (?, ?, ?, ?, ?)
-        RETURNING id, title, description, completed, created_at, updated_at
+        INSERT INTO todo (title, description, completed, created_at, updated_at, completed_at, due_date)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        RETURNING id
         "#,
         title,
         description,
-        false,
+        completed,
         now,
-        now
+        now,
+        completed_at,
+        due_date
     )
-    .fetch_one(pool)
+    .fetch_one(&mut *tx)
     .await?;
+    index_for_search(&mut tx, id, &title, description.as_deref()).await?;
+    let todo = todo_with_tags(&mut tx, id).await?;
+    tx.commit().await?;
     Ok(todo)
 }
 
+#[allow(dead_code)]
 pub async fn get_todos(pool: &SqlitePool) -> Result<Vec<Todo>, sqlx::Error> {
-    let todos = sqlx::query_as!(Todo, "SELECT * FROM todo")
-        .fetch_all(pool)
-        .await?;
+    let todos = sqlx::query_as!(
+        Todo,
+        r#"
+        SELECT * FROM todo_with_tags WHERE deleted_at IS NULL
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(todos)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TodoSortBy {
+    #[default]
+    CreatedAt,
+    UpdatedAt,
+    Title,
+}
+
+impl TodoSortBy {
+    /// The ORDER BY expression. Timestamps are written both as RFC 3339 and, by the
+    /// `updated_at` trigger, as `CURRENT_TIMESTAMP`, so they're compared through `datetime()`.
+    fn order_expr(self) -> &'static str {
+        match self {
+            TodoSortBy::CreatedAt => "datetime(created_at)",
+            TodoSortBy::UpdatedAt => "datetime(updated_at)",
+            TodoSortBy::Title => "title",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl SortOrder {
+    fn keyword(self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct TodoSort {
+    #[serde(default)]
+    pub sort_by: TodoSortBy,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn operator(self) -> &'static str {
+        match self {
+            Comparison::Eq => "=",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        }
+    }
+}
+
+/// One condition a listed todo must meet.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TodoFilter {
+    Completed(bool),
+    Important(bool),
+    Priority(Comparison, i64),
+    /// Has a tag with exactly this name
+    Tag(String),
+}
+
+/// Appends the WHERE clause matching live todos that meet every filter. Values are bound,
+/// only fixed SQL fragments are pushed as text.
+fn push_todo_filters(builder: &mut QueryBuilder<'_, Sqlite>, filters: &[TodoFilter]) {
+    builder.push(" WHERE deleted_at IS NULL");
+    for filter in filters {
+        match filter {
+            TodoFilter::Completed(completed) => {
+                builder.push(" AND completed = ").push_bind(*completed);
+            }
+            TodoFilter::Important(important) => {
+                builder.push(" AND important = ").push_bind(*important);
+            }
+            TodoFilter::Priority(comparison, priority) => {
+                builder
+                    .push(" AND priority ")
+                    .push(comparison.operator())
+                    .push(" ")
+                    .push_bind(*priority);
+            }
+            TodoFilter::Tag(tag) => {
+                builder
+                    .push(
+                        " AND id IN (SELECT todo_tags.todo_id FROM todo_tags \
+                         JOIN tags ON tags.id = todo_tags.tag_id WHERE tags.name = ",
+                    )
+                    .push_bind(tag.clone())
+                    .push(")");
+            }
+        }
+    }
+}
+
+/// One page of the todos matching every filter.
+pub async fn get_todos_page(
+    pool: &SqlitePool,
+    sort: TodoSort,
+    filters: &[TodoFilter],
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<Todo>, sqlx::Error> {
+    let mut builder = QueryBuilder::new("SELECT * FROM todo_with_tags");
+    push_todo_filters(&mut builder, filters);
+    // Only whitelisted column names and keywords are interpolated, never user input
+    builder
+        .push(format!(
+            " ORDER BY {expr} {order}, id {order} LIMIT ",
+            expr = sort.sort_by.order_expr(),
+            order = sort.order.keyword()
+        ))
+        .push_bind(limit)
+        .push(" OFFSET ")
+        .push_bind(offset);
+    let todos = builder.build_query_as::<Todo>().fetch_all(pool).await?;
     Ok(todos)
 }
 
+/// Counts the todos matching every filter.
+pub async fn count_todos(pool: &SqlitePool, filters: &[TodoFilter]) -> Result<i64, sqlx::Error> {
+    let mut builder = QueryBuilder::new("SELECT COUNT(*) FROM todo");
+    push_todo_filters(&mut builder, filters);
+    let count = builder.build_query_scalar::<i64>().fetch_one(pool).await?;
+    Ok(count)
+}
+
 pub async fn update_todo(
     pool: &SqlitePool,
     id: i64,
     title: Option<String>,
     description: Option<String>,
     completed: Option<bool>,
+    archived: Option<bool>,
+    due_date: Option<NaiveDateTime>,
 ) -> Result<Todo, sqlx::Error> {
     let now = Utc::now();
-    let todo = sqlx::query_as!(
-        Todo,
+    let mut tx = pool.begin().await?;
+    let id = sqlx::query_scalar!(
         r#"
         UPDATE todo
         SET title = COALESCE(?, title),
             description = COALESCE(?, description),
             completed = COALESCE(?, completed),
+            completed_at = CASE
+                WHEN ? IS NULL THEN completed_at
+                WHEN ? THEN COALESCE(completed_at, ?)
+                ELSE NULL
+            END,
+            archived = COALESCE(?, archived),
+            due_date = COALESCE(?, due_date),
             updated_at = ?
-        WHERE id = ?
-        RETURNING id, title, description, completed, created_at, updated_at
+        WHERE id = ? AND deleted_at IS NULL
+        RETURNING id
         "#,
         title,
         description,
         completed,
+        completed,
+        completed,
+        now,
+        archived,
+        due_date,
         now,
         id
     )
-    .fetch_one(pool)
+    .fetch_one(&mut *tx)
     .await?;
+    let todo = todo_with_tags(&mut tx, id).await?;
+    if title.is_some() || description.is_some() {
+        index_for_search(&mut tx, id, &todo.title, todo.description.as_deref()).await?;
+    }
+    tx.commit().await?;
     Ok(todo)
 }
 
-pub async fn delete_todo(pool: &SqlitePool, id: i64) -> Result<(), sqlx::Error> {
-    sqlx::query!("DELETE FROM todo WHERE id = ?", id)
-        .execute(pool)
+pub async fn set_due_date_bulk(
+    pool: &SqlitePool,
+    ids: &[i64],
+    due_date: NaiveDateTime,
+) -> Result<u64, sqlx::Error> {
+    if ids.is_empty() {
+        return Ok(0);
+    }
+    let mut tx = pool.begin().await?;
+    let mut updated = 0;
+    for id in ids {
+        let result = sqlx::query!(
+            "UPDATE todo SET due_date = ? WHERE id = ? AND deleted_at IS NULL",
+            due_date,
+            id
+        )
+        .execute(&mut *tx)
         .await?;
-    Ok(())
+        updated += result.rows_affected();
+    }
+    tx.commit().await?;
+    Ok(updated)
 }
 
-pub async fn get_todo_by_id(pool: &SqlitePool, id: i64) -> Result<Todo, sqlx::Error> {
-    let todo = sqlx::query_as!(Todo, "SELECT * FROM todo WHERE id = ?", id)
-        .fetch_one(pool)
+/// Sets the completion state of several todos in one transaction, returning how many
+/// actually changed. Todos already in that state, deleted or unknown are left alone, and
+/// so are important todos being completed unless `include_important` confirms them.
+pub async fn set_completion_bulk(
+    pool: &SqlitePool,
+    ids: &[i64],
+    completed: bool,
+    include_important: bool,
+) -> Result<u64, sqlx::Error> {
+    if ids.is_empty() {
+        return Ok(0);
+    }
+    let now = Utc::now();
+    let completed_at = completed.then_some(now);
+    // Reopening never needs confirming
+    let include_important = include_important || !completed;
+    let mut tx = pool.begin().await?;
+    let mut updated = 0;
+    for id in ids {
+        let result = sqlx::query!(
+            r#"
+            UPDATE todo SET completed = ?, completed_at = ?, updated_at = ?
+            WHERE id = ? AND deleted_at IS NULL AND completed != ? AND (? OR important = 0)
+            "#,
+            completed,
+            completed_at,
+            now,
+            id,
+            completed,
+            include_important
+        )
+        .execute(&mut *tx)
+        .await?;
+        updated += result.rows_affected();
+    }
+    tx.commit().await?;
+    Ok(updated)
+}
+
+/// Marks every incomplete todo created between `start` and `end` (inclusive) as completed,
+/// returning how many changed. A single UPDATE, so the whole range completes atomically.
+/// Important todos are left alone unless `include_important` confirms them.
+pub async fn complete_todos_in_range(
+    pool: &SqlitePool,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    include_important: bool,
+) -> Result<u64, sqlx::Error> {
+    let now = Utc::now();
+    let result = sqlx::query!(
+        r#"
+        UPDATE todo
+        SET completed = 1, completed_at = COALESCE(completed_at, ?), updated_at = ?
+        WHERE completed = 0 AND deleted_at IS NULL
+            AND datetime(created_at) BETWEEN datetime(?) AND datetime(?)
+            AND (? OR important = 0)
+        "#,
+        now,
+        now,
+        start,
+        end,
+        include_important
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Inserts `(title, completed)` pairs in a single transaction, returning how many were created.
+pub async fn create_todos_bulk(
+    pool: &SqlitePool,
+    items: &[(String, bool)],
+) -> Result<u64, sqlx::Error> {
+    let now = Utc::now();
+    let mut tx = pool.begin().await?;
+    let mut created = 0;
+    for (title, completed) in items {
+        let completed_at = completed.then_some(now);
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO todo (title, completed, created_at, updated_at, completed_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+            title,
+            completed,
+            now,
+            now,
+            completed_at
+        )
+        .execute(&mut *tx)
+        .await?;
+        index_for_search(&mut tx, result.last_insert_rowid(), title, None).await?;
+        created += result.rows_affected();
+    }
+    tx.commit().await?;
+    Ok(created)
+}
+
+/// A todo to insert, as read from a seed file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NewTodo {
+    pub title: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub completed: bool,
+    #[serde(default, with = "rfc3339::option")]
+    pub due_date: Option<NaiveDateTime>,
+}
+
+/// Inserts `todos` in one transaction, but only when the `todo` table has no rows at all
+/// (deleted ones included). Returns how many were created.
+pub async fn create_todos_if_empty(
+    pool: &SqlitePool,
+    todos: &[NewTodo],
+) -> Result<u64, sqlx::Error> {
+    let now = Utc::now();
+    let mut tx = pool.begin().await?;
+    let existing = sqlx::query_scalar!("SELECT COUNT(*) FROM todo")
+        .fetch_one(&mut *tx)
+        .await?;
+    if existing > 0 {
+        return Ok(0);
+    }
+    let mut created = 0;
+    for todo in todos {
+        let completed_at = todo.completed.then_some(now);
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO todo (title, description, completed, created_at, updated_at, completed_at,
+                due_date)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+            todo.title,
+            todo.description,
+            todo.completed,
+            now,
+            now,
+            completed_at,
+            todo.due_date
+        )
+        .execute(&mut *tx)
         .await?;
+        let id = result.last_insert_rowid();
+        index_for_search(&mut tx, id, &todo.title, todo.description.as_deref()).await?;
+        created += result.rows_affected();
+    }
+    tx.commit().await?;
+    Ok(created)
+}
+
+/// Sets or clears (with `None`) a todo's reminder offset.
+pub async fn set_reminder_offset(
+    pool: &SqlitePool,
+    id: i64,
+    offset_minutes: Option<i64>,
+) -> Result<Todo, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let id = sqlx::query_scalar!(
+        r#"
+        UPDATE todo SET reminder_offset_minutes = ?
+        WHERE id = ? AND deleted_at IS NULL
+        RETURNING id
+        "#,
+        offset_minutes,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let todo = todo_with_tags(&mut tx, id).await?;
+    tx.commit().await?;
+    Ok(todo)
+}
+
+/// Incomplete todos whose reminder should fire at `now`, i.e. where
+/// `now >= due_date - offset`. Todos without their own offset use `default_offset_minutes`.
+pub async fn get_reminders_due(
+    pool: &SqlitePool,
+    now: NaiveDateTime,
+    default_offset_minutes: i64,
+) -> Result<Vec<Todo>, sqlx::Error> {
+    let todos = sqlx::query_as!(
+        Todo,
+        r#"
+        SELECT * FROM todo_with_tags
+        WHERE completed = 0 AND deleted_at IS NULL AND due_date IS NOT NULL
+            AND datetime(due_date, '-' || COALESCE(reminder_offset_minutes, ?) || ' minutes')
+                <= datetime(?)
+        ORDER BY due_date
+        "#,
+        default_offset_minutes,
+        now
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(todos)
+}
+
+/// Makes `id` a subtask of `parent_id`, or a top-level todo with `None`.
+pub async fn set_parent(
+    pool: &SqlitePool,
+    id: i64,
+    parent_id: Option<i64>,
+) -> Result<Todo, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let id = sqlx::query_scalar!(
+        r#"
+        UPDATE todo SET parent_id = ?
+        WHERE id = ? AND deleted_at IS NULL
+        RETURNING id
+        "#,
+        parent_id,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let todo = todo_with_tags(&mut tx, id).await?;
+    tx.commit().await?;
+    Ok(todo)
+}
+
+pub async fn set_important(
+    pool: &SqlitePool,
+    id: i64,
+    important: bool,
+) -> Result<Todo, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let id = sqlx::query_scalar!(
+        r#"
+        UPDATE todo SET important = ?
+        WHERE id = ? AND deleted_at IS NULL
+        RETURNING id
+        "#,
+        important,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let todo = todo_with_tags(&mut tx, id).await?;
+    tx.commit().await?;
+    Ok(todo)
+}
+
+pub async fn set_priority(pool: &SqlitePool, id: i64, priority: i64) -> Result<Todo, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let id = sqlx::query_scalar!(
+        r#"
+        UPDATE todo SET priority = ?
+        WHERE id = ? AND deleted_at IS NULL
+        RETURNING id
+        "#,
+        priority,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let todo = todo_with_tags(&mut tx, id).await?;
+    tx.commit().await?;
     Ok(todo)
 }
 
-pub async fn get_todos_by_completion(
+/// Every live todo, top-level ones first and subtasks grouped by parent, for assembling
+/// the subtask tree in one pass.
+pub async fn get_todos_by_parent(pool: &SqlitePool) -> Result<Vec<Todo>, sqlx::Error> {
+    let todos = sqlx::query_as!(
+        Todo,
+        r#"
+        SELECT * FROM todo_with_tags WHERE deleted_at IS NULL
+        ORDER BY parent_id, id
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(todos)
+}
+
+/// Whether `ancestor_id` is `id` itself or appears somewhere above it in the parent chain,
+/// i.e. whether making `ancestor_id` a subtask of `id` would close a cycle.
+pub async fn is_ancestor_or_self(
+    pool: &SqlitePool,
+    ancestor_id: i64,
+    id: i64,
+) -> Result<bool, sqlx::Error> {
+    let found = sqlx::query_scalar!(
+        r#"
+        WITH RECURSIVE chain(id) AS (
+            SELECT ?
+            UNION
+            SELECT todo.parent_id FROM todo JOIN chain ON todo.id = chain.id
+            WHERE todo.parent_id IS NOT NULL
+        )
+        SELECT EXISTS(SELECT 1 FROM chain WHERE id = ?) AS "found!: bool"
+        "#,
+        id,
+        ancestor_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(found)
+}
+
+/// Completed todos that still have incomplete subtasks.
+pub async fn get_inconsistent_parents(pool: &SqlitePool) -> Result<Vec<Todo>, sqlx::Error> {
+    let todos = sqlx::query_as!(
+        Todo,
+        r#"
+        SELECT * FROM todo_with_tags AS parent
+        WHERE parent.completed = 1 AND parent.deleted_at IS NULL
+            AND EXISTS (
+                SELECT 1 FROM todo AS child
+                WHERE child.parent_id = parent.id AND child.completed = 0
+                    AND child.deleted_at IS NULL
+            )
+        ORDER BY parent.id
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(todos)
+}
+
+/// Re-opens every todo `get_inconsistent_parents` would report and returns them.
+pub async fn reopen_inconsistent_parents(pool: &SqlitePool) -> Result<Vec<Todo>, sqlx::Error> {
+    let now = Utc::now();
+    let mut tx = pool.begin().await?;
+    let ids = sqlx::query_scalar!(
+        r#"
+        UPDATE todo SET completed = 0, completed_at = NULL, updated_at = ?
+        WHERE completed = 1 AND deleted_at IS NULL
+            AND EXISTS (
+                SELECT 1 FROM todo AS child
+                WHERE child.parent_id = todo.id AND child.completed = 0
+                    AND child.deleted_at IS NULL
+            )
+        RETURNING id
+        "#,
+        now
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let mut todos = Vec::with_capacity(ids.len());
+    for id in ids {
+        todos.push(todo_with_tags(&mut tx, id).await?);
+    }
+    tx.commit().await?;
+    todos.sort_by_key(|todo| todo.id);
+    Ok(todos)
+}
+
+/// Soft-deletes a todo, returning how many rows were deleted: 0 if it doesn't exist or is
+/// already deleted.
+pub async fn delete_todo(pool: &SqlitePool, id: i64) -> Result<u64, sqlx::Error> {
+    let now = Utc::now();
+    let result = sqlx::query!(
+        "UPDATE todo SET deleted_at = ? WHERE id = ? AND deleted_at IS NULL",
+        now,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Undoes a soft delete. Errors with `RowNotFound` unless the todo exists and is deleted.
+pub async fn restore_todo(pool: &SqlitePool, id: i64) -> Result<Todo, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let id = sqlx::query_scalar!(
+        r#"
+        UPDATE todo SET deleted_at = NULL
+        WHERE id = ? AND deleted_at IS NOT NULL
+        RETURNING id
+        "#,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let todo = todo_with_tags(&mut tx, id).await?;
+    tx.commit().await?;
+    Ok(todo)
+}
+
+/// Physically deletes a todo, deleted or not, returning how many rows were removed.
+pub async fn purge_todo(pool: &SqlitePool, id: i64) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!("DELETE FROM todo WHERE id = ?", id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Clears `deleted_at` for the given ids in one transaction. Ids that aren't deleted are
+/// skipped, so the result counts only rows actually restored.
+pub async fn restore_todos(pool: &SqlitePool, ids: &[i64]) -> Result<u64, sqlx::Error> {
+    if ids.is_empty() {
+        return Ok(0);
+    }
+    let mut tx = pool.begin().await?;
+    let mut restored = 0;
+    for id in ids {
+        let result = sqlx::query!(
+            "UPDATE todo SET deleted_at = NULL WHERE id = ? AND deleted_at IS NOT NULL",
+            id
+        )
+        .execute(&mut *tx)
+        .await?;
+        restored += result.rows_affected();
+    }
+    tx.commit().await?;
+    Ok(restored)
+}
+
+/// Permanently deletes todos that are both completed and archived.
+pub async fn delete_completed_archived(pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!("DELETE FROM todo WHERE completed = 1 AND archived = 1")
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn get_todo_by_id(pool: &SqlitePool, id: i64) -> Result<Todo, sqlx::Error> {
+    let todo = sqlx::query_as!(
+        Todo,
+        r#"
+        SELECT * FROM todo_with_tags WHERE id = ? AND deleted_at IS NULL
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(todo)
+}
+
+pub async fn get_todos_by_completion(
+    pool: &SqlitePool,
+    completed: bool,
+) -> Result<Vec<Todo>, sqlx::Error> {
+    // With this plan sqlx can't infer the type of the view's `tags` column, so it's named
+    let todos = sqlx::query_as!(
+        Todo,
+        r#"
+        SELECT id, title, description, completed, created_at, updated_at, due_date, completed_at,
+            archived, deleted_at, reminder_offset_minutes, parent_id, important, priority,
+            tags AS "tags!: String"
+        FROM todo_with_tags WHERE completed = ? AND deleted_at IS NULL
+        "#,
+        completed
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(todos)
+}
+
+/// Incomplete todos that have a due date, soonest first.
+pub async fn get_due_todos(pool: &SqlitePool) -> Result<Vec<Todo>, sqlx::Error> {
+    let todos = sqlx::query_as!(
+        Todo,
+        r#"
+        SELECT * FROM todo_with_tags WHERE completed = 0 AND due_date IS NOT NULL AND deleted_at IS NULL
+        ORDER BY due_date
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(todos)
+}
+
+/// Todos with a due date strictly before `before`, soonest first. Todos without a due date
+/// are never included.
+pub async fn get_todos_due_before(
+    pool: &SqlitePool,
+    before: NaiveDateTime,
+) -> Result<Vec<Todo>, sqlx::Error> {
+    let todos = sqlx::query_as!(
+        Todo,
+        r#"
+        SELECT * FROM todo_with_tags
+        WHERE due_date IS NOT NULL AND datetime(due_date) < datetime(?) AND deleted_at IS NULL
+        ORDER BY due_date
+        "#,
+        before
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(todos)
+}
+
+/// Folds text for search: NFD-decomposes, strips combining marks and lowercases,
+/// so "Café" and "cafe" compare equal.
+pub fn normalize_search(text: &str) -> String {
+    text.nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Writes the folded title and description of a todo to `todo_search`. Called in the same
+/// transaction as every insert and title or description edit, so searching is a pure read.
+async fn index_for_search(
+    conn: &mut SqliteConnection,
+    id: i64,
+    title: &str,
+    description: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let content = normalize_search(&format!("{title}\n{}", description.unwrap_or_default()));
+    sqlx::query!(
+        "INSERT OR REPLACE INTO todo_search (todo_id, content) VALUES (?, ?)",
+        id,
+        content
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Indexes todos that have no `todo_search` row, e.g. ones written before the index was
+/// kept up to date on write. Runs once at startup.
+async fn index_missing_for_search(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let stale = sqlx::query!(
+        r#"
+        SELECT id AS "id!", title, description FROM todo
+        WHERE id NOT IN (SELECT todo_id FROM todo_search)
+        "#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    for todo in stale {
+        index_for_search(&mut tx, todo.id, &todo.title, todo.description.as_deref()).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Case- and accent-insensitive substring search over titles and descriptions, newest first.
+pub async fn search_todos(pool: &SqlitePool, term: &str) -> Result<Vec<Todo>, sqlx::Error> {
+    let pattern = format!(
+        "%{}%",
+        normalize_search(term)
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    );
+    let todos = sqlx::query_as!(
+        Todo,
+        r#"
+        SELECT todo.* FROM todo_with_tags AS todo
+        JOIN todo_search ON todo_search.todo_id = todo.id
+        WHERE todo_search.content LIKE ? ESCAPE '\' AND todo.deleted_at IS NULL
+        ORDER BY todo.created_at DESC, todo.id DESC
+        "#,
+        pattern
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(todos)
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug, Clone)]
+pub struct RecentTodo {
+    pub id: i64,
+    pub title: String,
+    #[serde(with = "rfc3339::option")]
+    pub created_at: Option<NaiveDateTime>,
+}
+
+/// The newest todos, selecting only the columns a compact listing needs.
+pub async fn get_recent_todos(
+    pool: &SqlitePool,
+    limit: i64,
+) -> Result<Vec<RecentTodo>, sqlx::Error> {
+    let todos = sqlx::query_as!(
+        RecentTodo,
+        r#"
+        SELECT id AS "id!", title, created_at FROM todo
+        WHERE deleted_at IS NULL
+        ORDER BY created_at DESC, id DESC
+        LIMIT ?
+        "#,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(todos)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AgeBuckets {
+    pub under_1_day: i64,
+    pub from_1_to_7_days: i64,
+    pub from_7_to_30_days: i64,
+    pub over_30_days: i64,
+}
+
+/// Counts incomplete todos by how long ago they were created relative to `now`.
+/// Each bucket's lower bound is inclusive, so a todo exactly 7 days old lands in 7-30.
+pub async fn get_age_buckets(
+    pool: &SqlitePool,
+    now: NaiveDateTime,
+) -> Result<AgeBuckets, sqlx::Error> {
+    let one_day_ago = now - chrono::Duration::days(1);
+    let seven_days_ago = now - chrono::Duration::days(7);
+    let thirty_days_ago = now - chrono::Duration::days(30);
+    let buckets = sqlx::query_as!(
+        AgeBuckets,
+        r#"
+        SELECT
+            COALESCE(SUM(datetime(created_at) > datetime(?)), 0) AS "under_1_day!: i64",
+            COALESCE(SUM(datetime(created_at) <= datetime(?)
+                AND datetime(created_at) > datetime(?)), 0) AS "from_1_to_7_days!: i64",
+            COALESCE(SUM(datetime(created_at) <= datetime(?)
+                AND datetime(created_at) > datetime(?)), 0) AS "from_7_to_30_days!: i64",
+            COALESCE(SUM(datetime(created_at) <= datetime(?)), 0) AS "over_30_days!: i64"
+        FROM todo
+        WHERE completed = 0 AND deleted_at IS NULL AND created_at IS NOT NULL
+        "#,
+        one_day_ago,
+        one_day_ago,
+        seven_days_ago,
+        seven_days_ago,
+        thirty_days_ago,
+        thirty_days_ago
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(buckets)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DuplicateGroup {
+    pub title: String,
+    pub ids: Vec<i64>,
+    pub count: usize,
+}
+
+/// Groups non-deleted todos by trimmed, case-insensitive title and returns the groups
+/// with more than one member, ordered by title.
+pub async fn get_duplicate_todos(pool: &SqlitePool) -> Result<Vec<DuplicateGroup>, sqlx::Error> {
+    let rows =
+        sqlx::query!(r#"SELECT id AS "id!", title FROM todo WHERE deleted_at IS NULL ORDER BY id"#)
+            .fetch_all(pool)
+            .await?;
+    let mut groups: BTreeMap<String, Vec<i64>> = BTreeMap::new();
+    for row in rows {
+        groups
+            .entry(row.title.trim().to_lowercase())
+            .or_default()
+            .push(row.id);
+    }
+    Ok(groups
+        .into_iter()
+        .filter(|(_, ids)| ids.len() > 1)
+        .map(|(title, ids)| DuplicateGroup {
+            title,
+            count: ids.len(),
+            ids,
+        })
+        .collect())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupedTodos {
+    pub incomplete: Vec<Todo>,
+    pub complete: Vec<Todo>,
+}
+
+pub async fn get_todos_grouped(
+    pool: &SqlitePool,
+    incomplete_limit: Option<i64>,
+    complete_limit: Option<i64>,
+) -> Result<GroupedTodos, sqlx::Error> {
+    // SQLite treats a negative LIMIT as "no limit"
+    let incomplete_limit = incomplete_limit.unwrap_or(-1);
+    let complete_limit = complete_limit.unwrap_or(-1);
+    let incomplete = sqlx::query_as!(
+        Todo,
+        r#"
+        SELECT * FROM todo_with_tags WHERE completed = 0 AND deleted_at IS NULL ORDER BY id LIMIT ?
+        "#,
+        incomplete_limit
+    )
+    .fetch_all(pool)
+    .await?;
+    let complete = sqlx::query_as!(
+        Todo,
+        r#"
+        SELECT * FROM todo_with_tags WHERE completed = 1 AND deleted_at IS NULL ORDER BY id LIMIT ?
+        "#,
+        complete_limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(GroupedTodos {
+        incomplete,
+        complete,
+    })
+}
+
+pub async fn get_tags_for_todo(
+    pool: &SqlitePool,
+    todo_id: i64,
+) -> Result<Vec<String>, sqlx::Error> {
+    let tags = sqlx::query_scalar!(
+        r#"
+        SELECT tags.name FROM tags
+        JOIN todo_tags ON todo_tags.tag_id = tags.id
+        WHERE todo_tags.todo_id = ?
+        ORDER BY tags.name
+        "#,
+        todo_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(tags)
+}
+
+/// The distinct, trimmed, non-empty tag names in `tags`, sorted, as `set_tags` stores them.
+pub fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut tags: Vec<String> = tags
+        .iter()
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+/// Replaces the full tag set of a todo, creating tags that don't exist yet.
+pub async fn set_tags(
+    pool: &SqlitePool,
+    todo_id: i64,
+    tags: &[String],
+) -> Result<Vec<String>, sqlx::Error> {
+    let wanted = normalize_tags(tags);
+
+    let mut tx = pool.begin().await?;
+    // Errors with RowNotFound when the todo doesn't exist
+    sqlx::query!(
+        "SELECT id FROM todo WHERE id = ? AND deleted_at IS NULL",
+        todo_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let current = sqlx::query!(
+        r#"
+        SELECT tags.id AS "id!", tags.name FROM tags
+        JOIN todo_tags ON todo_tags.tag_id = tags.id
+        WHERE todo_tags.todo_id = ?
+        "#,
+        todo_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    for tag in current.iter().filter(|tag| !wanted.contains(&tag.name)) {
+        sqlx::query!(
+            "DELETE FROM todo_tags WHERE todo_id = ? AND tag_id = ?",
+            todo_id,
+            tag.id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    for name in &wanted {
+        sqlx::query!("INSERT OR IGNORE INTO tags (name) VALUES (?)", name)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!(
+            r#"
+            INSERT OR IGNORE INTO todo_tags (todo_id, tag_id)
+            SELECT ?, id FROM tags WHERE name = ?
+            "#,
+            todo_id,
+            name
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    get_tags_for_todo(pool, todo_id).await
+}
+
+/// Attaches one tag to a todo, creating the tag if needed, and returns the todo's tags.
+/// Adding a tag the todo already has is a no-op.
+pub async fn add_tag(
+    pool: &SqlitePool,
+    todo_id: i64,
+    tag: &str,
+) -> Result<Vec<String>, sqlx::Error> {
+    let tag = tag.trim();
+    let mut tx = pool.begin().await?;
+    // Errors with RowNotFound when the todo doesn't exist
+    sqlx::query!(
+        "SELECT id FROM todo WHERE id = ? AND deleted_at IS NULL",
+        todo_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query!("INSERT OR IGNORE INTO tags (name) VALUES (?)", tag)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+        r#"
+        INSERT OR IGNORE INTO todo_tags (todo_id, tag_id)
+        SELECT ?, id FROM tags WHERE name = ?
+        "#,
+        todo_id,
+        tag
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    get_tags_for_todo(pool, todo_id).await
+}
+
+/// Detaches one tag from a todo and returns the remaining tags. Errors with `RowNotFound`
+/// when the todo doesn't exist or doesn't have the tag.
+pub async fn remove_tag(
+    pool: &SqlitePool,
+    todo_id: i64,
+    tag: &str,
+) -> Result<Vec<String>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM todo_tags
+        WHERE todo_id = (SELECT id FROM todo WHERE id = ? AND deleted_at IS NULL)
+            AND tag_id = (SELECT id FROM tags WHERE name = ?)
+        "#,
+        todo_id,
+        tag
+    )
+    .execute(pool)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound);
+    }
+    get_tags_for_todo(pool, todo_id).await
+}
+
+/// Live todos that have a tag with exactly this name, oldest first.
+pub async fn get_todos_by_tag(pool: &SqlitePool, tag: &str) -> Result<Vec<Todo>, sqlx::Error> {
+    // With this plan sqlx can't infer the type of the view's `tags` column, so it's named
+    let todos = sqlx::query_as!(
+        Todo,
+        r#"
+        SELECT id, title, description, completed, created_at, updated_at, due_date, completed_at,
+            archived, deleted_at, reminder_offset_minutes, parent_id, important, priority,
+            tags AS "tags!: String"
+        FROM todo_with_tags
+        WHERE id IN (
+                SELECT todo_tags.todo_id FROM todo_tags
+                JOIN tags ON tags.id = todo_tags.tag_id
+                WHERE tags.name = ?
+            )
+            AND deleted_at IS NULL
+        ORDER BY id
+        "#,
+        tag
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(todos)
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TagStats {
+    pub tag: String,
+    pub total: i64,
+    pub completed: i64,
+    /// Share of the tag's todos that are completed, from 0.0 to 1.0
+    pub rate: f64,
+}
+
+/// Completion counts per tag, sorted by tag name. Tags without any todos are omitted.
+pub async fn get_tag_stats(pool: &SqlitePool) -> Result<Vec<TagStats>, sqlx::Error> {
+    let stats = sqlx::query_as!(
+        TagStats,
+        r#"
+        SELECT tags.name AS tag,
+            COUNT(*) AS "total!: i64",
+            SUM(todo.completed) AS "completed!: i64",
+            CAST(SUM(todo.completed) AS REAL) / COUNT(*) AS "rate!: f64"
+        FROM tags
+        JOIN todo_tags ON todo_tags.tag_id = tags.id
+        JOIN todo ON todo.id = todo_tags.todo_id
+        WHERE todo.deleted_at IS NULL
+        GROUP BY tags.id
+        ORDER BY tags.name
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(stats)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CompletionStats {
+    pub completed: i64,
+    pub incomplete: i64,
+    pub total: i64,
+}
+
+/// How many todos are completed and how many aren't, deleted ones excluded. A status
+/// without todos has no row in the grouped query and is reported as 0.
+pub async fn get_completion_stats(pool: &SqlitePool) -> Result<CompletionStats, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT completed, COUNT(*) AS "count!: i64" FROM todo
+        WHERE deleted_at IS NULL
+        GROUP BY completed
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut stats = CompletionStats::default();
+    for row in rows {
+        if row.completed {
+            stats.completed += row.count;
+        } else {
+            stats.incomplete += row.count;
+        }
+        stats.total += row.count;
+    }
+    Ok(stats)
+}
+
+pub async fn get_setting(pool: &SqlitePool, key: &str) -> Result<Option<String>, sqlx::Error> {
+    let value = sqlx::query_scalar!("SELECT value FROM app_setting WHERE key = ?", key)
+        .fetch_optional(pool)
+        .await?;
+    Ok(value)
+}
+
+pub async fn set_setting(pool: &SqlitePool, key: &str, value: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO app_setting (key, value) VALUES (?, ?)
+        ON CONFLICT (key) DO UPDATE SET value = excluded.value
+        "#,
+        key,
+        value
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+const DEFAULT_SORT_SETTING: &str = "default_sort";
+
+/// The persisted default sort for todo listings. A stored value that no longer parses is
+/// treated as unset.
+pub async fn get_default_sort(pool: &SqlitePool) -> Result<Option<TodoSort>, sqlx::Error> {
+    let value = get_setting(pool, DEFAULT_SORT_SETTING).await?;
+    Ok(value.and_then(|value| serde_json::from_str(&value).ok()))
+}
+
+pub async fn set_default_sort(pool: &SqlitePool, sort: TodoSort) -> Result<(), sqlx::Error> {
+    let value = serde_json::to_string(&sort).expect("TodoSort serializes to JSON");
+    set_setting(pool, DEFAULT_SORT_SETTING, &value).await
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DbStats {
+    pub tables: BTreeMap<String, i64>,
+    pub size_bytes: i64,
+}
+
+pub async fn get_db_stats(pool: &SqlitePool) -> Result<DbStats, sqlx::Error> {
+    let names = sqlx::query_scalar!(
+        r#"
+        SELECT name AS "name!" FROM sqlite_master
+        WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name NOT LIKE '_sqlx_%'
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut tables = BTreeMap::new();
+    for name in names {
+        // Table names come from sqlite_master, not user input
+        let count: i64 = sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM \"{}\"",
+            name.replace('"', "\"\"")
+        ))
+        .fetch_one(pool)
+        .await?;
+        tables.insert(name, count);
+    }
+    let size_bytes: i64 = sqlx::query_scalar(
+        "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(DbStats { tables, size_bytes })
+}
+
+pub async fn add_attachment(
+    pool: &SqlitePool,
+    todo_id: i64,
+    name: String,
+    url: String,
+) -> Result<Attachment, sqlx::Error> {
+    let now = Utc::now();
+    let mut tx = pool.begin().await?;
+    // Errors with RowNotFound when the todo doesn't exist
+    sqlx::query!(
+        "SELECT id FROM todo WHERE id = ? AND deleted_at IS NULL",
+        todo_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let attachment = sqlx::query_as!(
+        Attachment,
+        r#"
+        INSERT INTO todo_attachment (todo_id, name, url, created_at)
+        VALUES (?, ?, ?, ?)
+        RETURNING id AS "id!", todo_id, name, url, created_at
+        "#,
+        todo_id,
+        name,
+        url,
+        now
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(attachment)
+}
+
+pub async fn get_attachments(
+    pool: &SqlitePool,
+    todo_id: i64,
+) -> Result<Vec<Attachment>, sqlx::Error> {
+    let attachments = sqlx::query_as!(
+        Attachment,
+        r#"
+        SELECT id AS "id!", todo_id, name, url, created_at
+        FROM todo_attachment WHERE todo_id = ? ORDER BY id
+        "#,
+        todo_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(attachments)
+}
+
+pub async fn delete_attachment(
     pool: &SqlitePool,
-    completed: bool,
-) -> Result<Vec<Todo>, sqlx::Error> {
-    let todos = sqlx::query_as!(Todo, "SELECT * FROM todo WHERE completed = ?", completed)
-        .fetch_all(pool)
-        .await?;
-    Ok(todos)
+    todo_id: i64,
+    attachment_id: i64,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM todo_attachment WHERE id = ? AND todo_id = ?",
+        attachment_id,
+        todo_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
 }
 
+/// Todos created between `start_date` and `end_date`, both inclusive. `created_at` is stored
+/// with a `T` separator and an offset, so both sides are normalized with `datetime()` to compare.
 pub async fn get_todos_by_time_range(
     pool: &SqlitePool,
     start_date: NaiveDateTime,
@@ -116,7 +1601,9 @@ pub async fn get_todos_by_time_range(
 ) -> Result<Vec<Todo>, sqlx::Error> {
     let todos = sqlx::query_as!(
         Todo,
-        "SELECT * FROM todo WHERE created_at BETWEEN ? AND ?",
+        r#"
+        SELECT * FROM todo_with_tags WHERE datetime(created_at) BETWEEN datetime(?) AND datetime(?) AND deleted_at IS NULL
+        "#,
         start_date,
         end_date
     )
@@ -125,6 +1612,85 @@ pub async fn get_todos_by_time_range(
     Ok(todos)
 }
 
+/// Opens a worklog entry. A second open entry for the same todo violates a unique index.
+pub async fn start_timer(pool: &SqlitePool, todo_id: i64) -> Result<Worklog, sqlx::Error> {
+    let now = Utc::now();
+    // Inserts nothing, and so errors with RowNotFound, when the todo doesn't exist
+    let worklog = sqlx::query_as!(
+        Worklog,
+        r#"
+        INSERT INTO todo_worklog (todo_id, started_at)
+        SELECT id, ? FROM todo WHERE id = ? AND deleted_at IS NULL
+        RETURNING id AS "id!", todo_id, started_at, ended_at
+        "#,
+        now,
+        todo_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(worklog)
+}
+
+/// Closes the running worklog entry, returning `None` when no timer is running.
+pub async fn stop_timer(pool: &SqlitePool, todo_id: i64) -> Result<Option<Worklog>, sqlx::Error> {
+    let now = Utc::now();
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "SELECT id FROM todo WHERE id = ? AND deleted_at IS NULL",
+        todo_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let worklog = sqlx::query_as!(
+        Worklog,
+        r#"
+        UPDATE todo_worklog SET ended_at = ?
+        WHERE todo_id = ? AND ended_at IS NULL
+        RETURNING id AS "id!", todo_id, started_at, ended_at
+        "#,
+        now,
+        todo_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(worklog)
+}
+
+/// Sums the durations of all worklog entries, counting a running timer up to now.
+pub async fn get_time_spent(pool: &SqlitePool, todo_id: i64) -> Result<TimeSpent, sqlx::Error> {
+    sqlx::query!(
+        "SELECT id FROM todo WHERE id = ? AND deleted_at IS NULL",
+        todo_id
+    )
+    .fetch_one(pool)
+    .await?;
+    let worklogs = sqlx::query_as!(
+        Worklog,
+        r#"
+        SELECT id AS "id!", todo_id, started_at, ended_at
+        FROM todo_worklog WHERE todo_id = ?
+        "#,
+        todo_id
+    )
+    .fetch_all(pool)
+    .await?;
+    let now = Utc::now().naive_utc();
+    let total_seconds = worklogs
+        .iter()
+        .map(|w| {
+            (w.ended_at.unwrap_or(now) - w.started_at)
+                .num_seconds()
+                .max(0)
+        })
+        .sum();
+    Ok(TimeSpent {
+        todo_id,
+        total_seconds,
+        running: worklogs.iter().any(|w| w.ended_at.is_none()),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::Days;
@@ -133,7 +1699,7 @@ mod tests {
 
     async fn init_test_db() -> Result<SqlitePool, sqlx::Error> {
         let db_url = "sqlite://test.db";
-        init_db(db_url).await
+        init_db(db_url, &PoolConfig::default()).await
     }
 
     async fn cleanup_test_db() -> Result<(), sqlx::Error> {
@@ -145,38 +1711,56 @@ mod tests {
     }
 
     async fn test_get_todos_empty(pool: &SqlitePool) {
-        let todos = get_todos(&pool).await;
+        let todos = get_todos(pool).await;
         assert!(todos.is_ok());
         let todos = todos.unwrap();
         assert!(todos.is_empty()); // Initially, the database should be empty
     }
 
     async fn test_create_todo(pool: &SqlitePool) {
-        let todo = create_todo(&pool, "Test Todo".to_string(), None).await;
+        let todo = create_todo(pool, "Test Todo".to_string(), None, false, None).await;
         assert!(todo.is_ok());
         let todo = todo.unwrap();
         assert_eq!(todo.title, "Test Todo");
         assert!(!todo.completed);
     }
 
+    async fn test_create_completed_todo(pool: &SqlitePool) {
+        let todo = create_todo(pool, "Imported Done".to_string(), None, true, None).await;
+        assert!(todo.is_ok());
+        let todo = todo.unwrap();
+        assert!(todo.completed);
+        assert!(todo.completed_at.is_some());
+
+        let reopened = update_todo(pool, todo.id.unwrap(), None, None, Some(false), None, None)
+            .await
+            .unwrap();
+        assert!(!reopened.completed);
+        assert!(reopened.completed_at.is_none());
+        delete_todo(pool, todo.id.unwrap()).await.unwrap();
+    }
+
     async fn test_get_todos(pool: &SqlitePool) {
-        let todos = get_todos(&pool).await;
+        let todos = get_todos(pool).await;
         assert!(todos.is_ok());
         let todos = todos.unwrap();
         assert!(!todos.is_empty()); // There should be at least one todo
         assert_eq!(todos[0].title, "Test Todo"); // Check the title of the created todo
+        assert_eq!(count_todos(pool, &[]).await.unwrap(), todos.len() as i64);
     }
 
     async fn test_update_todo(pool: &SqlitePool) {
-        let todo = create_todo(&pool, "Update Test".to_string(), None)
+        let todo = create_todo(pool, "Update Test".to_string(), None, false, None)
             .await
             .unwrap();
         let updated_todo = update_todo(
-            &pool,
+            pool,
             todo.id.unwrap(),
             Some("Updated Title".to_string()),
             None,
             None,
+            None,
+            None,
         )
         .await;
         assert!(updated_todo.is_ok());
@@ -185,30 +1769,38 @@ mod tests {
     }
 
     async fn test_delete_todo(pool: &SqlitePool) {
-        let todo = create_todo(&pool, "Delete Test".to_string(), None)
+        let todo = create_todo(pool, "Delete Test".to_string(), None, false, None)
             .await
             .unwrap();
-        let delete_result = delete_todo(&pool, todo.id.unwrap()).await;
-        assert!(delete_result.is_ok());
-        let todos = get_todos(&pool).await.unwrap();
+        let delete_result = delete_todo(pool, todo.id.unwrap()).await;
+        assert_eq!(delete_result.unwrap(), 1);
+        let todos = get_todos(pool).await.unwrap();
         assert!(todos.iter().all(|t| t.id != todo.id)); // The todo should be deleted
+        // Already deleted
+        assert_eq!(delete_todo(pool, todo.id.unwrap()).await.unwrap(), 0);
     }
 
     async fn test_get_todo_by_id(pool: &SqlitePool) {
-        let todo = create_todo(&pool, "Get by ID Test".to_string(), None)
+        let todo = create_todo(pool, "Get by ID Test".to_string(), None, false, None)
             .await
             .unwrap();
-        let fetched_todo = get_todo_by_id(&pool, todo.id.unwrap()).await;
+        let fetched_todo = get_todo_by_id(pool, todo.id.unwrap()).await;
         assert!(fetched_todo.is_ok());
         let fetched_todo = fetched_todo.unwrap();
         assert_eq!(fetched_todo.title, "Get by ID Test");
     }
 
     async fn test_get_todos_by_completion(pool: &SqlitePool) {
-        let _ = create_todo(&pool, "Get by Completion Test".to_string(), None)
-            .await
-            .unwrap();
-        let fetched_todos = get_todos_by_completion(&pool, false).await;
+        let _ = create_todo(
+            pool,
+            "Get by Completion Test".to_string(),
+            None,
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+        let fetched_todos = get_todos_by_completion(pool, false).await;
         assert!(fetched_todos.is_ok());
         let fetched_todos = fetched_todos.unwrap();
         assert_eq!(fetched_todos.len(), 4);
@@ -220,20 +1812,804 @@ mod tests {
             .naive_utc()
             .checked_sub_days(Days::new(1))
             .unwrap();
-        let _ = create_todo(&pool, "Get by Date Range Test".to_string(), None)
-            .await
-            .unwrap();
+        let _ = create_todo(
+            pool,
+            "Get by Date Range Test".to_string(),
+            None,
+            false,
+            None,
+        )
+        .await
+        .unwrap();
         let end_date = Utc::now()
             .naive_utc()
             .checked_add_days(Days::new(1))
             .unwrap();
-        let fetched_todos = get_todos_by_time_range(&pool, start_date, end_date).await;
+        let fetched_todos = get_todos_by_time_range(pool, start_date, end_date).await;
         assert!(fetched_todos.is_ok());
         let fetched_todos = fetched_todos.unwrap();
         assert_eq!(fetched_todos.len(), 5);
         assert_eq!(fetched_todos[4].title, "Get by Date Range Test");
     }
 
+    async fn test_get_todos_grouped(pool: &SqlitePool) {
+        let done = create_todo(pool, "Grouped Complete".to_string(), None, false, None)
+            .await
+            .unwrap();
+        update_todo(pool, done.id.unwrap(), None, None, Some(true), None, None)
+            .await
+            .unwrap();
+        let pending = create_todo(pool, "Grouped Incomplete".to_string(), None, false, None)
+            .await
+            .unwrap();
+
+        let grouped = get_todos_grouped(pool, None, None).await;
+        assert!(grouped.is_ok());
+        let grouped = grouped.unwrap();
+        assert_eq!(grouped.complete.len(), 1);
+        assert_eq!(grouped.complete[0].id, done.id);
+        assert!(grouped.complete[0].completed_at.is_some());
+        assert_eq!(grouped.incomplete.len(), 6);
+        assert!(grouped.incomplete.iter().all(|t| !t.completed));
+        assert!(grouped.incomplete.iter().any(|t| t.id == pending.id));
+
+        let limited = get_todos_grouped(pool, Some(2), Some(0)).await.unwrap();
+        assert_eq!(limited.incomplete.len(), 2);
+        assert!(limited.complete.is_empty());
+    }
+
+    async fn test_set_due_date_bulk(pool: &SqlitePool) {
+        let mut ids = Vec::new();
+        for title in ["Due One", "Due Two", "Due Three"] {
+            let todo = create_todo(pool, title.to_string(), None, false, None)
+                .await
+                .unwrap();
+            ids.push(todo.id.unwrap());
+        }
+        let due_date = Utc::now()
+            .naive_utc()
+            .checked_add_days(Days::new(7))
+            .unwrap();
+        let updated = set_due_date_bulk(pool, &ids, due_date).await;
+        assert!(updated.is_ok());
+        assert_eq!(updated.unwrap(), 3);
+        for id in ids {
+            let todo = get_todo_by_id(pool, id).await.unwrap();
+            assert_eq!(todo.due_date, Some(due_date));
+        }
+        assert_eq!(set_due_date_bulk(pool, &[], due_date).await.unwrap(), 0);
+    }
+
+    async fn test_set_completion_bulk(pool: &SqlitePool) {
+        let mut ids = Vec::new();
+        for (title, completed) in [
+            ("Bulk One", false),
+            ("Bulk Two", false),
+            ("Bulk Done", true),
+        ] {
+            let todo = create_todo(pool, title.to_string(), None, completed, None)
+                .await
+                .unwrap();
+            ids.push(todo.id.unwrap());
+        }
+        // The already-completed todo and the unknown id don't count, and the important
+        // one needs confirming
+        let mut with_unknown = ids.clone();
+        with_unknown.push(i64::MAX);
+        set_important(pool, ids[1], true).await.unwrap();
+        assert_eq!(
+            set_completion_bulk(pool, &with_unknown, true, false)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(!get_todo_by_id(pool, ids[1]).await.unwrap().completed);
+        assert_eq!(
+            set_completion_bulk(pool, &with_unknown, true, true)
+                .await
+                .unwrap(),
+            1
+        );
+        for &id in &ids {
+            let todo = get_todo_by_id(pool, id).await.unwrap();
+            assert!(todo.completed);
+            assert!(todo.completed_at.is_some());
+        }
+
+        assert_eq!(
+            set_completion_bulk(pool, &ids, false, false).await.unwrap(),
+            3
+        );
+        let reopened = get_todo_by_id(pool, ids[0]).await.unwrap();
+        assert!(!reopened.completed);
+        assert_eq!(reopened.completed_at, None);
+        assert_eq!(
+            set_completion_bulk(pool, &[], true, false).await.unwrap(),
+            0
+        );
+    }
+
+    async fn test_complete_todos_in_range(pool: &SqlitePool) {
+        let day = |d: u32| {
+            chrono::NaiveDate::from_ymd_opt(2001, 1, d)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap()
+        };
+        let mut ids = Vec::new();
+        for (d, completed) in [
+            (1, false),
+            (5, false),
+            (6, false),
+            (8, true),
+            (10, false),
+            (20, false),
+        ] {
+            let todo = create_todo(pool, format!("Sprint {d}"), None, completed, None)
+                .await
+                .unwrap();
+            let id = todo.id.unwrap();
+            sqlx::query("UPDATE todo SET created_at = ? WHERE id = ?")
+                .bind(day(d))
+                .bind(id)
+                .execute(pool)
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+
+        set_important(pool, ids[2], true).await.unwrap();
+
+        let states = || async {
+            let mut states = Vec::new();
+            for id in &ids {
+                let todo = get_todo_by_id(pool, *id).await.unwrap();
+                assert_eq!(todo.completed, todo.completed_at.is_some());
+                states.push(todo.completed);
+            }
+            states
+        };
+        // The important todo on day 6 needs confirming
+        let completed = complete_todos_in_range(pool, day(2), day(10), false)
+            .await
+            .unwrap();
+        assert_eq!(completed, 2);
+        assert_eq!(states().await, [false, true, false, true, true, false]);
+        let completed = complete_todos_in_range(pool, day(2), day(10), true)
+            .await
+            .unwrap();
+        assert_eq!(completed, 1);
+        assert_eq!(states().await, [false, true, true, true, true, false]);
+    }
+
+    async fn test_set_tags(pool: &SqlitePool) {
+        let todo = create_todo(pool, "Tagged Todo".to_string(), None, false, None)
+            .await
+            .unwrap();
+        let id = todo.id.unwrap();
+        let tags = set_tags(pool, id, &["b".to_string(), "a".to_string()]).await;
+        assert!(tags.is_ok());
+        assert_eq!(tags.unwrap(), vec!["a", "b"]);
+
+        let tags = set_tags(
+            pool,
+            id,
+            &["c".to_string(), "b".to_string(), " c ".to_string()],
+        )
+        .await
+        .unwrap();
+        assert_eq!(tags, vec!["b", "c"]);
+        assert_eq!(get_tags_for_todo(pool, id).await.unwrap(), vec!["b", "c"]);
+
+        let missing = set_tags(pool, -1, &["a".to_string()]).await;
+        assert!(matches!(missing, Err(sqlx::Error::RowNotFound)));
+    }
+
+    async fn test_add_remove_tag(pool: &SqlitePool) {
+        let todo = create_todo(pool, "Tag one by one".to_string(), None, false, None)
+            .await
+            .unwrap();
+        let id = todo.id.unwrap();
+        assert!(todo.tags.0.is_empty());
+
+        assert_eq!(
+            add_tag(pool, id, "by-tag-b").await.unwrap(),
+            vec!["by-tag-b"]
+        );
+        assert_eq!(
+            add_tag(pool, id, " by-tag-a ").await.unwrap(),
+            vec!["by-tag-a", "by-tag-b"]
+        );
+        // Adding twice is a no-op
+        assert_eq!(
+            add_tag(pool, id, "by-tag-a").await.unwrap(),
+            vec!["by-tag-a", "by-tag-b"]
+        );
+        let fetched = get_todo_by_id(pool, id).await.unwrap();
+        assert_eq!(
+            fetched.tags,
+            Tags(vec!["by-tag-a".into(), "by-tag-b".into()])
+        );
+
+        let tagged = get_todos_by_tag(pool, "by-tag-b").await.unwrap();
+        assert_eq!(tagged.len(), 1);
+        assert_eq!(tagged[0].id, Some(id));
+        assert_eq!(tagged[0].tags.0, vec!["by-tag-a", "by-tag-b"]);
+
+        assert_eq!(
+            remove_tag(pool, id, "by-tag-b").await.unwrap(),
+            vec!["by-tag-a"]
+        );
+        assert!(get_todos_by_tag(pool, "by-tag-b").await.unwrap().is_empty());
+        assert!(matches!(
+            remove_tag(pool, id, "by-tag-b").await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+        assert!(matches!(
+            add_tag(pool, -1, "by-tag-a").await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+
+        delete_todo(pool, id).await.unwrap();
+        assert!(get_todos_by_tag(pool, "by-tag-a").await.unwrap().is_empty());
+    }
+
+    async fn test_get_tag_stats(pool: &SqlitePool) {
+        let seeds = [
+            (true, vec!["stats-work", "stats-home"]),
+            (false, vec!["stats-work"]),
+            (false, vec!["stats-work"]),
+            (true, vec!["stats-home"]),
+            (false, vec!["stats-idle"]),
+        ];
+        for (completed, tags) in seeds {
+            let todo = create_todo(pool, "Tag stats".to_string(), None, completed, None)
+                .await
+                .unwrap();
+            let tags: Vec<String> = tags.into_iter().map(String::from).collect();
+            set_tags(pool, todo.id.unwrap(), &tags).await.unwrap();
+        }
+        // Untagging leaves "stats-empty" without todos, so it drops out of the stats
+        let idle = create_todo(pool, "Idle".to_string(), None, false, None)
+            .await
+            .unwrap();
+        set_tags(pool, idle.id.unwrap(), &["stats-empty".to_string()])
+            .await
+            .unwrap();
+        set_tags(pool, idle.id.unwrap(), &[]).await.unwrap();
+
+        let stats: Vec<TagStats> = get_tag_stats(pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|stats| stats.tag.starts_with("stats-"))
+            .collect();
+        let summary: Vec<(&str, i64, i64)> = stats
+            .iter()
+            .map(|stats| (stats.tag.as_str(), stats.total, stats.completed))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("stats-home", 2, 2),
+                ("stats-idle", 1, 0),
+                ("stats-work", 3, 1)
+            ]
+        );
+        assert_eq!(stats[0].rate, 1.0);
+        assert_eq!(stats[1].rate, 0.0);
+        assert!((stats[2].rate - 1.0 / 3.0).abs() < 1e-9);
+    }
+
+    async fn test_get_db_stats(pool: &SqlitePool) {
+        // Stats count physical rows, soft-deleted todos included
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM todo")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        let stats = get_db_stats(pool).await;
+        assert!(stats.is_ok());
+        let stats = stats.unwrap();
+        assert_eq!(stats.tables["todo"], rows);
+        assert_eq!(stats.tables["tags"], 3); // "a", "b" and "c" from test_set_tags
+        assert_eq!(stats.tables["todo_tags"], 2);
+        assert!(!stats.tables.contains_key("_sqlx_migrations"));
+        assert!(stats.size_bytes > 0);
+    }
+
+    async fn test_delete_completed_archived(pool: &SqlitePool) {
+        let mut ids = Vec::new();
+        for (title, completed, archived) in [
+            ("Done and Archived", true, true),
+            ("Done Only", true, false),
+            ("Archived Only", false, true),
+        ] {
+            let todo = create_todo(pool, title.to_string(), None, completed, None)
+                .await
+                .unwrap();
+            let todo = update_todo(
+                pool,
+                todo.id.unwrap(),
+                None,
+                None,
+                None,
+                Some(archived),
+                None,
+            )
+            .await
+            .unwrap();
+            assert_eq!(todo.archived, archived);
+            ids.push(todo.id.unwrap());
+        }
+
+        let deleted = delete_completed_archived(pool).await;
+        assert!(deleted.is_ok());
+        assert_eq!(deleted.unwrap(), 1);
+        assert!(get_todo_by_id(pool, ids[0]).await.is_err());
+        assert!(get_todo_by_id(pool, ids[1]).await.is_ok());
+        assert!(get_todo_by_id(pool, ids[2]).await.is_ok());
+        for id in &ids[1..] {
+            delete_todo(pool, *id).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pool_warm_up() {
+        let config = PoolConfig {
+            min_connections: 3,
+            ..PoolConfig::default()
+        };
+        let pool = init_db("sqlite::memory:", &config).await.unwrap();
+        for _ in 0..50 {
+            if pool.num_idle() >= 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(pool.num_idle() >= 3);
+    }
+
+    #[tokio::test]
+    async fn test_sort_by_updated_at_mixes_timestamp_formats() {
+        let pool = init_db("sqlite::memory:", &PoolConfig::default())
+            .await
+            .unwrap();
+        let edited = create_todo(&pool, "Edited".to_string(), None, false, None)
+            .await
+            .unwrap();
+        // Never edited, so its timestamps keep the RFC 3339 form `create_todo` writes
+        let an_hour_ago = Utc::now() - chrono::Duration::hours(1);
+        sqlx::query("INSERT INTO todo (title, created_at, updated_at) VALUES (?, ?, ?)")
+            .bind("Untouched")
+            .bind(an_hour_ago)
+            .bind(an_hour_ago)
+            .execute(&pool)
+            .await
+            .unwrap();
+        // The trigger rewrites `updated_at` as `CURRENT_TIMESTAMP`
+        update_todo(
+            &pool,
+            edited.id.unwrap(),
+            None,
+            None,
+            Some(true),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        let titles = |order: SortOrder| {
+            let pool = pool.clone();
+            async move {
+                let sort = TodoSort {
+                    sort_by: TodoSortBy::UpdatedAt,
+                    order,
+                };
+                get_todos_page(&pool, sort, &[], 10, 0)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|todo| todo.title)
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(titles(SortOrder::Desc).await, ["Edited", "Untouched"]);
+        assert_eq!(titles(SortOrder::Asc).await, ["Untouched", "Edited"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_completion_stats() {
+        let pool = init_db("sqlite::memory:", &PoolConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            get_completion_stats(&pool).await.unwrap(),
+            CompletionStats::default()
+        );
+
+        for _ in 0..2 {
+            create_todo(&pool, "Done".to_string(), None, true, None)
+                .await
+                .unwrap();
+        }
+        let stats = get_completion_stats(&pool).await.unwrap();
+        assert_eq!(
+            stats,
+            CompletionStats {
+                completed: 2,
+                incomplete: 0,
+                total: 2
+            }
+        );
+
+        create_todo(&pool, "Pending".to_string(), None, false, None)
+            .await
+            .unwrap();
+        let deleted = create_todo(&pool, "Deleted".to_string(), None, false, None)
+            .await
+            .unwrap();
+        delete_todo(&pool, deleted.id.unwrap()).await.unwrap();
+        let stats = get_completion_stats(&pool).await.unwrap();
+        assert_eq!(
+            stats,
+            CompletionStats {
+                completed: 2,
+                incomplete: 1,
+                total: 3
+            }
+        );
+    }
+
+    async fn test_get_age_buckets(pool: &SqlitePool) {
+        let todo = create_todo(pool, "Aged".to_string(), None, false, None)
+            .await
+            .unwrap();
+        let created_at = todo.created_at.unwrap();
+        let buckets_at = |age: chrono::Duration| async move {
+            let buckets = get_age_buckets(pool, created_at + age).await.unwrap();
+            [
+                buckets.under_1_day,
+                buckets.from_1_to_7_days,
+                buckets.from_7_to_30_days,
+                buckets.over_30_days,
+            ]
+        };
+        // 25 hours puts the 1-day bound on the todo's own calendar date
+        let cases = [
+            (chrono::Duration::hours(2), [1, 0, 0, 0]),
+            (chrono::Duration::hours(25), [0, 1, 0, 0]),
+            (chrono::Duration::days(7), [0, 0, 1, 0]),
+            (chrono::Duration::days(10), [0, 0, 1, 0]),
+            (chrono::Duration::days(45), [0, 0, 0, 1]),
+        ];
+        let mut open = Vec::new();
+        for (age, _) in cases {
+            open.push(buckets_at(age).await);
+        }
+
+        // Completed todos aren't open, whatever their age, so completing it removes it
+        // from the counts that earlier tests' rows also contribute to
+        update_todo(pool, todo.id.unwrap(), None, None, Some(true), None, None)
+            .await
+            .unwrap();
+        for ((age, expected), open) in cases.into_iter().zip(open) {
+            let closed = buckets_at(age).await;
+            let counted: Vec<i64> = open.iter().zip(closed).map(|(o, c)| o - c).collect();
+            assert_eq!(counted, expected, "{age}");
+        }
+    }
+
+    async fn test_attachments(pool: &SqlitePool) {
+        let todo = create_todo(pool, "With Attachments".to_string(), None, false, None)
+            .await
+            .unwrap();
+        let id = todo.id.unwrap();
+        for (name, url) in [
+            ("Spec", "https://example.com/spec.pdf"),
+            ("Mockup", "https://example.com/mockup.png"),
+        ] {
+            let attachment = add_attachment(pool, id, name.to_string(), url.to_string()).await;
+            assert!(attachment.is_ok());
+            assert_eq!(attachment.unwrap().todo_id, id);
+        }
+
+        let attachments = get_attachments(pool, id).await.unwrap();
+        assert_eq!(attachments.len(), 2);
+        assert_eq!(attachments[0].name, "Spec");
+        assert_eq!(attachments[1].url, "https://example.com/mockup.png");
+
+        let missing = add_attachment(pool, -1, "x".to_string(), "https://x.io".to_string()).await;
+        assert!(matches!(missing, Err(sqlx::Error::RowNotFound)));
+
+        assert_eq!(
+            delete_attachment(pool, id, attachments[0].id)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            delete_attachment(pool, id, attachments[0].id)
+                .await
+                .unwrap(),
+            0
+        );
+
+        // Soft delete keeps the row, so its attachments survive for a restore
+        delete_todo(pool, id).await.unwrap();
+        assert_eq!(get_attachments(pool, id).await.unwrap().len(), 1);
+    }
+
+    async fn test_worklog(pool: &SqlitePool) {
+        let todo = create_todo(pool, "Timed".to_string(), None, false, None)
+            .await
+            .unwrap();
+        let id = todo.id.unwrap();
+
+        let started = start_timer(pool, id).await.unwrap();
+        assert_eq!(started.todo_id, id);
+        assert!(started.ended_at.is_none());
+        assert!(get_time_spent(pool, id).await.unwrap().running);
+
+        match start_timer(pool, id).await {
+            Err(sqlx::Error::Database(e)) => assert!(e.is_unique_violation()),
+            other => panic!("expected unique violation, got {other:?}"),
+        }
+
+        let stopped = stop_timer(pool, id).await.unwrap().unwrap();
+        assert_eq!(stopped.id, started.id);
+        assert!(stopped.ended_at.unwrap() >= stopped.started_at);
+        assert!(stop_timer(pool, id).await.unwrap().is_none());
+
+        // A new timer can be started once the previous one is closed
+        start_timer(pool, id).await.unwrap();
+        stop_timer(pool, id).await.unwrap().unwrap();
+        let spent = get_time_spent(pool, id).await.unwrap();
+        assert!(!spent.running);
+        assert!(spent.total_seconds >= 0);
+
+        assert!(matches!(
+            start_timer(pool, -1).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+        assert!(matches!(
+            get_time_spent(pool, -1).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_normalize_search() {
+        assert_eq!(normalize_search("Café"), "cafe");
+        assert_eq!(normalize_search("CRÈME Brûlée"), "creme brulee");
+        assert_eq!(normalize_search("naïve"), normalize_search("NAIVE"));
+    }
+
+    async fn test_search_todos(pool: &SqlitePool) {
+        let accented = create_todo(
+            pool,
+            "Meet at the Café Zanzibar".to_string(),
+            Some("Bring the crème brûlée".to_string()),
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+        let plain = create_todo(pool, "Cafe Quokka review".to_string(), None, false, None)
+            .await
+            .unwrap();
+
+        let ids = |todos: Vec<Todo>| todos.into_iter().map(|t| t.id).collect::<Vec<_>>();
+        assert_eq!(
+            ids(search_todos(pool, "cafe zanzibar").await.unwrap()),
+            vec![accented.id]
+        );
+        assert_eq!(
+            ids(search_todos(pool, "CAFÉ QUOKKA").await.unwrap()),
+            vec![plain.id]
+        );
+        assert_eq!(
+            ids(search_todos(pool, "creme brulee").await.unwrap()),
+            vec![accented.id]
+        );
+        assert_eq!(
+            ids(search_todos(pool, "cafe").await.unwrap()),
+            vec![plain.id, accented.id]
+        );
+        assert!(search_todos(pool, "no such todo").await.unwrap().is_empty());
+
+        // Edits rewrite the index entry so the new text is searchable
+        update_todo(
+            pool,
+            plain.id.unwrap(),
+            Some("Crêpe Quokka review".to_string()),
+            None,
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(search_todos(pool, "cafe quokka").await.unwrap().is_empty());
+        assert_eq!(
+            ids(search_todos(pool, "crepe quokka").await.unwrap()),
+            vec![plain.id]
+        );
+        // LIKE wildcards in the term are matched literally
+        assert!(search_todos(pool, "quokka%").await.unwrap().is_empty());
+
+        // Searching only reads, so concurrent searches right after an edit all succeed
+        update_todo(
+            pool,
+            plain.id.unwrap(),
+            Some("Crêpe Quokka rerun".to_string()),
+            None,
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        let searches: Vec<_> = (0..8)
+            .map(|_| {
+                let pool = pool.clone();
+                tokio::spawn(async move { search_todos(&pool, "quokka rerun").await })
+            })
+            .collect();
+        for search in searches {
+            assert_eq!(ids(search.await.unwrap().unwrap()), vec![plain.id]);
+        }
+    }
+
+    async fn test_restore_todos(pool: &SqlitePool) {
+        let mut ids = Vec::new();
+        for title in ["Restore A", "Restore B", "Restore C"] {
+            let todo = create_todo(pool, title.to_string(), None, false, None)
+                .await
+                .unwrap();
+            ids.push(todo.id.unwrap());
+        }
+        delete_todo(pool, ids[0]).await.unwrap();
+        delete_todo(pool, ids[1]).await.unwrap();
+        assert!(matches!(
+            get_todo_by_id(pool, ids[0]).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+        let visible = get_todos(pool).await.unwrap();
+        assert!(
+            visible
+                .iter()
+                .all(|t| t.id != Some(ids[0]) && t.id != Some(ids[1]))
+        );
+
+        // ids[2] was never deleted and -1 doesn't exist, so only two rows are restored
+        let restored = restore_todos(pool, &[ids[0], ids[1], ids[2], -1])
+            .await
+            .unwrap();
+        assert_eq!(restored, 2);
+        for id in &ids {
+            let todo = get_todo_by_id(pool, *id).await.unwrap();
+            assert!(todo.deleted_at.is_none());
+        }
+        assert_eq!(restore_todos(pool, &ids).await.unwrap(), 0);
+        assert_eq!(restore_todos(pool, &[]).await.unwrap(), 0);
+    }
+
+    async fn test_get_duplicate_todos(pool: &SqlitePool) {
+        let first = create_todo(pool, "Water the ferns".to_string(), None, false, None)
+            .await
+            .unwrap();
+        let second = create_todo(pool, "  WATER the Ferns ".to_string(), None, true, None)
+            .await
+            .unwrap();
+        let unique = create_todo(pool, "Repot the cactus".to_string(), None, false, None)
+            .await
+            .unwrap();
+
+        let groups = get_duplicate_todos(pool).await.unwrap();
+        let ferns = groups
+            .iter()
+            .find(|group| group.title == "water the ferns")
+            .expect("duplicate group should be reported");
+        assert_eq!(ferns.ids, vec![first.id.unwrap(), second.id.unwrap()]);
+        assert_eq!(ferns.count, 2);
+        assert!(groups.iter().all(|group| group.count > 1));
+        assert!(
+            groups
+                .iter()
+                .all(|group| !group.ids.contains(&unique.id.unwrap()))
+        );
+
+        // Deleted todos no longer count towards a group
+        delete_todo(pool, second.id.unwrap()).await.unwrap();
+        let groups = get_duplicate_todos(pool).await.unwrap();
+        assert!(groups.iter().all(|group| group.title != "water the ferns"));
+    }
+
+    async fn test_inconsistent_parents(pool: &SqlitePool) {
+        let parent = create_todo(pool, "Parent".to_string(), None, true, None)
+            .await
+            .unwrap();
+        let parent_id = parent.id.unwrap();
+        let reported = |todos: Vec<Todo>| todos.iter().any(|t| t.id == Some(parent_id));
+        let done = create_todo(pool, "Done child".to_string(), None, true, None)
+            .await
+            .unwrap();
+        set_parent(pool, done.id.unwrap(), Some(parent_id))
+            .await
+            .unwrap();
+        assert!(!reported(get_inconsistent_parents(pool).await.unwrap()));
+
+        let open = create_todo(pool, "Open child".to_string(), None, false, None)
+            .await
+            .unwrap();
+        let open = set_parent(pool, open.id.unwrap(), Some(parent_id))
+            .await
+            .unwrap();
+        assert_eq!(open.parent_id, Some(parent_id));
+        assert!(reported(get_inconsistent_parents(pool).await.unwrap()));
+
+        let fixed = reopen_inconsistent_parents(pool).await.unwrap();
+        assert!(reported(fixed));
+        let parent = get_todo_by_id(pool, parent_id).await.unwrap();
+        assert!(!parent.completed);
+        assert!(parent.completed_at.is_none());
+        assert!(!reported(get_inconsistent_parents(pool).await.unwrap()));
+
+        assert!(
+            is_ancestor_or_self(pool, parent_id, open.id.unwrap())
+                .await
+                .unwrap()
+        );
+        assert!(
+            is_ancestor_or_self(pool, parent_id, parent_id)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !is_ancestor_or_self(pool, open.id.unwrap(), parent_id)
+                .await
+                .unwrap()
+        );
+    }
+
+    async fn test_get_reminders_due(pool: &SqlitePool) {
+        let now = Utc::now().naive_utc();
+        let todo = create_todo(pool, "Reminder".to_string(), None, false, None)
+            .await
+            .unwrap();
+        let id = todo.id.unwrap();
+        set_due_date_bulk(pool, &[id], now + chrono::Duration::minutes(30))
+            .await
+            .unwrap();
+        let fires = |todos: Vec<Todo>| todos.iter().any(|t| t.id == Some(id));
+
+        let todo = set_reminder_offset(pool, id, Some(60)).await.unwrap();
+        assert_eq!(todo.reminder_offset_minutes, Some(60));
+        assert!(fires(get_reminders_due(pool, now, 0).await.unwrap()));
+
+        set_reminder_offset(pool, id, Some(10)).await.unwrap();
+        assert!(!fires(get_reminders_due(pool, now, 0).await.unwrap()));
+        // 25 minutes later the 10 minute window has opened
+        let later = now + chrono::Duration::minutes(25);
+        assert!(fires(get_reminders_due(pool, later, 0).await.unwrap()));
+
+        // Without its own offset the todo falls back to the default
+        let todo = set_reminder_offset(pool, id, None).await.unwrap();
+        assert!(todo.reminder_offset_minutes.is_none());
+        assert!(fires(get_reminders_due(pool, now, 60).await.unwrap()));
+        assert!(!fires(get_reminders_due(pool, now, 10).await.unwrap()));
+
+        update_todo(pool, id, None, None, Some(true), None, None)
+            .await
+            .unwrap();
+        assert!(!fires(get_reminders_due(pool, later, 60).await.unwrap()));
+        assert!(matches!(
+            set_reminder_offset(pool, -1, Some(5)).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+    }
+
     #[tokio::test]
     async fn run_tests() {
         let pool = init_test_db().await;
@@ -244,12 +2620,30 @@ mod tests {
 
         test_get_todos_empty(&pool).await;
         test_create_todo(&pool).await;
+        test_create_completed_todo(&pool).await;
         test_get_todos(&pool).await;
         test_update_todo(&pool).await;
         test_delete_todo(&pool).await;
         test_get_todo_by_id(&pool).await;
         test_get_todos_by_completion(&pool).await;
         test_get_todos_by_date_range(&pool).await;
+        test_get_todos_grouped(&pool).await;
+        test_set_due_date_bulk(&pool).await;
+        test_set_completion_bulk(&pool).await;
+        test_complete_todos_in_range(&pool).await;
+        test_set_tags(&pool).await;
+        test_get_db_stats(&pool).await;
+        test_get_tag_stats(&pool).await;
+        test_add_remove_tag(&pool).await;
+        test_delete_completed_archived(&pool).await;
+        test_attachments(&pool).await;
+        test_worklog(&pool).await;
+        test_search_todos(&pool).await;
+        test_restore_todos(&pool).await;
+        test_get_duplicate_todos(&pool).await;
+        test_get_reminders_due(&pool).await;
+        test_inconsistent_parents(&pool).await;
+        test_get_age_buckets(&pool).await;
 
         cleanup_test_db()
             .await
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE url ADD COLUMN note TEXT;
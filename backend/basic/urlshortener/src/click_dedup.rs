@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Entries are pruned once the map holds this many, so idle keys don't pile up.
+const PRUNE_AT: usize = 4096;
+
+/// In-memory filter that counts repeated hits on a short code from one client within
+/// `window` as a single click, e.g. a browser prefetch followed by the real click.
+/// State is per process and resets on restart.
+#[derive(Debug)]
+pub struct ClickDeduplicator {
+    window: Duration,
+    counted: Mutex<HashMap<(String, IpAddr), Instant>>,
+}
+
+impl ClickDeduplicator {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            counted: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns `true` if `client` already had a hit on the code counted within the window
+    /// before `now`; otherwise remembers this hit as the counted one.
+    pub fn is_duplicate(&self, short_url: &str, client: IpAddr, now: Instant) -> bool {
+        let mut counted = self.counted.lock().unwrap();
+        if counted.len() >= PRUNE_AT {
+            counted.retain(|_, &mut at| now.duration_since(at) < self.window);
+        }
+        let key = (short_url.to_string(), client);
+        match counted.get(&key) {
+            Some(&at) if now.duration_since(at) < self.window => true,
+            _ => {
+                counted.insert(key, now);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeats_within_window_are_duplicates() {
+        let dedup = ClickDeduplicator::new(Duration::from_secs(2));
+        let client: IpAddr = [10, 0, 0, 1].into();
+        let other: IpAddr = [10, 0, 0, 2].into();
+        let start = Instant::now();
+
+        assert!(!dedup.is_duplicate("abc", client, start));
+        assert!(dedup.is_duplicate("abc", client, start + Duration::from_millis(500)));
+        assert!(!dedup.is_duplicate("abc", other, start + Duration::from_millis(500)));
+        assert!(!dedup.is_duplicate("xyz", client, start + Duration::from_millis(500)));
+        // The window runs from the counted hit, so steady repeats still count periodically
+        assert!(dedup.is_duplicate("abc", client, start + Duration::from_millis(1900)));
+        assert!(!dedup.is_duplicate("abc", client, start + Duration::from_secs(2)));
+    }
+}
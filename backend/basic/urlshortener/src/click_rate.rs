@@ -0,0 +1,173 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Codes are pruned once the map holds this many, so codes that went quiet don't pile up.
+const PRUNE_AT: usize = 4096;
+
+/// In-memory sliding-window click counter used to flag short codes that receive
+/// an unusual burst of clicks. State is per process and resets on restart.
+#[derive(Debug)]
+pub struct ClickRateMonitor {
+    threshold: usize,
+    window: Duration,
+    state: Mutex<MonitorState>,
+}
+
+#[derive(Debug, Default)]
+struct MonitorState {
+    clicks: HashMap<String, VecDeque<Instant>>,
+    flagged: HashSet<String>,
+}
+
+impl MonitorState {
+    /// Drops clicks that slid out of the window ending at `now`, then codes left without
+    /// clicks, and unflags codes back under `threshold`.
+    fn prune(&mut self, now: Instant, window: Duration, threshold: usize) {
+        self.clicks.retain(|_, clicks| {
+            while clicks
+                .front()
+                .is_some_and(|&click| now.duration_since(click) >= window)
+            {
+                clicks.pop_front();
+            }
+            !clicks.is_empty()
+        });
+        let clicks = &self.clicks;
+        self.flagged.retain(|code| {
+            clicks
+                .get(code)
+                .is_some_and(|clicks| clicks.len() > threshold)
+        });
+    }
+}
+
+impl ClickRateMonitor {
+    pub fn new(threshold: usize, window: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            state: Mutex::new(MonitorState::default()),
+        }
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Records a click at `now` and returns `true` if this click pushed the code over
+    /// the threshold since it was last under it.
+    pub fn record(&self, short_url: &str, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.clicks.len() >= PRUNE_AT {
+            state.prune(now, self.window, self.threshold);
+        }
+        let clicks = state.clicks.entry(short_url.to_string()).or_default();
+        clicks.push_back(now);
+        while clicks
+            .front()
+            .is_some_and(|&click| now.duration_since(click) >= self.window)
+        {
+            clicks.pop_front();
+        }
+        if clicks.len() > self.threshold {
+            return state.flagged.insert(short_url.to_string());
+        }
+        state.flagged.remove(short_url);
+        false
+    }
+
+    pub fn is_flagged(&self, short_url: &str) -> bool {
+        self.state.lock().unwrap().flagged.contains(short_url)
+    }
+
+    /// Clicks recorded for the code within the window ending at `now`.
+    pub fn clicks_in_window(&self, short_url: &str, now: Instant) -> usize {
+        let state = self.state.lock().unwrap();
+        state.clicks.get(short_url).map_or(0, |clicks| {
+            clicks
+                .iter()
+                .filter(|&&click| now.duration_since(click) < self.window)
+                .count()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_when_threshold_exceeded() {
+        let monitor = ClickRateMonitor::new(3, Duration::from_secs(60));
+        let start = Instant::now();
+        for i in 0..3 {
+            assert!(!monitor.record("abc", start + Duration::from_secs(i)));
+        }
+        assert!(!monitor.is_flagged("abc"));
+        assert_eq!(
+            monitor.clicks_in_window("abc", start + Duration::from_secs(3)),
+            3
+        );
+
+        assert!(monitor.record("abc", start + Duration::from_secs(3)));
+        assert!(monitor.is_flagged("abc"));
+        // Only the first crossing reports a new flag
+        assert!(!monitor.record("abc", start + Duration::from_secs(4)));
+        assert!(!monitor.is_flagged("other"));
+    }
+
+    #[test]
+    fn test_old_clicks_leave_the_window() {
+        let monitor = ClickRateMonitor::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+        monitor.record("abc", start);
+        monitor.record("abc", start + Duration::from_secs(30));
+        // The first click has slid out, so this is the second click in the window
+        assert!(!monitor.record("abc", start + Duration::from_secs(60)));
+        assert_eq!(
+            monitor.clicks_in_window("abc", start + Duration::from_secs(60)),
+            2
+        );
+        assert_eq!(
+            monitor.clicks_in_window("abc", start + Duration::from_secs(200)),
+            0
+        );
+        assert!(!monitor.is_flagged("abc"));
+    }
+
+    #[test]
+    fn test_flag_clears_once_back_under_threshold() {
+        let monitor = ClickRateMonitor::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+        monitor.record("abc", start);
+        monitor.record("abc", start + Duration::from_secs(1));
+        assert!(monitor.record("abc", start + Duration::from_secs(2)));
+        assert!(monitor.is_flagged("abc"));
+
+        // Only this click is left in the window
+        assert!(!monitor.record("abc", start + Duration::from_secs(100)));
+        assert!(!monitor.is_flagged("abc"));
+        // A new burst flags the code again
+        monitor.record("abc", start + Duration::from_secs(101));
+        assert!(monitor.record("abc", start + Duration::from_secs(102)));
+    }
+
+    #[test]
+    fn test_quiet_codes_are_pruned() {
+        let monitor = ClickRateMonitor::new(1, Duration::from_secs(60));
+        let start = Instant::now();
+        monitor.record("busy", start);
+        assert!(monitor.record("busy", start));
+        for i in 0..PRUNE_AT {
+            monitor.record(&format!("code-{i}"), start + Duration::from_secs(1));
+        }
+
+        monitor.record("late", start + Duration::from_secs(61));
+        let state = monitor.state.lock().unwrap();
+        // Every earlier click slid out of the window, so only the new code is kept
+        assert_eq!(state.clicks.len(), 1);
+        assert!(state.clicks.contains_key("late"));
+        assert!(state.flagged.is_empty());
+    }
+}
@@ -1,16 +1,68 @@
+mod click_dedup;
+mod click_rate;
+mod error;
+mod rate_limit;
 mod storage;
+use std::collections::BTreeMap;
+use std::future::IntoFuture;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-use crate::storage::{DB_URL, Url, init_db};
-use axum::{extract::{Path, Query}, http::StatusCode, response::Redirect, Extension, Json, Router};
+use crate::click_dedup::ClickDeduplicator;
+use crate::click_rate::ClickRateMonitor;
+use crate::error::AppError;
+use crate::rate_limit::RateLimiter;
+use crate::storage::{DB_URL, SortOrder, Url, UrlSortBy, init_db};
+use axum::{
+    Extension, Json, Router,
+    body::Body,
+    error_handling::HandleErrorLayer,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Redirect},
+};
 use base_62::encode;
+use futures_util::StreamExt;
+use image::{ImageFormat, Luma};
+use qrcode::QrCode;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tower::{BoxError, ServiceBuilder, limit::GlobalConcurrencyLimitLayer};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::limit::RequestBodyLimitLayer;
 use tower_http::trace::{DefaultMakeSpan, TraceLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 #[derive(Debug)]
 pub struct AppState {
     pub db_pool: sqlx::SqlitePool,
     pub root_url: String,
+    pub allowed_schemes: Vec<String>,
+    pub admin_token: Option<String>,
+    pub click_monitor: ClickRateMonitor,
+    /// Longest short code or alias that can exist; longer lookups are rejected without a query
+    pub max_code_length: usize,
+    /// Redirects enqueue clicks in `click_outbox` for a background fold instead of
+    /// updating `click_count` inline
+    pub click_outbox: bool,
+    /// Host that short links are served from; hits on any other `Host` are sent here first
+    pub canonical_host: Option<String>,
+    /// Refuse to shorten URLs that point back at this service, which would allow redirect loops
+    pub reject_self_links: bool,
+    /// Lowercased sequences that generated short codes must not contain
+    pub code_blocklist: Vec<String>,
+    /// Request header a reverse proxy puts the client's country in, recorded with each click
+    pub country_header: Option<HeaderName>,
+    /// Counts repeated hits from one client within a short window once; `None` counts every hit
+    pub click_dedup: Option<ClickDeduplicator>,
+    /// Limits `POST /create` per client IP; `None` doesn't limit
+    pub create_limiter: Option<RateLimiter>,
+    /// Reverse proxies in front of the service whose `X-Forwarded-For` entries are
+    /// believed; `0` uses the peer address
+    pub trusted_proxy_hops: usize,
 }
 
 #[tokio::main]
@@ -23,62 +75,624 @@ async fn main() {
         )
         .with(tracing_subscriber::fmt::layer())
         .init();
-    let db_pool = init_db(DB_URL).await.unwrap();
+    let bind_addr = parse_bind_addr(std::env::var("BIND_ADDR").ok().as_deref())
+        .unwrap_or_else(|e| panic!("{e}"));
+    let cors = cors_layer(std::env::var("ALLOWED_ORIGIN").ok().as_deref())
+        .unwrap_or_else(|e| panic!("{e}"));
+    let db_pool = init_db(&db_url(), &pool_config_from_env()).await.unwrap();
+    storage::set_unique_original_url(&db_pool, env_or("UNIQUE_ORIGINAL_URL", false))
+        .await
+        .expect("Failed to apply UNIQUE_ORIGINAL_URL; remove duplicate original_url rows first");
     let app_state = Arc::new(AppState {
         db_pool,
         root_url: "http://localhost:3000".into(),
+        allowed_schemes: parse_allowed_schemes(std::env::var("ALLOWED_SCHEMES").ok().as_deref()),
+        admin_token: std::env::var("ADMIN_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty()),
+        click_monitor: ClickRateMonitor::new(
+            env_or("CLICK_RATE_THRESHOLD", DEFAULT_CLICK_RATE_THRESHOLD),
+            Duration::from_secs(env_or(
+                "CLICK_RATE_WINDOW_SECS",
+                DEFAULT_CLICK_RATE_WINDOW_SECS,
+            )),
+        ),
+        max_code_length: env_or("MAX_CODE_LENGTH", DEFAULT_MAX_CODE_LENGTH),
+        click_outbox: env_or("CLICK_OUTBOX", false),
+        canonical_host: std::env::var("CANONICAL_HOST")
+            .ok()
+            .filter(|host| !host.is_empty()),
+        reject_self_links: env_or("REJECT_SELF_LINKS", false),
+        code_blocklist: parse_code_blocklist(std::env::var("CODE_BLOCKLIST").ok().as_deref()),
+        country_header: parse_country_header(std::env::var("COUNTRY_HEADER").ok().as_deref()),
+        click_dedup: match env_or("CLICK_DEDUP_WINDOW_MS", 0) {
+            0 => None,
+            window_ms => Some(ClickDeduplicator::new(Duration::from_millis(window_ms))),
+        },
+        create_limiter: match env_or("CREATE_RATE_LIMIT_PER_MINUTE", DEFAULT_CREATE_RATE_LIMIT) {
+            0 => None,
+            limit => Some(RateLimiter::new(limit, Duration::from_secs(60))),
+        },
+        trusted_proxy_hops: env_or("TRUSTED_PROXY_HOPS", 0),
     });
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    spawn_click_retention(
+        app_state.db_pool.clone(),
+        env_or("CLICK_RETENTION_DAYS", DEFAULT_CLICK_RETENTION_DAYS),
+        Duration::from_secs(env_or(
+            "CLICK_PURGE_INTERVAL_SECS",
+            DEFAULT_CLICK_PURGE_INTERVAL_SECS,
+        )),
+    );
+    if app_state.click_outbox {
+        spawn_click_outbox_folder(
+            app_state.db_pool.clone(),
+            Duration::from_secs(env_or(
+                "CLICK_OUTBOX_FOLD_INTERVAL_SECS",
+                DEFAULT_CLICK_OUTBOX_FOLD_INTERVAL_SECS,
+            )),
+        );
+    }
 
     let app = Router::new()
+        .route("/", axum::routing::get(root))
+        .route("/healthz", axum::routing::get(healthz))
+        .route("/readyz", axum::routing::get(readyz))
         .route(
-            "/",
-            axum::routing::get(|| async { "Welcome to the URL Shortener!" }),
+            "/create",
+            axum::routing::post(create_url)
+                .route_layer(axum::middleware::from_fn(limit_create_rate)),
         )
-        .route("/create", axum::routing::post(create_url))
         .route("/{short_url}", axum::routing::get(redirect))
         .route("/urls", axum::routing::get(get_urls))
+        .route(
+            "/urls/time-range",
+            axum::routing::post(get_urls_by_time_range),
+        )
+        .route("/urls/suggest-code", axum::routing::get(suggest_code))
         .route(
             "/clicks/{short_url}",
             axum::routing::get(get_url_click_count),
         )
+        .route("/clicks/batch", axum::routing::post(get_click_counts))
+        .route(
+            "/clicks/{short_url}/by-country",
+            axum::routing::get(get_clicks_by_country),
+        )
+        .route("/qr/{short_url}", axum::routing::get(get_qr_code))
+        .route(
+            "/analytics/{short_url}",
+            axum::routing::get(get_click_analytics),
+        )
+        .route(
+            "/urls/{short_url}/status",
+            axum::routing::get(get_url_status),
+        )
+        .route(
+            "/urls/{short_url}/details",
+            axum::routing::get(get_url_details),
+        )
+        .route("/urls/{short_url}/note", axum::routing::put(set_url_note))
+        .route(
+            "/urls/{short_url}/extend",
+            axum::routing::post(extend_url_expiry),
+        )
+        .route("/urls/bulk-extend", axum::routing::post(bulk_extend_expiry))
         .route("/cleanup", axum::routing::delete(cleanup_not_used_urls))
-        .fallback(|| async { (StatusCode::NOT_FOUND, "Route not found") })
-        .layer(Extension(app_state))
-        .layer(
-            TraceLayer::new_for_http()
-                // Customize the level for different events
-                .make_span_with(DefaultMakeSpan::new().level(tracing::Level::INFO))
-                .on_request(|request: &axum::extract::Request, _span: &tracing::Span| {
-                    tracing::info!(
-                        "Incoming request: {} {}",
-                        request.method(),
-                        request.uri().path()
-                    );
-                })
-                .on_response(
-                    |response: &axum::response::Response,
-                     latency: std::time::Duration,
-                     _span: &tracing::Span| {
-                        tracing::info!("Response: {} (latency: {:?})", response.status(), latency);
-                    },
-                )
-                .on_failure(
-                    |error: tower_http::classify::ServerErrorsFailureClass,
-                     latency: std::time::Duration,
-                     _span: &tracing::Span| {
-                        tracing::error!("Request failed: {:?} (latency: {:?})", error, latency);
-                    },
+        .route("/admin/export.sql", axum::routing::get(export_sql))
+        .route("/admin/vacuum", axum::routing::post(vacuum_database))
+        .route(
+            "/admin/code-capacity",
+            axum::routing::get(get_code_capacity),
+        )
+        .fallback(|| async { (StatusCode::NOT_FOUND, "Route not found") });
+    let app = limit_concurrency(
+        app,
+        env_or("MAX_CONCURRENT_REQUESTS", DEFAULT_MAX_CONCURRENT_REQUESTS),
+    )
+    .layer(RequestBodyLimitLayer::new(env_or(
+        "MAX_BODY_BYTES",
+        DEFAULT_MAX_BODY_BYTES,
+    )))
+    .layer(axum::middleware::from_fn(error::negotiate_problem_details))
+    .layer(axum::middleware::from_fn(pretty_json))
+    .layer(axum::middleware::from_fn_with_state(
+        Arc::new(security_headers(|key| std::env::var(key).ok())),
+        add_security_headers,
+    ))
+    .layer(Extension(app_state.clone()))
+    .layer(axum::middleware::from_fn_with_state(
+        in_flight.clone(),
+        track_in_flight,
+    ))
+    .layer(cors)
+    .layer(
+        TraceLayer::new_for_http()
+            // Customize the level for different events
+            .make_span_with(DefaultMakeSpan::new().level(tracing::Level::INFO))
+            .on_request(|request: &axum::extract::Request, _span: &tracing::Span| {
+                tracing::info!(
+                    "Incoming request: {} {}",
+                    request.method(),
+                    request.uri().path()
+                );
+            })
+            .on_response(
+                |response: &axum::response::Response,
+                 latency: std::time::Duration,
+                 _span: &tracing::Span| {
+                    tracing::info!("Response: {} (latency: {:?})", response.status(), latency);
+                },
+            )
+            .on_failure(
+                |error: tower_http::classify::ServerErrorsFailureClass,
+                 latency: std::time::Duration,
+                 _span: &tracing::Span| {
+                    tracing::error!("Request failed: {:?} (latency: {:?})", error, latency);
+                },
+            ),
+    );
+
+    let listener = tokio::net::TcpListener::bind(bind_addr).await.unwrap();
+
+    let grace_period = shutdown_grace_period();
+    let shutdown = Arc::new(Notify::new());
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal(shutdown.clone()))
+    .into_future();
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => result.unwrap(),
+        _ = shutdown.notified() => {
+            tracing::info!(
+                "Shutdown signal received with {} request(s) in flight, waiting up to {:?}",
+                in_flight.load(Ordering::SeqCst),
+                grace_period
+            );
+            match wait_with_grace_period(&mut server, grace_period).await {
+                Some(result) => result.unwrap(),
+                None => tracing::warn!(
+                    "Grace period elapsed with {} request(s) still in flight, forcing shutdown",
+                    in_flight.load(Ordering::SeqCst)
                 ),
-        );
+            }
+        }
+    }
+
+    app_state.db_pool.close().await;
+}
+
+/// Admin routes require `Authorization: Bearer <ADMIN_TOKEN>` and are disabled when no token is set.
+fn check_admin_token(headers: &HeaderMap, state: &AppState) -> Result<(), AppError> {
+    let Some(expected) = state.admin_token.as_deref() else {
+        return Err(AppError::Forbidden(
+            "Admin endpoints are disabled".to_string(),
+        ));
+    };
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if provided != Some(expected) {
+        return Err(AppError::Unauthorized("Invalid admin token".to_string()));
+    }
+    Ok(())
+}
+
+const DEFAULT_BIND_ADDR: &str = "0.0.0.0:3000";
+const DEFAULT_SHUTDOWN_GRACE_SECS: u64 = 30;
+const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 256;
+const DEFAULT_MAX_BODY_BYTES: usize = 64 * 1024;
+const DEFAULT_CLICK_RETENTION_DAYS: i64 = 90;
+const DEFAULT_CLICK_PURGE_INTERVAL_SECS: u64 = 3600;
+const DEFAULT_CLICK_OUTBOX_FOLD_INTERVAL_SECS: u64 = 5;
+const DEFAULT_CLICK_RATE_THRESHOLD: usize = 120;
+const DEFAULT_CLICK_RATE_WINDOW_SECS: u64 = 60;
+const DEFAULT_MAX_CODE_LENGTH: usize = 32;
+
+/// The socket address to listen on from `BIND_ADDR`, e.g. `127.0.0.1:8080`.
+fn parse_bind_addr(value: Option<&str>) -> Result<SocketAddr, String> {
+    let value = value.unwrap_or(DEFAULT_BIND_ADDR);
+    value.parse().map_err(|e| {
+        format!("Invalid BIND_ADDR '{value}', expected host:port such as {DEFAULT_BIND_ADDR}: {e}")
+    })
+}
+
+/// The database URL from `DB_URL`, falling back to the built-in SQLite file.
+fn db_url() -> String {
+    std::env::var("DB_URL").unwrap_or_else(|_| DB_URL.to_string())
+}
+
+/// Reads and parses an environment variable, falling back to `default` when unset or invalid.
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+fn shutdown_grace_period() -> Duration {
+    Duration::from_secs(env_or("SHUTDOWN_GRACE_SECS", DEFAULT_SHUTDOWN_GRACE_SECS))
+}
+
+/// Pool settings from `DB_MIN_CONNECTIONS`, `DB_IDLE_TIMEOUT_SECS` and `DB_MAX_LIFETIME_SECS`.
+fn pool_config_from_env() -> storage::PoolConfig {
+    let defaults = storage::PoolConfig::default();
+    storage::PoolConfig {
+        min_connections: env_or("DB_MIN_CONNECTIONS", defaults.min_connections),
+        idle_timeout: Duration::from_secs(env_or(
+            "DB_IDLE_TIMEOUT_SECS",
+            defaults.idle_timeout.as_secs(),
+        )),
+        max_lifetime: Duration::from_secs(env_or(
+            "DB_MAX_LIFETIME_SECS",
+            defaults.max_lifetime.as_secs(),
+        )),
+    }
+}
+
+/// Periodically purges click events past the retention window.
+fn spawn_click_retention(pool: sqlx::SqlitePool, retention_days: i64, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match storage::purge_click_events_older_than(&pool, retention_days).await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!(
+                    "Purged {} click event(s) older than {} day(s)",
+                    count,
+                    retention_days
+                ),
+                Err(e) => tracing::error!("Failed to purge click events: {}", e),
+            }
+        }
+    });
+}
+
+/// Periodically folds pending outbox clicks into `click_count`. The first tick fires
+/// immediately, so clicks left over from before a restart are counted on startup.
+fn spawn_click_outbox_folder(pool: sqlx::SqlitePool, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match storage::fold_click_outbox(&pool).await {
+                Ok(0) => {}
+                Ok(count) => tracing::debug!("Folded {} outbox click(s)", count),
+                Err(e) => tracing::error!("Failed to fold click outbox: {}", e),
+            }
+        }
+    });
+}
+
+/// CORS for browser frontends. With `ALLOWED_ORIGIN` set only that origin may call the API;
+/// without it debug builds allow any origin and release builds send no CORS headers.
+fn cors_layer(allowed_origin: Option<&str>) -> Result<CorsLayer, String> {
+    let origin = match allowed_origin {
+        Some(origin) => AllowOrigin::list([origin
+            .parse()
+            .map_err(|_| format!("Invalid ALLOWED_ORIGIN '{origin}'"))?]),
+        None if cfg!(debug_assertions) => AllowOrigin::any(),
+        None => return Ok(CorsLayer::new()),
+    };
+    Ok(CorsLayer::new()
+        .allow_origin(origin)
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::AUTHORIZATION,
+            header::ACCEPT,
+            header::IF_NONE_MATCH,
+        ])
+        .expose_headers([TOTAL_COUNT_HEADER, header::ETAG]))
+}
+
+/// Sheds requests with `503 Service Unavailable` once `max` requests are already being handled.
+fn limit_concurrency(router: Router, max: usize) -> Router {
+    router.layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(|_: BoxError| async {
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Server is busy, try again later",
+                )
+            }))
+            .load_shed()
+            .layer(GlobalConcurrencyLimitLayer::new(max)),
+    )
+}
+
+/// Resolves once Ctrl-C or SIGTERM is received, notifying `notify` so the caller can start the grace period.
+async fn shutdown_signal(notify: Arc<Notify>) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl-C handler");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    notify.notify_one();
+}
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+/// Waits for `future` for at most `grace_period`, returning `None` if it had to be abandoned.
+async fn wait_with_grace_period<F: Future>(future: F, grace_period: Duration) -> Option<F::Output> {
+    tokio::time::timeout(grace_period, future).await.ok()
+}
+
+/// Decrements the in-flight counter when a request finishes or is dropped.
+struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+async fn track_in_flight(
+    State(in_flight): State<Arc<AtomicUsize>>,
+    request: axum::extract::Request,
+    next: Next,
+) -> axum::response::Response {
+    in_flight.fetch_add(1, Ordering::SeqCst);
+    let _guard = InFlightGuard(in_flight);
+    next.run(request).await
+}
+
+#[derive(Deserialize, Debug)]
+struct PrettyQuery {
+    #[serde(default)]
+    pretty: bool,
+}
+
+/// Whether the client asked for indented JSON with `?pretty=true` or `X-Pretty: true`.
+fn wants_pretty(request: &axum::extract::Request) -> bool {
+    let header = request
+        .headers()
+        .get("x-pretty")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("true"));
+    header
+        || Query::<PrettyQuery>::try_from_uri(request.uri()).is_ok_and(|Query(query)| query.pretty)
+}
+
+/// Re-serializes JSON responses with `serde_json::to_vec_pretty` when the client asked for
+/// it; everything else, and every response by default, passes through untouched.
+async fn pretty_json(request: axum::extract::Request, next: Next) -> axum::response::Response {
+    let pretty = wants_pretty(&request);
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    if !pretty || !is_json {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let body = match serde_json::from_slice::<serde_json::Value>(&bytes)
+        .and_then(|value| serde_json::to_vec_pretty(&value))
+    {
+        Ok(pretty) => axum::body::Body::from(pretty),
+        Err(_) => axum::body::Body::from(bytes),
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    axum::response::Response::from_parts(parts, body)
+}
+
+/// Headers added to every response. `X-Content-Type-Options` and `X-Frame-Options` default
+/// to `nosniff` and `DENY`; `Content-Security-Policy` is only sent when configured. Setting
+/// a variable to an empty string drops that header.
+fn security_headers(env: impl Fn(&str) -> Option<String>) -> Vec<(HeaderName, HeaderValue)> {
+    [
+        (
+            header::X_CONTENT_TYPE_OPTIONS,
+            "X_CONTENT_TYPE_OPTIONS",
+            Some("nosniff"),
+        ),
+        (header::X_FRAME_OPTIONS, "X_FRAME_OPTIONS", Some("DENY")),
+        (
+            header::CONTENT_SECURITY_POLICY,
+            "CONTENT_SECURITY_POLICY",
+            None,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(name, key, default)| {
+        let value = env(key)
+            .or(default.map(String::from))
+            .filter(|value| !value.is_empty())?;
+        match HeaderValue::from_str(&value) {
+            Ok(value) => Some((name, value)),
+            Err(_) => {
+                tracing::warn!("Ignoring invalid {} header value", key);
+                None
+            }
+        }
+    })
+    .collect()
+}
+
+async fn add_security_headers(
+    State(headers): State<Arc<Vec<(HeaderName, HeaderValue)>>>,
+    request: axum::extract::Request,
+    next: Next,
+) -> axum::response::Response {
+    let mut response = next.run(request).await;
+    for (name, value) in headers.iter() {
+        response
+            .headers_mut()
+            .entry(name.clone())
+            .or_insert_with(|| value.clone());
+    }
+    response
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ServiceInfo {
+    service: &'static str,
+    version: &'static str,
+    status: &'static str,
+}
+
+/// Returns service metadata to clients that ask for JSON and a plain greeting otherwise.
+async fn root(headers: HeaderMap) -> axum::response::Response {
+    let wants_json = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("application/json"));
+    if wants_json {
+        Json(ServiceInfo {
+            service: "urlshortener",
+            version: env!("CARGO_PKG_VERSION"),
+            status: "ok",
+        })
+        .into_response()
+    } else {
+        "Welcome to the URL Shortener!".into_response()
+    }
+}
+
+/// Liveness probe: answering at all means the process is up.
+async fn healthz() -> StatusCode {
+    StatusCode::OK
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Readiness {
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+/// Readiness probe: `503` with a reason until the database answers and every migration is applied.
+async fn readyz(Extension(state): Extension<Arc<AppState>>) -> (StatusCode, Json<Readiness>) {
+    let reason = match storage::pending_migrations(&state.db_pool).await {
+        Ok(pending) if pending.is_empty() => None,
+        Ok(pending) => Some(format!("Pending migrations: {pending:?}")),
+        Err(e) => Some(format!("Database unavailable: {e}")),
+    };
+    match reason {
+        None => (
+            StatusCode::OK,
+            Json(Readiness {
+                status: "ready".to_string(),
+                reason: None,
+            }),
+        ),
+        Some(reason) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(Readiness {
+                status: "not_ready".to_string(),
+                reason: Some(reason),
+            }),
+        ),
+    }
+}
+
+/// What a create endpoint responds with, chosen by `?return=full|minimal`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ReturnMode {
+    /// The whole created record
+    Full,
+    /// Only the new record's short code, as plain text
+    Minimal,
+}
 
-    axum::serve(listener, app).await.unwrap();
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct CreateQuery {
+    #[serde(rename = "return")]
+    return_mode: Option<ReturnMode>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CreateURLBody {
     pub original_url: String,
+    pub note: Option<String>,
+    /// Custom short code to use instead of the generated one
+    pub alias: Option<String>,
+    /// Days until the link stops redirecting; `None` never expires
+    pub expires_in_days: Option<i64>,
+    /// Always mint a new code, even if the URL was shortened before
+    pub force_new: Option<bool>,
+}
+
+const MIN_ALIAS_LENGTH: usize = 3;
+const MAX_ALIAS_LENGTH: usize = 32;
+
+/// First path segments of the service's own routes. A short code equal to one of these
+/// would be shadowed by the route and never redirect.
+const RESERVED_CODES: &[&str] = &[
+    "admin",
+    "analytics",
+    "cleanup",
+    "clicks",
+    "create",
+    "healthz",
+    "qr",
+    "readyz",
+    "urls",
+];
+
+/// Whether `code` is a route name from [`RESERVED_CODES`], ignoring case.
+fn is_reserved_code(code: &str) -> bool {
+    RESERVED_CODES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(code))
+}
+
+/// Checks a requested alias against `^[A-Za-z0-9_-]{3,32}$`. Aliases longer than
+/// `max_code_length` are refused too, since the redirect would never look them up, and
+/// so are route names.
+fn validate_alias(alias: &str, max_code_length: usize) -> Result<(), String> {
+    let max_length = MAX_ALIAS_LENGTH.min(max_code_length);
+    if !(MIN_ALIAS_LENGTH..=max_length).contains(&alias.len()) {
+        return Err(format!(
+            "Alias must be {MIN_ALIAS_LENGTH} to {max_length} characters long"
+        ));
+    }
+    if !alias
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err("Alias may only contain letters, digits, '_' and '-'".to_string());
+    }
+    if is_reserved_code(alias) {
+        return Err(format!("Alias '{alias}' is reserved"));
+    }
+    Ok(())
+}
+
+const MAX_BATCH_CODES: usize = 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ClickBatchBody {
+    codes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct NoteBody {
+    note: Option<String>,
 }
 
 fn id_to_base62(id: i64) -> String {
@@ -86,99 +700,2582 @@ fn id_to_base62(id: i64) -> String {
     encode(&bytes)
 }
 
-fn validate_url(url: &str) -> Result<(), String> {
+/// `id_to_base62` encodes a `0x01` byte followed by the 8 id bytes, i.e. the number 2^64 + id.
+const CODE_OFFSET: u128 = 1 << 64;
+
+/// How many positive ids get a code of at most `length` characters.
+fn ids_with_code_length_at_most(length: u32) -> u64 {
+    // id + 2^64 must stay below 62^length
+    let limit = 62u128.pow(length).saturating_sub(CODE_OFFSET);
+    limit.saturating_sub(1).min(i64::MAX as u128) as u64
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct CodeCapacity {
+    max_id: i64,
+    /// Length of the newest code
+    code_length: usize,
+    /// Ids whose code has exactly `code_length` characters
+    codes_at_length: u64,
+    /// Ids above `max_id` that still get a `code_length` code
+    codes_remaining: u64,
+    /// Shortest length that leaves room for the table to double
+    recommended_length: usize,
+}
+
+fn code_capacity(max_id: i64) -> CodeCapacity {
+    let code_length = id_to_base62(max_id.max(1)).len();
+    let at_most = ids_with_code_length_at_most(code_length as u32);
+    let shorter = ids_with_code_length_at_most(code_length as u32 - 1);
+    let needed = (max_id.max(1) as u64).saturating_mul(2);
+    let recommended_length = (1..=code_length as u32)
+        .find(|&length| ids_with_code_length_at_most(length) >= needed)
+        .unwrap_or(code_length as u32) as usize;
+    CodeCapacity {
+        max_id,
+        code_length,
+        codes_at_length: at_most - shorter,
+        codes_remaining: at_most.saturating_sub(max_id as u64),
+        recommended_length,
+    }
+}
+
+/// Offset between successive code candidates for one id. Far above any real id, so a
+/// fallback candidate never collides with the natural code of a later row.
+const CODE_CANDIDATE_STRIDE: i64 = 1 << 40;
+const MAX_CODE_CANDIDATES: i64 = 16;
+
+fn parse_code_blocklist(raw: Option<&str>) -> Vec<String> {
+    raw.unwrap_or_default()
+        .split(',')
+        .map(|term| term.trim().to_lowercase())
+        .filter(|term| !term.is_empty())
+        .collect()
+}
+
+/// Whether `code` contains a blocked sequence, ignoring case.
+fn is_blocked_code(code: &str, blocklist: &[String]) -> bool {
+    let code = code.to_lowercase();
+    blocklist.iter().any(|term| code.contains(term.as_str()))
+}
+
+/// The base-62 codes for `id` in order of preference, leaving out codes that contain a
+/// blocked sequence or are reserved. `storage::create_url` takes the first one not already
+/// stored, which an alias may have claimed.
+fn generate_codes(id: i64, blocklist: &[String]) -> Vec<String> {
+    let usable = |code: &str| !is_reserved_code(code) && !is_blocked_code(code, blocklist);
+    let codes: Vec<String> = (0..MAX_CODE_CANDIDATES)
+        .map(|n| id_to_base62(id + n * CODE_CANDIDATE_STRIDE))
+        .filter(|code| usable(code))
+        .collect();
+    if codes.is_empty() {
+        let natural = id_to_base62(id);
+        tracing::warn!(
+            "Every candidate code for id {} is blocked, using {}",
+            id,
+            natural
+        );
+        return vec![natural];
+    }
+    codes
+}
+
+const DEFAULT_ALLOWED_SCHEMES: &str = "http,https";
+
+/// Parses a comma-separated scheme list such as `ALLOWED_SCHEMES`, falling back to http/https.
+fn parse_allowed_schemes(raw: Option<&str>) -> Vec<String> {
+    let schemes: Vec<String> = raw
+        .unwrap_or(DEFAULT_ALLOWED_SCHEMES)
+        .split(',')
+        .map(|scheme| scheme.trim().to_ascii_lowercase())
+        .filter(|scheme| !scheme.is_empty())
+        .collect();
+    if schemes.is_empty() {
+        return parse_allowed_schemes(None);
+    }
+    schemes
+}
+
+fn validate_url(url: &str, allowed_schemes: &[String]) -> Result<(), String> {
     if url.is_empty() {
         return Err("URL cannot be empty".to_string());
     }
-    if !url.starts_with("http://") && !url.starts_with("https://") {
-        return Err("URL must start with http:// or https://".to_string());
+    let not_allowed = || format!("URL scheme must be one of: {}", allowed_schemes.join(", "));
+    let (scheme, rest) = url.split_once(':').ok_or_else(not_allowed)?;
+    let scheme = scheme.to_ascii_lowercase();
+    if rest.is_empty() || !allowed_schemes.contains(&scheme) {
+        return Err(not_allowed());
+    }
+    if (scheme == "http" || scheme == "https") && !rest.starts_with("//") {
+        return Err(format!("URL must start with {scheme}://"));
     }
     Ok(())
 }
 
+/// Lowercased host name of an authority such as `user@Example.com:8080`, without the port.
+fn host_name(authority: &str) -> String {
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Host name of a `scheme://authority/...` URL, if it has one.
+fn url_host(url: &str) -> Option<String> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    Some(host_name(authority)).filter(|host| !host.is_empty())
+}
+
+/// Whether `url` points at this service, judged by `root_url`, `CANONICAL_HOST` and the
+/// `Host` the request arrived on. Ports are ignored, so other ports on the same host count.
+fn is_self_link(url: &str, headers: &HeaderMap, state: &AppState) -> bool {
+    let Some(target) = url_host(url) else {
+        return false;
+    };
+    let request_host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .map(host_name);
+    url_host(&state.root_url)
+        .into_iter()
+        .chain(state.canonical_host.as_deref().map(host_name))
+        .chain(request_host)
+        .any(|own| own == target)
+}
+
+const DEFAULT_CREATE_RATE_LIMIT: u32 = 60;
+
+/// Rejects `POST /create` with `429 Too Many Requests` once the client has used up its
+/// `CREATE_RATE_LIMIT_PER_MINUTE`.
+async fn limit_create_rate(
+    Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: axum::extract::Request,
+    next: Next,
+) -> Result<axum::response::Response, AppError> {
+    if let Some(limiter) = &state.create_limiter {
+        let client = client_ip(request.headers(), peer, state.trusted_proxy_hops);
+        if let Err(retry_after) = limiter.check(client, Instant::now()) {
+            tracing::warn!("Rate limited URL creation from {client}");
+            return Err(AppError::RateLimited {
+                retry_after_seconds: retry_after.as_secs_f64().ceil() as u64,
+            });
+        }
+    }
+    Ok(next.run(request).await)
+}
+
 async fn create_url(
     Extension(state): Extension<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<CreateQuery>,
     Json(body): Json<CreateURLBody>,
-) -> Result<String, (StatusCode, String)> {
-    match validate_url(&body.original_url) {
-        Ok(_) => (),
-        Err(e) => return Err((StatusCode::BAD_REQUEST, e)),
+) -> Result<axum::response::Response, AppError> {
+    validate_url(&body.original_url, &state.allowed_schemes).map_err(AppError::Validation)?;
+    if state.reject_self_links && is_self_link(&body.original_url, &headers, &state) {
+        return Err(AppError::Validation(
+            "URL points back to this shortener".to_string(),
+        ));
+    }
+
+    if let Some(alias) = &body.alias {
+        validate_alias(alias, state.max_code_length).map_err(AppError::Validation)?;
+        if is_blocked_code(alias, &state.code_blocklist) {
+            return Err(AppError::Conflict("Alias is not allowed".to_string()));
+        }
+        match storage::get_url_by_short(&state.db_pool, alias).await {
+            Ok(None) => (),
+            Ok(Some(_)) => return Err(AppError::Conflict("Alias already taken".to_string())),
+            Err(e) => return Err(AppError::Database(e)),
+        }
     }
 
-    let url = storage::create_url(&state.db_pool, body.original_url.clone()).await;
+    let expires_at = match body.expires_in_days {
+        Some(days) if !(1..=MAX_EXTEND_DAYS).contains(&days) => {
+            return Err(AppError::BadRequest(format!(
+                "expires_in_days must be between 1 and {MAX_EXTEND_DAYS}"
+            )));
+        }
+        Some(days) => Some(chrono::Utc::now().naive_utc() + chrono::Duration::days(days)),
+        None => None,
+    };
 
-    match url {
-        Ok(url) => {
-            let short_url = id_to_base62(url.id.unwrap_or(0));
-            match storage::update_short_url(&state.db_pool, url.id.unwrap_or(0), &short_url).await {
-                Ok(_) => Ok(short_url),
-                Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    // Shortening a known URL again hands back its live code instead of a duplicate row.
+    // Aliases and expiries ask for a specific new link, so they always get one.
+    let reuse_existing =
+        body.alias.is_none() && expires_at.is_none() && !body.force_new.unwrap_or(false);
+    let now = chrono::Utc::now().naive_utc();
+    if reuse_existing {
+        match storage::get_url_by_long(&state.db_pool, &body.original_url, now).await {
+            Ok(Some(existing)) => {
+                return Ok(create_response(existing, query.return_mode, false));
+            }
+            Ok(None) => (),
+            Err(e) => return Err(AppError::Database(e)),
+        }
+    }
+
+    let has_alias = body.alias.is_some();
+    let url = storage::create_url(
+        &state.db_pool,
+        body.original_url.clone(),
+        body.note,
+        expires_at,
+        |id| match body.alias {
+            Some(alias) => vec![alias],
+            None => generate_codes(id, &state.code_blocklist),
+        },
+    )
+    .await;
+
+    let (url, created) = match url {
+        Ok(url) => (url, true),
+        // Another create claimed the alias after the check above
+        Err(sqlx::Error::Database(e))
+            if e.is_unique_violation() && e.message().contains("url.short_url") =>
+        {
+            if has_alias {
+                return Err(AppError::Conflict("Alias already taken".to_string()));
+            }
+            // Every candidate code for the new id was already stored
+            return Err(AppError::Internal(format!(
+                "Generated code is already in use: {e}"
+            )));
+        }
+        // With UNIQUE_ORIGINAL_URL a concurrent create of the same URL won the race,
+        // so hand back its mapping instead of failing
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            match storage::get_url_by_long(&state.db_pool, &body.original_url, now).await {
+                Ok(Some(existing)) => (existing, false),
+                Ok(None) => return Err(AppError::Conflict(e.to_string())),
+                Err(e) => return Err(AppError::Database(e)),
             }
         }
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Err(e) => return Err(AppError::Database(e)),
+    };
+    Ok(create_response(url, query.return_mode, created))
+}
+
+/// `201 Created` for a new mapping, `200 OK` when an existing one is handed back, with
+/// `Location` pointing at the short link either way.
+fn create_response(
+    url: Url,
+    return_mode: Option<ReturnMode>,
+    created: bool,
+) -> axum::response::Response {
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    let location = format!("/{}", url.short_url);
+    let body = match return_mode.unwrap_or(ReturnMode::Minimal) {
+        ReturnMode::Minimal => url.short_url.into_response(),
+        ReturnMode::Full => Json(url).into_response(),
+    };
+    (status, [(header::LOCATION, location)], body).into_response()
+}
+
+/// The configured country header. Capture is off unless one is named, since an unproxied
+/// client could otherwise set it to anything.
+fn parse_country_header(raw: Option<&str>) -> Option<HeaderName> {
+    match raw.map(str::trim) {
+        None | Some("") => None,
+        Some(name) => match HeaderName::try_from(name) {
+            Ok(name) => Some(name),
+            Err(_) => {
+                tracing::warn!("Ignoring invalid COUNTRY_HEADER {:?}", name);
+                None
+            }
+        },
+    }
+}
+
+/// The client's country code from `country_header`, uppercased. Missing or implausible
+/// values give `None` rather than failing the redirect.
+fn click_country(headers: &HeaderMap, country_header: Option<&HeaderName>) -> Option<String> {
+    let value = headers.get(country_header?)?.to_str().ok()?.trim();
+    let plausible =
+        !value.is_empty() && value.len() <= 8 && value.chars().all(|c| c.is_ascii_alphanumeric());
+    plausible.then(|| value.to_ascii_uppercase())
+}
+
+/// The client's address for click deduplication and rate limiting. Behind
+/// `trusted_hops` proxies it's the `X-Forwarded-For` entry the outermost one appended;
+/// entries left of it come from the client and may be forged. Otherwise, or when the
+/// header is too short, it's the peer address.
+fn client_ip(headers: &HeaderMap, peer: SocketAddr, trusted_hops: usize) -> IpAddr {
+    if trusted_hops == 0 {
+        return peer.ip();
+    }
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    forwarded
+        .len()
+        .checked_sub(trusted_hops)
+        .and_then(|index| forwarded[index].parse().ok())
+        .unwrap_or(peer.ip())
+}
+
+/// A `301` to the same short link on `canonical_host` when the request arrived on another
+/// host. The scheme follows `X-Forwarded-Proto` so TLS-terminating proxies keep working.
+fn canonical_host_redirect(
+    headers: &HeaderMap,
+    canonical_host: &str,
+    short_url: &str,
+) -> Option<axum::response::Response> {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok());
+    if host.is_some_and(|host| host.eq_ignore_ascii_case(canonical_host)) {
+        return None;
     }
+    let scheme = headers
+        .get("x-forwarded-proto")
+        .and_then(|proto| proto.to_str().ok())
+        .unwrap_or("http");
+    let location = format!("{scheme}://{canonical_host}/{short_url}");
+    Some(
+        (
+            StatusCode::MOVED_PERMANENTLY,
+            [(header::LOCATION, location)],
+        )
+            .into_response(),
+    )
 }
 
 async fn redirect(
     Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Path(short_url): Path<String>,
-) -> Result<Redirect, (StatusCode, String)> {
+) -> Result<axum::response::Response, AppError> {
+    if let Some(response) = state
+        .canonical_host
+        .as_deref()
+        .and_then(|canonical_host| canonical_host_redirect(&headers, canonical_host, &short_url))
+    {
+        return Ok(response);
+    }
+    // Scanners probe long junk paths; no stored code can match, so skip the query
+    if short_url.len() > state.max_code_length {
+        return Err(AppError::NotFound("URL not found".to_string()));
+    }
     let url = storage::get_url_by_short(&state.db_pool, &short_url).await;
 
     match url {
-        Ok(Some(url)) => match storage::increment_click_count(&state.db_pool, &short_url).await {
-            Ok(_) => Ok(Redirect::temporary(&url.original_url)),
-            Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
-        },
-        Ok(None) => Err((StatusCode::NOT_FOUND, "URL not found".to_string())),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Ok(Some(url)) => {
+            let now = chrono::Utc::now().naive_utc();
+            // Expired links are not clicks
+            if url.expires_at.is_some_and(|expires_at| expires_at <= now) {
+                return Err(AppError::Gone("URL has expired".to_string()));
+            }
+            let duplicate = state.click_dedup.as_ref().is_some_and(|dedup| {
+                dedup.is_duplicate(
+                    &short_url,
+                    client_ip(&headers, peer, state.trusted_proxy_hops),
+                    Instant::now(),
+                )
+            });
+            if duplicate {
+                return Ok(Redirect::temporary(&url.original_url).into_response());
+            }
+            let url_id = url.id.unwrap_or(0);
+            let counted = if state.click_outbox {
+                storage::enqueue_click(&state.db_pool, url_id, now).await
+            } else {
+                storage::increment_click_count(&state.db_pool, &short_url)
+                    .await
+                    .map(|_| ())
+            };
+            let counted = match counted {
+                Ok(()) => {
+                    let country = click_country(&headers, state.country_header.as_ref());
+                    storage::record_click(&state.db_pool, url_id, now, country.as_deref()).await
+                }
+                Err(e) => Err(e),
+            };
+            if state.click_monitor.record(&short_url, Instant::now()) {
+                tracing::warn!(
+                    "Short URL {} flagged: more than {} clicks within the rate window",
+                    short_url,
+                    state.click_monitor.threshold()
+                );
+            }
+            match counted {
+                Ok(_) => Ok(Redirect::temporary(&url.original_url).into_response()),
+                Err(e) => Err(e.into()),
+            }
+        }
+        Ok(None) => Err(AppError::NotFound("URL not found".to_string())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Page size of the listing endpoints, `GET /urls` and `POST /urls/time-range`.
+const DEFAULT_PAGE_LIMIT: i64 = 50;
+const MAX_PAGE_LIMIT: i64 = 500;
+
+fn default_page_limit() -> i64 {
+    DEFAULT_PAGE_LIMIT
+}
+
+fn validate_page(limit: i64, offset: i64) -> Result<(), AppError> {
+    if !(1..=MAX_PAGE_LIMIT).contains(&limit) || offset < 0 {
+        return Err(AppError::BadRequest(format!(
+            "limit must be between 1 and {MAX_PAGE_LIMIT} and offset not negative"
+        )));
     }
+    Ok(())
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct GetURLQuery {
+    #[serde(default = "default_page_limit")]
     limit: i64,
+    #[serde(default)]
     offset: i64,
+    #[serde(default)]
+    sort_by: UrlSortBy,
+    #[serde(default)]
+    order: SortOrder,
 }
 
+const TOTAL_COUNT_HEADER: HeaderName = HeaderName::from_static("x-total-count");
+
 async fn get_urls(
     Extension(state): Extension<Arc<AppState>>,
     Query(query): Query<GetURLQuery>,
-) -> Result<Json<Vec<Url>>, (StatusCode, String)> {
-    let urls = storage::get_urls(&state.db_pool, query.limit, query.offset).await;
+) -> Result<([(HeaderName, String); 1], Json<Vec<Url>>), AppError> {
+    validate_page(query.limit, query.offset)?;
+    let total = storage::count_urls(&state.db_pool).await?;
+    let urls = storage::get_urls_sorted(
+        &state.db_pool,
+        query.sort_by,
+        query.order,
+        query.limit,
+        query.offset,
+    )
+    .await?;
+    Ok(([(TOTAL_COUNT_HEADER, total.to_string())], Json(urls)))
+}
+
+/// RFC 3339 bounds of `POST /urls/time-range`, e.g. `2026-03-01T00:00:00Z`.
+#[derive(Serialize, Deserialize, Debug)]
+struct UrlTimeRange {
+    start: String,
+    end: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TimeRangePage {
+    #[serde(default = "default_page_limit")]
+    limit: i64,
+    #[serde(default)]
+    offset: i64,
+}
+
+fn parse_rfc3339(value: &str, name: &str) -> Result<chrono::NaiveDateTime, AppError> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|time| time.naive_utc())
+        .map_err(|_| AppError::BadRequest(format!("Invalid {name} time, expected RFC 3339")))
+}
+
+/// URLs created between `start` and `end` (both inclusive), oldest first, paged by
+/// `?limit=` and `?offset=` like `GET /urls`.
+async fn get_urls_by_time_range(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(page): Query<TimeRangePage>,
+    Json(range): Json<UrlTimeRange>,
+) -> Result<Json<Vec<Url>>, AppError> {
+    let start = parse_rfc3339(&range.start, "start")?;
+    let end = parse_rfc3339(&range.end, "end")?;
+    if start > end {
+        return Err(AppError::BadRequest(
+            "start must not be after end".to_string(),
+        ));
+    }
+    validate_page(page.limit, page.offset)?;
+    let urls = storage::get_urls_by_time_range(&state.db_pool, start, end, page.limit, page.offset)
+        .await?;
+    Ok(Json(urls))
+}
+
+/// Length of suggested aliases. Generated codes encode 2^64 + id and so are at least 11
+/// characters, so a suggestion can't be taken by a later generated code.
+const SUGGESTED_CODE_LENGTH: usize = 7;
+const MAX_SUGGEST_ATTEMPTS: usize = 10;
+
+const CODE_ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+fn random_code(length: usize) -> String {
+    let mut rng = rand::rng();
+    (0..length)
+        .map(|_| CODE_ALPHABET[rng.random_range(0..CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SuggestedCode {
+    code: String,
+}
 
-    match urls {
-        Ok(urls) => Ok(Json(urls)),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+/// A random alias that no stored url uses, isn't a route name and `CODE_BLOCKLIST`
+/// allows, for clients to pre-fill. It isn't held for the caller, so creating with it
+/// can still conflict.
+async fn suggest_code(
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<SuggestedCode>, AppError> {
+    let length = SUGGESTED_CODE_LENGTH.min(state.max_code_length);
+    for _ in 0..MAX_SUGGEST_ATTEMPTS {
+        let code = random_code(length);
+        if is_reserved_code(&code) || is_blocked_code(&code, &state.code_blocklist) {
+            continue;
+        }
+        if storage::get_url_by_short(&state.db_pool, &code)
+            .await?
+            .is_none()
+        {
+            return Ok(Json(SuggestedCode { code }));
+        }
     }
+    Err(AppError::Conflict(
+        "Could not find an unused code, try again".to_string(),
+    ))
 }
 
 async fn get_url_click_count(
     Extension(state): Extension<Arc<AppState>>,
     Path(short_url): Path<String>,
-) -> Result<Json<i64>, (StatusCode, String)> {
+) -> Result<Json<i64>, AppError> {
     let url = storage::get_url_by_short(&state.db_pool, &short_url).await;
 
     match url {
         Ok(Some(url)) => Ok(Json(url.click_count)),
-        Ok(None) => Err((StatusCode::NOT_FOUND, "URL not found".to_string())),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Ok(None) => Err(AppError::NotFound("URL not found".to_string())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn get_clicks_by_country(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(short_url): Path<String>,
+) -> Result<Json<Vec<storage::CountryClicks>>, AppError> {
+    let url = storage::get_url_by_short(&state.db_pool, &short_url).await;
+
+    match url {
+        Ok(Some(url)) => {
+            let clicks =
+                storage::get_clicks_by_country(&state.db_pool, url.id.unwrap_or(0)).await?;
+            Ok(Json(clicks))
+        }
+        Ok(None) => Err(AppError::NotFound("URL not found".to_string())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+const DEFAULT_ANALYTICS_DAYS: i64 = 30;
+const MAX_ANALYTICS_DAYS: i64 = 366;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct AnalyticsQuery {
+    from: Option<chrono::NaiveDate>,
+    to: Option<chrono::NaiveDate>,
+}
+
+/// Daily click counts from `from` through `to` (UTC days, both inclusive), with a zero
+/// entry for every day without clicks. Defaults to the last 30 days.
+async fn get_click_analytics(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(short_url): Path<String>,
+    Query(query): Query<AnalyticsQuery>,
+) -> Result<Json<Vec<storage::DayClicks>>, AppError> {
+    let to = query.to.unwrap_or_else(|| chrono::Utc::now().date_naive());
+    let from = query
+        .from
+        .unwrap_or(to - chrono::Duration::days(DEFAULT_ANALYTICS_DAYS - 1));
+    let days = (to - from).num_days() + 1;
+    if !(1..=MAX_ANALYTICS_DAYS).contains(&days) {
+        return Err(AppError::BadRequest(format!(
+            "from must not be after to, and the range may span at most {MAX_ANALYTICS_DAYS} days"
+        )));
+    }
+    let url = match storage::get_url_by_short(&state.db_pool, &short_url).await {
+        Ok(Some(url)) => url,
+        Ok(None) => return Err(AppError::NotFound("URL not found".to_string())),
+        Err(e) => return Err(AppError::Database(e)),
+    };
+
+    let counted = storage::get_clicks_by_day(&state.db_pool, url.id.unwrap_or(0), from, to).await?;
+    let counted: BTreeMap<_, _> = counted
+        .into_iter()
+        .map(|day| (day.day, day.clicks))
+        .collect();
+    let buckets = from
+        .iter_days()
+        .take(days as usize)
+        .map(|day| storage::DayClicks {
+            day,
+            clicks: counted.get(&day).copied().unwrap_or(0),
+        })
+        .collect();
+    Ok(Json(buckets))
+}
+
+/// Click counts for a list of codes, with `null` for codes that don't exist.
+async fn get_click_counts(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(body): Json<ClickBatchBody>,
+) -> Result<Json<BTreeMap<String, Option<i64>>>, AppError> {
+    if body.codes.len() > MAX_BATCH_CODES {
+        return Err(AppError::BadRequest(format!(
+            "At most {MAX_BATCH_CODES} codes can be looked up at once"
+        )));
     }
+    let counts = storage::get_click_counts(&state.db_pool, &body.codes).await?;
+    Ok(Json(
+        body.codes
+            .into_iter()
+            .map(|code| {
+                let count = counts.get(&code).copied();
+                (code, count)
+            })
+            .collect(),
+    ))
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct CleanupQuery {
-    days: i64,
+struct UrlStatus {
+    short_url: String,
+    note: Option<String>,
+    flagged: bool,
+    clicks_in_window: usize,
 }
 
-async fn cleanup_not_used_urls(
+async fn get_url_status(
     Extension(state): Extension<Arc<AppState>>,
-    Query(query): Query<CleanupQuery>,
-) -> Result<Json<u64>, (StatusCode, String)> {
-    let result = storage::cleanup_not_used_urls(&state.db_pool, query.days).await;
+    Path(short_url): Path<String>,
+) -> Result<Json<UrlStatus>, AppError> {
+    let url = storage::get_url_by_short(&state.db_pool, &short_url).await;
+
+    match url {
+        Ok(Some(url)) => Ok(Json(UrlStatus {
+            flagged: state.click_monitor.is_flagged(&url.short_url),
+            clicks_in_window: state
+                .click_monitor
+                .clicks_in_window(&url.short_url, Instant::now()),
+            short_url: url.short_url,
+            note: url.note,
+        })),
+        Ok(None) => Err(AppError::NotFound("URL not found".to_string())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+const RECENT_CLICKS_LIMIT: i64 = 20;
+
+/// Everything known about one link, for auditing.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct UrlDetails {
+    #[serde(flatten)]
+    url: Url,
+    /// Whether `expires_at` has passed, so redirects answer `410 Gone`
+    expired: bool,
+    flagged: bool,
+    clicks_in_window: usize,
+    /// Click events still kept in the `clicks` table; older ones are purged after
+    /// `CLICK_RETENTION_DAYS`, so this can be below `click_count`
+    recorded_clicks: i64,
+    clicks_by_country: Vec<storage::CountryClicks>,
+    /// The newest recorded click events, newest first
+    recent_clicks: Vec<storage::ClickEvent>,
+}
+
+async fn get_url_details(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(short_url): Path<String>,
+) -> Result<Json<UrlDetails>, AppError> {
+    let Some(url) = storage::get_url_by_short(&state.db_pool, &short_url).await? else {
+        return Err(AppError::NotFound("URL not found".to_string()));
+    };
+    let url_id = url.id.unwrap_or_default();
+    let clicks_by_country = storage::get_clicks_by_country(&state.db_pool, url_id).await?;
+    let recent_clicks =
+        storage::get_recent_clicks(&state.db_pool, url_id, RECENT_CLICKS_LIMIT).await?;
+    let now = chrono::Utc::now().naive_utc();
+    Ok(Json(UrlDetails {
+        expired: url.expires_at.is_some_and(|expires_at| expires_at <= now),
+        flagged: state.click_monitor.is_flagged(&url.short_url),
+        clicks_in_window: state
+            .click_monitor
+            .clicks_in_window(&url.short_url, Instant::now()),
+        recorded_clicks: clicks_by_country.iter().map(|country| country.clicks).sum(),
+        clicks_by_country,
+        recent_clicks,
+        url,
+    }))
+}
+
+const DEFAULT_QR_SIZE: u32 = 256;
+const MIN_QR_SIZE: u32 = 64;
+const MAX_QR_SIZE: u32 = 1024;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct QrQuery {
+    size: Option<u32>,
+}
+
+/// Renders `data` as a PNG QR code no larger than `size` pixels square, quiet zone included.
+fn render_qr_png(data: &str, size: u32) -> Result<Vec<u8>, String> {
+    let code = QrCode::new(data.as_bytes()).map_err(|e| e.to_string())?;
+    let image = code.render::<Luma<u8>>().max_dimensions(size, size).build();
+    let mut png = Vec::new();
+    image
+        .write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| e.to_string())?;
+    Ok(png)
+}
+
+async fn get_qr_code(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(short_url): Path<String>,
+    Query(query): Query<QrQuery>,
+) -> Result<axum::response::Response, AppError> {
+    let size = query.size.unwrap_or(DEFAULT_QR_SIZE);
+    if !(MIN_QR_SIZE..=MAX_QR_SIZE).contains(&size) {
+        return Err(AppError::BadRequest(format!(
+            "size must be between {MIN_QR_SIZE} and {MAX_QR_SIZE}"
+        )));
+    }
+    let url = match storage::get_url_by_short(&state.db_pool, &short_url).await {
+        Ok(Some(url)) => url,
+        Ok(None) => return Err(AppError::NotFound("URL not found".to_string())),
+        Err(e) => return Err(AppError::Database(e)),
+    };
+
+    let link = format!("{}/{}", state.root_url.trim_end_matches('/'), url.short_url);
+    match render_qr_png(&link, size) {
+        Ok(png) => Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response()),
+        Err(e) => Err(AppError::Internal(e)),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CleanupQuery {
+    days: i64,
+}
+
+async fn set_url_note(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(short_url): Path<String>,
+    Json(body): Json<NoteBody>,
+) -> Result<Json<Url>, AppError> {
+    let url = storage::set_note(&state.db_pool, &short_url, body.note).await;
+
+    match url {
+        Ok(Some(url)) => Ok(Json(url)),
+        Ok(None) => Err(AppError::NotFound("URL not found".to_string())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+const MAX_EXTEND_DAYS: i64 = 3650;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ExtendBody {
+    days: i64,
+}
+
+async fn extend_url_expiry(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(short_url): Path<String>,
+    Json(body): Json<ExtendBody>,
+) -> Result<Json<Url>, AppError> {
+    if !(1..=MAX_EXTEND_DAYS).contains(&body.days) {
+        return Err(AppError::BadRequest(format!(
+            "days must be between 1 and {MAX_EXTEND_DAYS}"
+        )));
+    }
+    let now = chrono::Utc::now().naive_utc();
+    let url = storage::extend_expiry(&state.db_pool, &short_url, body.days, now).await;
+
+    match url {
+        Ok(Some(url)) => Ok(Json(url)),
+        Ok(None) => Err(AppError::NotFound("URL not found".to_string())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BulkExtendBody {
+    codes: Vec<String>,
+    days: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BulkExtendResponse {
+    updated: u64,
+}
+
+async fn bulk_extend_expiry(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(body): Json<BulkExtendBody>,
+) -> Result<Json<BulkExtendResponse>, AppError> {
+    if !(1..=MAX_EXTEND_DAYS).contains(&body.days) {
+        return Err(AppError::BadRequest(format!(
+            "days must be between 1 and {MAX_EXTEND_DAYS}"
+        )));
+    }
+    if body.codes.len() > MAX_BATCH_CODES {
+        return Err(AppError::BadRequest(format!(
+            "At most {MAX_BATCH_CODES} codes can be extended at once"
+        )));
+    }
+    let now = chrono::Utc::now().naive_utc();
+    let updated = storage::extend_expiry_bulk(&state.db_pool, &body.codes, body.days, now).await?;
+    Ok(Json(BulkExtendResponse { updated }))
+}
+
+async fn cleanup_not_used_urls(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(query): Query<CleanupQuery>,
+) -> Result<Json<u64>, AppError> {
+    let count = storage::cleanup_not_used_urls(&state.db_pool, query.days).await?;
+    Ok(Json(count))
+}
+
+/// Rows buffered between the database reader and the response body.
+const EXPORT_BUFFER_ROWS: usize = 64;
+
+async fn export_sql(
+    Extension(state): Extension<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    check_admin_token(&headers, &state)?;
+
+    let (tx, rx) = tokio::sync::mpsc::channel(EXPORT_BUFFER_ROWS);
+    let pool = state.db_pool.clone();
+    tokio::spawn(async move {
+        let mut urls = storage::stream_urls(&pool);
+        while let Some(url) = urls.next().await {
+            let statement = url.map(|url| storage::url_insert_statement(&url));
+            let failed = statement.is_err();
+            // Stop when the client disconnects or the query fails
+            if tx.send(statement).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+    let body = futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|statement| (statement, rx))
+    });
+
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        Body::from_stream(body),
+    ))
+}
+
+async fn vacuum_database(
+    Extension(state): Extension<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<storage::VacuumReport>, AppError> {
+    check_admin_token(&headers, &state)?;
+    let report = storage::vacuum(&state.db_pool).await?;
+    Ok(Json(report))
+}
+
+async fn get_code_capacity(
+    Extension(state): Extension<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<CodeCapacity>, AppError> {
+    check_admin_token(&headers, &state)?;
+    let max_id = storage::max_url_id(&state.db_pool).await?;
+    Ok(Json(code_capacity(max_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+
+    async fn test_state() -> Arc<AppState> {
+        Arc::new(AppState {
+            db_pool: storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+                .await
+                .unwrap(),
+            root_url: "http://localhost:3000".into(),
+            allowed_schemes: parse_allowed_schemes(None),
+            admin_token: Some("secret".to_string()),
+            click_monitor: ClickRateMonitor::new(
+                DEFAULT_CLICK_RATE_THRESHOLD,
+                Duration::from_secs(DEFAULT_CLICK_RATE_WINDOW_SECS),
+            ),
+            max_code_length: DEFAULT_MAX_CODE_LENGTH,
+            click_outbox: false,
+            canonical_host: None,
+            reject_self_links: false,
+            code_blocklist: Vec::new(),
+            country_header: parse_country_header(None),
+            click_dedup: None,
+            create_limiter: None,
+            trusted_proxy_hops: 0,
+        })
+    }
+
+    fn test_peer() -> ConnectInfo<SocketAddr> {
+        ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000)))
+    }
+
+    async fn seed_url(state: &AppState, original_url: &str) -> Url {
+        storage::create_url(&state.db_pool, original_url.to_string(), None, None, |id| {
+            vec![id_to_base62(id)]
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_healthz_and_readyz() {
+        let state = test_state().await;
+        assert_eq!(healthz().await, StatusCode::OK);
+        let (status, Json(ready)) = readyz(Extension(state.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ready.status, "ready");
+
+        // Forget the newest migration, as if it were still to run
+        sqlx::query(
+            "DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)",
+        )
+            .execute(&state.db_pool)
+            .await
+            .unwrap();
+        let (status, Json(ready)) = readyz(Extension(state.clone())).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(ready.reason.unwrap().starts_with("Pending migrations"));
+
+        state.db_pool.close().await;
+        let (status, Json(ready)) = readyz(Extension(state.clone())).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(ready.status, "not_ready");
+        assert!(ready.reason.unwrap().starts_with("Database unavailable"));
+        // Liveness doesn't depend on the database
+        assert_eq!(healthz().await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_root_content_negotiation() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, "application/json".parse().unwrap());
+        let response = root(headers).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let info: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(info["service"], "urlshortener");
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(info["status"], "ok");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, "text/html,*/*".parse().unwrap());
+        for headers in [headers, HeaderMap::new()] {
+            let response = root(headers).await;
+            assert!(
+                response.headers()[header::CONTENT_TYPE]
+                    .to_str()
+                    .unwrap()
+                    .starts_with("text/plain")
+            );
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, "Welcome to the URL Shortener!");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_url_return_modes() {
+        let state = test_state().await;
+        let body = || CreateURLBody {
+            original_url: "https://example.com/page".to_string(),
+            note: None,
+            alias: None,
+            expires_in_days: None,
+            force_new: None,
+        };
+        let read_body = |response: axum::response::Response| async move {
+            axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap()
+        };
+
+        let mut statuses = Vec::new();
+        for return_mode in [None, Some(ReturnMode::Minimal)] {
+            let response = create_url(
+                Extension(state.clone()),
+                HeaderMap::new(),
+                Query(CreateQuery { return_mode }),
+                Json(body()),
+            )
+            .await
+            .unwrap();
+            statuses.push(response.status());
+            let location = response.headers()[header::LOCATION].clone();
+            let code = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+            assert_eq!(location, format!("/{code}"));
+            let url = storage::get_url_by_short(&state.db_pool, &code)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(url.original_url, "https://example.com/page");
+        }
+        // Shortening the same URL again hands back the existing mapping
+        assert_eq!(statuses, [StatusCode::CREATED, StatusCode::OK]);
+
+        let response = create_url(
+            Extension(state.clone()),
+            HeaderMap::new(),
+            Query(CreateQuery {
+                return_mode: Some(ReturnMode::Full),
+            }),
+            Json(body()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let url: serde_json::Value = serde_json::from_slice(&read_body(response).await).unwrap();
+        assert_eq!(url["original_url"], "https://example.com/page");
+        assert_eq!(url["click_count"], 0);
+        assert_eq!(
+            url["short_url"],
+            id_to_base62(url["id"].as_i64().unwrap()).as_str()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_create_with_unique_original_url() {
+        let state = test_state().await;
+        storage::set_unique_original_url(&state.db_pool, true)
+            .await
+            .unwrap();
+        let create = || async {
+            let response = create_url(
+                Extension(state.clone()),
+                HeaderMap::new(),
+                Query(CreateQuery::default()),
+                Json(CreateURLBody {
+                    original_url: "https://example.com/race".to_string(),
+                    note: None,
+                    alias: None,
+                    expires_in_days: None,
+                    force_new: None,
+                }),
+            )
+            .await
+            .unwrap();
+            // One of the racing creates inserts, the other gets the existing mapping
+            assert!(
+                [StatusCode::CREATED, StatusCode::OK].contains(&response.status()),
+                "{}",
+                response.status()
+            );
+            axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap()
+        };
+
+        let (first, second) = tokio::join!(create(), create());
+        assert_eq!(first, second);
+        assert_eq!(storage::count_urls(&state.db_pool).await.unwrap(), 1);
+        // Sequential repeats keep resolving to the same mapping
+        assert_eq!(create().await, first);
+        assert_eq!(storage::count_urls(&state.db_pool).await.unwrap(), 1);
+
+        storage::set_unique_original_url(&state.db_pool, false)
+            .await
+            .unwrap();
+        // Without the index a sequential repeat is still answered from the existing row
+        assert_eq!(create().await, first);
+        assert_eq!(storage::count_urls(&state.db_pool).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_create_url_reuses_existing_code() {
+        let state = test_state().await;
+        let create = |original_url: &str, force_new: Option<bool>| {
+            create_url(
+                Extension(state.clone()),
+                HeaderMap::new(),
+                Query(CreateQuery::default()),
+                Json(CreateURLBody {
+                    original_url: original_url.to_string(),
+                    note: None,
+                    alias: None,
+                    expires_in_days: None,
+                    force_new,
+                }),
+            )
+        };
+        let code = |response: axum::response::Response| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+
+        let first = code(create("https://example.com/again", None).await.unwrap()).await;
+        let repeat = code(
+            create("https://example.com/again", Some(false))
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(repeat, first);
+        assert_eq!(storage::count_urls(&state.db_pool).await.unwrap(), 1);
+
+        let fresh = code(
+            create("https://example.com/again", Some(true))
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert_ne!(fresh, first);
+        assert_eq!(storage::count_urls(&state.db_pool).await.unwrap(), 2);
+
+        // An expired mapping isn't handed out again
+        let expiring = seed_url(&state, "https://example.com/expired").await;
+        sqlx::query("UPDATE url SET expires_at = ? WHERE id = ?")
+            .bind(chrono::Utc::now().naive_utc() - chrono::Duration::days(1))
+            .bind(expiring.id)
+            .execute(&state.db_pool)
+            .await
+            .unwrap();
+        let renewed = code(create("https://example.com/expired", None).await.unwrap()).await;
+        assert_ne!(renewed, expiring.short_url);
+        // Later creates reuse the live mapping rather than minting more duplicates
+        for _ in 0..3 {
+            let again = code(create("https://example.com/expired", None).await.unwrap()).await;
+            assert_eq!(again, renewed);
+        }
+        assert_eq!(storage::count_urls(&state.db_pool).await.unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_vacuum_database() {
+        let state = test_state().await;
+        for i in 0..200 {
+            let url = seed_url(
+                &state,
+                &format!("https://example.com/{i}/{}", "x".repeat(200)),
+            )
+            .await;
+            storage::delete_url(&state.db_pool, url.id.unwrap())
+                .await
+                .unwrap();
+        }
+
+        let unauthorized = vacuum_database(Extension(state.clone()), HeaderMap::new()).await;
+        assert_eq!(unauthorized.unwrap_err().status(), StatusCode::UNAUTHORIZED);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        let Json(report) = vacuum_database(Extension(state.clone()), headers)
+            .await
+            .unwrap();
+        assert!(report.size_before > 0);
+        assert!(report.size_after > 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_urls_by_time_range() {
+        let state = test_state().await;
+        for (path, created_at) in [
+            ("before", "2026-02-28 23:59:59"),
+            ("first", "2026-03-01 00:00:00"),
+            ("second", "2026-03-02 12:00:00"),
+            ("third", "2026-03-03 08:00:00"),
+            ("after", "2026-03-04 00:00:01"),
+        ] {
+            let url = seed_url(&state, &format!("https://example.com/{path}")).await;
+            sqlx::query("UPDATE url SET created_at = ? WHERE id = ?")
+                .bind(created_at)
+                .bind(url.id)
+                .execute(&state.db_pool)
+                .await
+                .unwrap();
+        }
+        let range = |start: &str, end: &str, limit: Option<i64>, offset: Option<i64>| {
+            get_urls_by_time_range(
+                Extension(state.clone()),
+                Query(TimeRangePage {
+                    limit: limit.unwrap_or(DEFAULT_PAGE_LIMIT),
+                    offset: offset.unwrap_or(0),
+                }),
+                Json(UrlTimeRange {
+                    start: start.to_string(),
+                    end: end.to_string(),
+                }),
+            )
+        };
+        let paths = |urls: Vec<Url>| -> Vec<String> {
+            urls.into_iter()
+                .map(|url| url.original_url.replace("https://example.com/", ""))
+                .collect()
+        };
+
+        let Json(urls) = range("2026-03-01T00:00:00Z", "2026-03-04T00:00:00Z", None, None)
+            .await
+            .unwrap();
+        assert_eq!(paths(urls), ["first", "second", "third"]);
+        // Offsets are converted to UTC
+        let Json(urls) = range(
+            "2026-03-02T13:00:00+01:00",
+            "2026-03-03T10:00:00+02:00",
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(paths(urls), ["second", "third"]);
+        let Json(urls) = range(
+            "2026-03-01T00:00:00Z",
+            "2026-03-04T00:00:00Z",
+            Some(1),
+            Some(1),
+        )
+        .await
+        .unwrap();
+        assert_eq!(paths(urls), ["second"]);
+
+        for (start, end, limit) in [
+            ("2026-03-04T00:00:00Z", "2026-03-01T00:00:00Z", None),
+            ("2026-03-01 00:00:00", "2026-03-04T00:00:00Z", None),
+            ("2026-03-01T00:00:00Z", "2026-03-04T00:00:00Z", Some(0)),
+            (
+                "2026-03-01T00:00:00Z",
+                "2026-03-04T00:00:00Z",
+                Some(MAX_PAGE_LIMIT + 1),
+            ),
+        ] {
+            let error = range(start, end, limit, None).await.unwrap_err();
+            assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_body_limit() {
+        let state = test_state().await;
+        let app = Router::new()
+            .route("/create", axum::routing::post(create_url))
+            .route("/urls", axum::routing::get(get_urls))
+            .layer(RequestBodyLimitLayer::new(1024))
+            .layer(Extension(state));
+        let create = |original_url: String| {
+            let body = serde_json::json!({ "original_url": original_url });
+            let request = axum::http::Request::builder()
+                .method(Method::POST)
+                .uri("/create")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(axum::body::Body::from(body.to_string()))
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        let huge = format!("https://example.com/{}", "x".repeat(2048));
+        let response = create(huge).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let response = create("https://example.com/small".to_string())
+            .await
+            .unwrap();
+        assert!(response.status().is_success(), "{}", response.status());
+
+        let request = axum::http::Request::builder()
+            .uri("/urls?limit=10&offset=0")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[TOTAL_COUNT_HEADER], "1");
+    }
+
+    #[tokio::test]
+    async fn test_create_is_rate_limited_per_ip() {
+        let app_with_hops = |trusted_proxy_hops: usize| async move {
+            let mut state = test_state().await;
+            let state_mut = Arc::get_mut(&mut state).unwrap();
+            state_mut.create_limiter = Some(RateLimiter::new(2, Duration::from_secs(60)));
+            state_mut.trusted_proxy_hops = trusted_proxy_hops;
+            Router::new()
+                .route(
+                    "/create",
+                    axum::routing::post(create_url)
+                        .route_layer(axum::middleware::from_fn(limit_create_rate)),
+                )
+                .layer(Extension(state))
+                .layer(axum::extract::connect_info::MockConnectInfo(
+                    SocketAddr::from(([127, 0, 0, 1], 40000)),
+                ))
+        };
+
+        // Without trusted proxies a forged X-Forwarded-For doesn't buy a fresh budget
+        let app = app_with_hops(0).await;
+        for (n, forged) in ["203.0.113.7", "198.51.100.1", "192.0.2.9"]
+            .iter()
+            .enumerate()
+        {
+            let request = axum::http::Request::builder()
+                .method(Method::POST)
+                .uri("/create")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("x-forwarded-for", *forged)
+                .body(axum::body::Body::from(format!(
+                    r#"{{"original_url": "https://example.com/{n}"}}"#
+                )))
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            let expected_limited = n >= 2;
+            assert_eq!(
+                response.status() == StatusCode::TOO_MANY_REQUESTS,
+                expected_limited,
+                "{forged}"
+            );
+        }
+
+        let app = app_with_hops(1).await;
+        let create = |n: usize, forwarded_for: &str| {
+            let request = axum::http::Request::builder()
+                .method(Method::POST)
+                .uri("/create")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("x-forwarded-for", forwarded_for)
+                .body(axum::body::Body::from(format!(
+                    r#"{{"original_url": "https://example.com/{n}"}}"#
+                )))
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        for n in 0..2 {
+            let response = create(n, "203.0.113.7").await.unwrap();
+            assert!(response.status().is_success(), "{}", response.status());
+        }
+        let response = create(2, "203.0.113.7").await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response.headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=60).contains(&retry_after));
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "error": "rate_limited", "retry_after_seconds": retry_after })
+        );
+
+        // Other clients behind the trusted proxy have their own budget, but only the
+        // entry the proxy appended counts
+        let response = create(3, "198.51.100.1").await.unwrap();
+        assert!(response.status().is_success());
+        let response = create(4, "192.0.2.1, 203.0.113.7").await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_get_url_details() {
+        let state = test_state().await;
+        let url = seed_url(&state, "https://example.com/audited").await;
+        let at = |timestamp: &str| timestamp.parse::<chrono::NaiveDateTime>().unwrap();
+        for (timestamp, country) in [
+            ("2026-03-01T10:00:00", Some("DE")),
+            ("2026-03-02T10:00:00", None),
+            ("2026-03-03T10:00:00", Some("DE")),
+        ] {
+            storage::record_click(&state.db_pool, url.id.unwrap(), at(timestamp), country)
+                .await
+                .unwrap();
+            storage::increment_click_count(&state.db_pool, &url.short_url)
+                .await
+                .unwrap();
+        }
+        let get_details = |short_url: &str| {
+            get_url_details(Extension(state.clone()), Path(short_url.to_string()))
+        };
+
+        let Json(details) = get_details(&url.short_url).await.unwrap();
+        assert_eq!(details.url.short_url, url.short_url);
+        assert_eq!(details.url.click_count, 3);
+        assert_eq!(details.recorded_clicks, 3);
+        assert!(!details.expired);
+        assert_eq!(details.clicks_by_country[0].country.as_deref(), Some("DE"));
+        assert_eq!(details.clicks_by_country[0].clicks, 2);
+        let recent: Vec<_> = details
+            .recent_clicks
+            .iter()
+            .map(|click| click.clicked_at)
+            .collect();
+        assert_eq!(
+            recent,
+            [
+                at("2026-03-03T10:00:00"),
+                at("2026-03-02T10:00:00"),
+                at("2026-03-01T10:00:00")
+            ]
+        );
+        let value = serde_json::to_value(&details).unwrap();
+        assert_eq!(value["original_url"], "https://example.com/audited");
+        assert_eq!(
+            value["recent_clicks"][0]["clicked_at"],
+            "2026-03-03T10:00:00Z"
+        );
+
+        assert_eq!(
+            get_details("missing").await.unwrap_err().status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_click_analytics() {
+        let state = test_state().await;
+        let url = seed_url(&state, "https://example.com/analytics").await;
+        let at = |timestamp: &str| timestamp.parse::<chrono::NaiveDateTime>().unwrap();
+        for timestamp in [
+            "2026-03-01T00:00:00",
+            "2026-03-01T23:59:59",
+            "2026-03-03T12:00:00",
+            "2026-03-04T00:00:00",
+        ] {
+            storage::record_click(&state.db_pool, url.id.unwrap(), at(timestamp), None)
+                .await
+                .unwrap();
+        }
+        let analytics = |short_url: &str, from: &str, to: &str| {
+            get_click_analytics(
+                Extension(state.clone()),
+                Path(short_url.to_string()),
+                Query(AnalyticsQuery {
+                    from: Some(from.parse().unwrap()),
+                    to: Some(to.parse().unwrap()),
+                }),
+            )
+        };
+
+        let Json(days) = analytics(&url.short_url, "2026-03-01", "2026-03-03")
+            .await
+            .unwrap();
+        let days: Vec<(String, i64)> = days
+            .into_iter()
+            .map(|day| (day.day.to_string(), day.clicks))
+            .collect();
+        assert_eq!(
+            days,
+            [
+                ("2026-03-01".to_string(), 2),
+                ("2026-03-02".to_string(), 0),
+                ("2026-03-03".to_string(), 1),
+            ]
+        );
+
+        let Json(days) = get_click_analytics(
+            Extension(state.clone()),
+            Path(url.short_url.clone()),
+            Query(AnalyticsQuery::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(days.len(), DEFAULT_ANALYTICS_DAYS as usize);
+        assert_eq!(days.last().unwrap().day, chrono::Utc::now().date_naive());
+
+        let reversed = analytics(&url.short_url, "2026-03-03", "2026-03-01").await;
+        assert_eq!(reversed.unwrap_err().status(), StatusCode::BAD_REQUEST);
+        let too_long = analytics(&url.short_url, "2024-01-01", "2026-03-01").await;
+        assert_eq!(too_long.unwrap_err().status(), StatusCode::BAD_REQUEST);
+        let missing = analytics("missing", "2026-03-01", "2026-03-03").await;
+        assert_eq!(missing.unwrap_err().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_qr_code() {
+        let state = test_state().await;
+        let url = seed_url(&state, "https://example.com/printed").await;
+        let qr = |short_url: &str, size: Option<u32>| {
+            get_qr_code(
+                Extension(state.clone()),
+                Path(short_url.to_string()),
+                Query(QrQuery { size }),
+            )
+        };
+
+        let response = qr(&url.short_url, None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let image = image::load_from_memory_with_format(&bytes, ImageFormat::Png).unwrap();
+        assert_eq!(image.width(), image.height());
+        assert!(image.width() <= DEFAULT_QR_SIZE);
+        assert!(image.width() > DEFAULT_QR_SIZE / 2);
+
+        let response = qr(&url.short_url, Some(MAX_QR_SIZE)).await.unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let image = image::load_from_memory_with_format(&bytes, ImageFormat::Png).unwrap();
+        assert!(image.width() > DEFAULT_QR_SIZE && image.width() <= MAX_QR_SIZE);
+
+        assert_eq!(
+            qr("missing", None).await.unwrap_err().status(),
+            StatusCode::NOT_FOUND
+        );
+        for size in [MIN_QR_SIZE - 1, MAX_QR_SIZE + 1] {
+            assert_eq!(
+                qr(&url.short_url, Some(size)).await.unwrap_err().status(),
+                StatusCode::BAD_REQUEST
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_code_capacity() {
+        let state = test_state().await;
+        for i in 0..3 {
+            seed_url(&state, &format!("https://example.com/capacity/{i}")).await;
+        }
+
+        let unauthorized = get_code_capacity(Extension(state.clone()), HeaderMap::new()).await;
+        assert_eq!(unauthorized.unwrap_err().status(), StatusCode::UNAUTHORIZED);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        let Json(capacity) = get_code_capacity(Extension(state.clone()), headers)
+            .await
+            .unwrap();
+        // The 0x01 prefix byte makes every positive i64 id an 11-character code
+        assert_eq!(
+            capacity,
+            CodeCapacity {
+                max_id: 3,
+                code_length: 11,
+                codes_at_length: i64::MAX as u64,
+                codes_remaining: i64::MAX as u64 - 3,
+                recommended_length: 11,
+            }
+        );
+        assert_eq!(ids_with_code_length_at_most(10), 0);
+        assert_eq!(id_to_base62(i64::MAX).len(), 11);
+    }
+
+    #[tokio::test]
+    async fn test_redirect_canonical_host() {
+        let mut state = test_state().await;
+        Arc::get_mut(&mut state).unwrap().canonical_host = Some("sho.rt".to_string());
+        let url = seed_url(&state, "https://example.com/canonical").await;
+        let request_on = |host: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::HOST, host.parse().unwrap());
+            redirect(
+                Extension(state.clone()),
+                test_peer(),
+                headers,
+                Path(url.short_url.clone()),
+            )
+        };
+
+        let response = request_on("www.sho.rt").await.unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            format!("http://sho.rt/{}", url.short_url)
+        );
+        let clicks = storage::get_url_by_short(&state.db_pool, &url.short_url)
+            .await
+            .unwrap()
+            .unwrap()
+            .click_count;
+        assert_eq!(clicks, 0);
+
+        let response = request_on("SHO.RT").await.unwrap();
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://example.com/canonical"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clicks_by_country() {
+        let mut state = test_state().await;
+        Arc::get_mut(&mut state).unwrap().country_header =
+            parse_country_header(Some("CF-IPCountry"));
+        let url = seed_url(&state, "https://example.com/geo").await;
+        let click_from = |country: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(country) = country {
+                headers.insert("cf-ipcountry", country.parse().unwrap());
+            }
+            redirect(
+                Extension(state.clone()),
+                test_peer(),
+                headers,
+                Path(url.short_url.clone()),
+            )
+        };
+        for country in [
+            Some("de"),
+            Some("DE"),
+            Some("US"),
+            None,
+            Some("not a country"),
+        ] {
+            assert!(click_from(country).await.is_ok());
+        }
+
+        let Json(by_country) =
+            get_clicks_by_country(Extension(state.clone()), Path(url.short_url.clone()))
+                .await
+                .unwrap();
+        let country_clicks = |country: Option<&str>, clicks: i64| storage::CountryClicks {
+            country: country.map(String::from),
+            clicks,
+        };
+        assert_eq!(
+            by_country,
+            [
+                country_clicks(None, 2),
+                country_clicks(Some("DE"), 2),
+                country_clicks(Some("US"), 1),
+            ]
+        );
+        assert_eq!(
+            get_clicks_by_country(Extension(state.clone()), Path("missing".to_string()))
+                .await
+                .unwrap_err()
+                .status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(parse_country_header(None), None);
+        assert_eq!(parse_country_header(Some("")), None);
+        assert_eq!(
+            parse_country_header(Some("X-Country")),
+            Some(HeaderName::from_static("x-country"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_url_timestamps_are_rfc3339_utc() {
+        let state = test_state().await;
+        let url = seed_url(&state, "https://example.com/stamped").await;
+        let value = serde_json::to_value(&url).unwrap();
+        let created_at = value["created_at"].as_str().unwrap().to_string();
+        assert!(created_at.ends_with('Z'), "{created_at}");
+        assert!(chrono::DateTime::parse_from_rfc3339(&created_at).is_ok());
+        assert!(value["expires_at"].is_null());
+        let round_trip: Url = serde_json::from_value(value).unwrap();
+        assert_eq!(round_trip.created_at, url.created_at);
+
+        let expires_at = "2025-06-25T10:00:00".parse::<chrono::NaiveDateTime>().ok();
+        let value = serde_json::to_value(Url { expires_at, ..url }).unwrap();
+        assert_eq!(value["expires_at"], "2025-06-25T10:00:00Z");
+    }
+
+    #[tokio::test]
+    async fn test_create_url_with_expiry() {
+        let state = test_state().await;
+        let create = |original_url: &str, expires_in_days: Option<i64>| {
+            create_url(
+                Extension(state.clone()),
+                HeaderMap::new(),
+                Query(CreateQuery {
+                    return_mode: Some(ReturnMode::Full),
+                }),
+                Json(CreateURLBody {
+                    original_url: original_url.to_string(),
+                    note: None,
+                    alias: None,
+                    expires_in_days,
+                    force_new: None,
+                }),
+            )
+        };
+        let created = |response: axum::response::Response| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<Url>(&bytes).unwrap()
+        };
+
+        let now = chrono::Utc::now().naive_utc();
+        let url = created(create("https://example.com/week", Some(7)).await.unwrap()).await;
+        let expires_at = url.expires_at.unwrap();
+        assert!(
+            (expires_at - (now + chrono::Duration::days(7)))
+                .num_seconds()
+                .abs()
+                < 5
+        );
+        let response = redirect(
+            Extension(state.clone()),
+            test_peer(),
+            HeaderMap::new(),
+            Path(url.short_url.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+
+        let url = created(create("https://example.com/forever", None).await.unwrap()).await;
+        assert_eq!(url.expires_at, None);
+
+        for days in [0, -1, MAX_EXTEND_DAYS + 1] {
+            let response = create("https://example.com/bad", Some(days)).await;
+            assert_eq!(response.unwrap_err().status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_extend_url_expiry() {
+        let state = test_state().await;
+        let now = chrono::Utc::now().naive_utc();
+        let seed_expiring = |path: &str, expires_at: chrono::NaiveDateTime| {
+            let state = state.clone();
+            let original_url = format!("https://example.com/{path}");
+            async move {
+                let url = seed_url(&state, &original_url).await;
+                sqlx::query("UPDATE url SET expires_at = ? WHERE id = ?")
+                    .bind(expires_at)
+                    .bind(url.id)
+                    .execute(&state.db_pool)
+                    .await
+                    .unwrap();
+                url.short_url
+            }
+        };
+        let extend = |short_url: &str, days: i64| {
+            extend_url_expiry(
+                Extension(state.clone()),
+                Path(short_url.to_string()),
+                Json(ExtendBody { days }),
+            )
+        };
+        let redirect_to = |short_url: &str| {
+            redirect(
+                Extension(state.clone()),
+                test_peer(),
+                HeaderMap::new(),
+                Path(short_url.to_string()),
+            )
+        };
+        let close_to = |actual: Option<chrono::NaiveDateTime>, expected: chrono::NaiveDateTime| {
+            (actual.unwrap() - expected).num_seconds().abs() < 5
+        };
+
+        // A live link is extended from its current expiry
+        let live = seed_expiring("live", now + chrono::Duration::days(5)).await;
+        let Json(url) = extend(&live, 30).await.unwrap();
+        assert!(close_to(url.expires_at, now + chrono::Duration::days(35)));
+
+        // An expired link is revived for the given days from now
+        let expired = seed_expiring("expired", now - chrono::Duration::days(2)).await;
+        assert_eq!(
+            redirect_to(&expired).await.unwrap_err().status(),
+            StatusCode::GONE
+        );
+        let clicks = storage::get_url_by_short(&state.db_pool, &expired)
+            .await
+            .unwrap()
+            .unwrap()
+            .click_count;
+        assert_eq!(clicks, 0);
+        let Json(url) = extend(&expired, 30).await.unwrap();
+        assert!(close_to(url.expires_at, now + chrono::Duration::days(30)));
+        let response = redirect_to(&expired).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+
+        // Links without an expiry keep none
+        let forever = seed_url(&state, "https://example.com/forever").await;
+        let Json(url) = extend(&forever.short_url, 30).await.unwrap();
+        assert_eq!(url.expires_at, None);
+
+        assert_eq!(
+            extend(&live, 0).await.unwrap_err().status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            extend("missing", 30).await.unwrap_err().status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bulk_extend_url_expiry() {
+        let state = test_state().await;
+        let now = chrono::Utc::now().naive_utc();
+        let mut codes = Vec::new();
+        for path in ["spring", "summer"] {
+            let url = seed_url(&state, &format!("https://example.com/{path}")).await;
+            sqlx::query("UPDATE url SET expires_at = ? WHERE id = ?")
+                .bind(now + chrono::Duration::days(5))
+                .bind(url.id)
+                .execute(&state.db_pool)
+                .await
+                .unwrap();
+            codes.push(url.short_url);
+        }
+        let extend = |codes: Vec<String>, days: i64| {
+            bulk_extend_expiry(
+                Extension(state.clone()),
+                Json(BulkExtendBody { codes, days }),
+            )
+        };
+
+        let never_expires = seed_url(&state, "https://example.com/evergreen").await;
+
+        // A repeated code is extended once, and a link without an expiry keeps none
+        let mut with_extras = codes.clone();
+        with_extras.push(codes[0].clone());
+        with_extras.push(never_expires.short_url.clone());
+        with_extras.push("unknown".to_string());
+        let Json(response) = extend(with_extras, 30).await.unwrap();
+        assert_eq!(response.updated, 2);
+        let evergreen = storage::get_url_by_short(&state.db_pool, &never_expires.short_url)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(evergreen.expires_at, None);
+        for code in &codes {
+            let url = storage::get_url_by_short(&state.db_pool, code)
+                .await
+                .unwrap()
+                .unwrap();
+            let expected = now + chrono::Duration::days(35);
+            assert!((url.expires_at.unwrap() - expected).num_seconds().abs() < 5);
+        }
+
+        assert_eq!(
+            extend(codes, 0).await.unwrap_err().status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redirect_dedup_counts_rapid_repeats_once() {
+        let mut state = test_state().await;
+        let state_mut = Arc::get_mut(&mut state).unwrap();
+        state_mut.click_dedup = Some(ClickDeduplicator::new(Duration::from_secs(2)));
+        state_mut.trusted_proxy_hops = 1;
+        let url = seed_url(&state, "https://example.com/dedup").await;
+        let click_from = |forwarded_for: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-forwarded-for", forwarded_for.parse().unwrap());
+            redirect(
+                Extension(state.clone()),
+                test_peer(),
+                headers,
+                Path(url.short_url.clone()),
+            )
+        };
+        let clicks = || async {
+            storage::get_url_by_short(&state.db_pool, &url.short_url)
+                .await
+                .unwrap()
+                .unwrap()
+                .click_count
+        };
+
+        for _ in 0..2 {
+            let response = click_from("203.0.113.7").await.unwrap();
+            assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        }
+        assert_eq!(clicks().await, 1);
+        assert!(click_from("10.0.0.1, 198.51.100.2").await.is_ok());
+        assert_eq!(clicks().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_redirect_rejects_over_length_code_without_query() {
+        let state = test_state().await;
+        let url = seed_url(&state, "https://example.com/target").await;
+        let redirect_to = |code: String| {
+            redirect(
+                Extension(state.clone()),
+                test_peer(),
+                HeaderMap::new(),
+                Path(code),
+            )
+        };
+
+        assert!(redirect_to(url.short_url.clone()).await.is_ok());
+
+        // With the pool closed any query fails, so a 404 proves none was issued
+        state.db_pool.close().await;
+        let too_long = "a".repeat(DEFAULT_MAX_CODE_LENGTH + 1);
+        assert_eq!(
+            redirect_to(too_long).await.unwrap_err().status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            redirect_to(url.short_url).await.unwrap_err().status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_sql_escapes_values() {
+        let state = test_state().await;
+        let url = seed_url(&state, "https://example.com/it's?q='x'").await;
+
+        let unauthorized = export_sql(Extension(state.clone()), HeaderMap::new()).await;
+        assert_eq!(
+            unauthorized.err().unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        let response = export_sql(Extension(state.clone()), headers)
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let dump = String::from_utf8(body.to_vec()).unwrap();
+        assert!(dump.starts_with("INSERT INTO url ("));
+        assert!(dump.contains("'https://example.com/it''s?q=''x'''"));
+
+        // The dump must replay cleanly into an empty database
+        let target = storage::init_db("sqlite::memory:", &storage::PoolConfig::default())
+            .await
+            .unwrap();
+        sqlx::raw_sql(&dump).execute(&target).await.unwrap();
+        let restored = storage::get_url_by_short(&target, &url.short_url)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(restored.original_url, url.original_url);
+        assert_eq!(restored.id, url.id);
+    }
+
+    #[tokio::test]
+    async fn test_pretty_json() {
+        let state = test_state().await;
+        seed_url(&state, "https://example.com/pretty").await;
+        let app = Router::new()
+            .route("/urls", axum::routing::get(get_urls))
+            .layer(axum::middleware::from_fn(pretty_json))
+            .layer(Extension(state));
+        let body = |uri: &str, header: Option<&str>| {
+            let mut request = axum::http::Request::builder().uri(uri);
+            if let Some(value) = header {
+                request = request.header("x-pretty", value);
+            }
+            let response = app.clone().oneshot(request.body(Body::empty()).unwrap());
+            async move {
+                let bytes = axum::body::to_bytes(response.await.unwrap().into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                String::from_utf8(bytes.to_vec()).unwrap()
+            }
+        };
+
+        let compact = body("/urls?limit=10&offset=0", None).await;
+        assert!(!compact.contains('\n'));
+        let pretty = body("/urls?limit=10&offset=0&pretty=true", None).await;
+        assert!(pretty.starts_with("[\n  {\n    \""));
+        assert_eq!(body("/urls?limit=10&offset=0", Some("true")).await, pretty);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&pretty).unwrap(),
+            serde_json::from_str::<serde_json::Value>(&compact).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_rejects_self_links() {
+        let mut state = test_state().await;
+        // Off by default, so the same self link is accepted until the check is turned on
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, "sho.rt".parse().unwrap());
+        let allowed = create_url(
+            Extension(state.clone()),
+            headers,
+            Query(CreateQuery::default()),
+            Json(CreateURLBody {
+                original_url: "https://sho.rt/loop".to_string(),
+                note: None,
+                alias: None,
+                expires_in_days: None,
+                force_new: None,
+            }),
+        )
+        .await;
+        assert!(allowed.is_ok());
+        Arc::get_mut(&mut state).unwrap().reject_self_links = true;
+        let create = |state: &Arc<AppState>, host: &str, original_url: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::HOST, host.parse().unwrap());
+            create_url(
+                Extension(state.clone()),
+                headers,
+                Query(CreateQuery::default()),
+                Json(CreateURLBody {
+                    original_url: original_url.to_string(),
+                    note: None,
+                    alias: None,
+                    expires_in_days: None,
+                    force_new: None,
+                }),
+            )
+        };
+
+        for self_link in [
+            "http://localhost:3000/abc",
+            "https://LOCALHOST/abc",
+            "https://sho.rt:8443/def?x=1",
+            "http://user@sho.rt./abc",
+        ] {
+            let rejected = create(&state, "sho.rt", self_link).await;
+            assert_eq!(
+                rejected.unwrap_err().status(),
+                StatusCode::BAD_REQUEST,
+                "{self_link}"
+            );
+        }
+        for external in ["https://example.com/abc", "https://short.example/sho.rt"] {
+            assert!(
+                create(&state, "sho.rt", external).await.is_ok(),
+                "{external}"
+            );
+        }
+
+        Arc::get_mut(&mut state).unwrap().reject_self_links = false;
+        assert!(create(&state, "sho.rt", "https://sho.rt/abc").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_get_click_counts_batch() {
+        let state = test_state().await;
+        let busy = seed_url(&state, "https://example.com/busy").await;
+        let idle = seed_url(&state, "https://example.com/idle").await;
+        for _ in 0..3 {
+            storage::increment_click_count(&state.db_pool, &busy.short_url)
+                .await
+                .unwrap();
+        }
+        let batch = |codes: Vec<String>| {
+            get_click_counts(Extension(state.clone()), Json(ClickBatchBody { codes }))
+        };
+
+        let Json(counts) = batch(vec![
+            busy.short_url.clone(),
+            idle.short_url.clone(),
+            "unknown".to_string(),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(&counts).unwrap(),
+            serde_json::json!({
+                busy.short_url.clone(): 3,
+                idle.short_url.clone(): 0,
+                "unknown": null,
+            })
+        );
+
+        let Json(empty) = batch(Vec::new()).await.unwrap();
+        assert!(empty.is_empty());
+        let too_many = vec!["x".to_string(); MAX_BATCH_CODES + 1];
+        assert_eq!(
+            batch(too_many).await.unwrap_err().status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_suggest_code_is_unused() {
+        let state = test_state().await;
+        let mut existing = Vec::new();
+        for n in 0..5 {
+            let url = seed_url(&state, &format!("https://example.com/{n}")).await;
+            existing.push(url.short_url);
+        }
+        for _ in 0..20 {
+            let Json(SuggestedCode { code }) =
+                suggest_code(Extension(state.clone())).await.unwrap();
+            assert_eq!(code.len(), SUGGESTED_CODE_LENGTH);
+            assert!(validate_alias(&code, state.max_code_length).is_ok());
+            assert!(!existing.contains(&code));
+            assert!(
+                storage::get_url_by_short(&state.db_pool, &code)
+                    .await
+                    .unwrap()
+                    .is_none()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generated_code_skips_blocked_sequences() {
+        let mut state = test_state().await;
+        // The first row gets id 1, so block its natural code
+        let blocked = id_to_base62(1);
+        Arc::get_mut(&mut state).unwrap().code_blocklist =
+            parse_code_blocklist(Some(&format!(" {} ,", blocked.to_uppercase())));
+        let create = |original_url: &str| {
+            create_url(
+                Extension(state.clone()),
+                HeaderMap::new(),
+                Query(CreateQuery {
+                    return_mode: Some(ReturnMode::Full),
+                }),
+                Json(CreateURLBody {
+                    original_url: original_url.to_string(),
+                    note: None,
+                    alias: None,
+                    expires_in_days: None,
+                    force_new: None,
+                }),
+            )
+        };
+        let created = |response: axum::response::Response| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<Url>(&bytes).unwrap()
+        };
+
+        let first = created(create("https://example.com/1").await.unwrap()).await;
+        assert_eq!(first.id, Some(1));
+        assert_ne!(first.short_url, blocked);
+        assert!(!is_blocked_code(&first.short_url, &state.code_blocklist));
+        assert_eq!(first.short_url, id_to_base62(1 + CODE_CANDIDATE_STRIDE));
+
+        let second = created(create("https://example.com/2").await.unwrap()).await;
+        assert_eq!(second.short_url, id_to_base62(2));
+        assert!(is_blocked_code(
+            &format!("x{blocked}x"),
+            &state.code_blocklist
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_create_url_with_alias() {
+        let mut state = test_state().await;
+        Arc::get_mut(&mut state).unwrap().code_blocklist = parse_code_blocklist(Some("darn"));
+        let create = |original_url: &str, alias: Option<&str>| {
+            create_url(
+                Extension(state.clone()),
+                HeaderMap::new(),
+                Query(CreateQuery {
+                    return_mode: Some(ReturnMode::Minimal),
+                }),
+                Json(CreateURLBody {
+                    original_url: original_url.to_string(),
+                    note: None,
+                    alias: alias.map(String::from),
+                    expires_in_days: None,
+                    force_new: None,
+                }),
+            )
+        };
+        let code = |response: axum::response::Response| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+
+        let response = create("https://example.com/docs", Some("my-docs_2")).await;
+        assert_eq!(code(response.unwrap()).await, "my-docs_2");
+        let stored = storage::get_url_by_short(&state.db_pool, "my-docs_2")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.original_url, "https://example.com/docs");
+
+        // Without an alias the id-based code is used
+        let response = create("https://example.com/other", None).await;
+        assert_eq!(code(response.unwrap()).await, id_to_base62(2));
+
+        let taken = create("https://example.com/else", Some("my-docs_2")).await;
+        assert_eq!(taken.unwrap_err().status(), StatusCode::CONFLICT);
+        let blocked = create("https://example.com/else", Some("DarnIt")).await;
+        assert_eq!(blocked.unwrap_err().status(), StatusCode::CONFLICT);
+        for invalid in [
+            "ab",
+            "has space",
+            "slash/y",
+            &"a".repeat(MAX_ALIAS_LENGTH + 1),
+            "urls",
+            "Create",
+            "cleanup",
+        ] {
+            let response = create("https://example.com/else", Some(invalid)).await;
+            assert_eq!(
+                response.unwrap_err().status(),
+                StatusCode::BAD_REQUEST,
+                "{invalid}"
+            );
+        }
+
+        // An alias holding the code the next plain create would be given pushes that
+        // create on to its next candidate
+        let response = create("https://example.com/claimed", Some(&id_to_base62(4))).await;
+        assert_eq!(code(response.unwrap()).await, id_to_base62(4));
+        let response = create("https://example.com/next", None).await;
+        let next = code(response.unwrap()).await;
+        assert_eq!(next, id_to_base62(4 + CODE_CANDIDATE_STRIDE));
+    }
+
+    #[tokio::test]
+    async fn test_url_note() {
+        let state = test_state().await;
+        let response = create_url(
+            Extension(state.clone()),
+            HeaderMap::new(),
+            Query(CreateQuery {
+                return_mode: Some(ReturnMode::Full),
+            }),
+            Json(CreateURLBody {
+                original_url: "https://example.com/noted".to_string(),
+                note: Some("Newsletter link".to_string()),
+                alias: None,
+                expires_in_days: None,
+                force_new: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let created: Url = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(created.note.as_deref(), Some("Newsletter link"));
+        let listed_note = || async {
+            let query = GetURLQuery {
+                limit: 10,
+                offset: 0,
+                sort_by: UrlSortBy::default(),
+                order: SortOrder::default(),
+            };
+            let (_, Json(urls)) = get_urls(Extension(state.clone()), Query(query))
+                .await
+                .unwrap();
+            urls.into_iter()
+                .find(|url| url.id == created.id)
+                .unwrap()
+                .note
+        };
+        assert_eq!(listed_note().await.as_deref(), Some("Newsletter link"));
+
+        let set_note = |short_url: &str, note: Option<&str>| {
+            set_url_note(
+                Extension(state.clone()),
+                Path(short_url.to_string()),
+                Json(NoteBody {
+                    note: note.map(String::from),
+                }),
+            )
+        };
+        let Json(updated) = set_note(&created.short_url, Some("Spring campaign"))
+            .await
+            .unwrap();
+        assert_eq!(updated.note.as_deref(), Some("Spring campaign"));
+        assert_eq!(listed_note().await.as_deref(), Some("Spring campaign"));
+        let Json(status) =
+            get_url_status(Extension(state.clone()), Path(created.short_url.clone()))
+                .await
+                .unwrap();
+        assert_eq!(status.note.as_deref(), Some("Spring campaign"));
+
+        let Json(cleared) = set_note(&created.short_url, None).await.unwrap();
+        assert_eq!(cleared.note, None);
+        assert_eq!(listed_note().await, None);
+        assert_eq!(
+            set_note("missing", Some("x")).await.unwrap_err().status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight() {
+        let app = |allowed_origin: Option<&str>| {
+            Router::new()
+                .route("/items/{id}", axum::routing::delete(|| async { "deleted" }))
+                .layer(cors_layer(allowed_origin).unwrap())
+        };
+        let preflight = |origin: &str| {
+            axum::http::Request::builder()
+                .method(Method::OPTIONS)
+                .uri("/items/1")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "DELETE")
+                .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "if-none-match")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let app = app(Some("https://app.example.com"));
+        let response = app
+            .clone()
+            .oneshot(preflight("https://app.example.com"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert!(
+            headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+                .to_str()
+                .unwrap()
+                .contains("DELETE")
+        );
+        assert!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS]
+                .to_str()
+                .unwrap()
+                .contains("if-none-match")
+        );
+        let response = app
+            .oneshot(preflight("https://evil.example.com"))
+            .await
+            .unwrap();
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+
+        // Debug builds, which the tests are, allow any origin when none is configured
+        let response = Router::new()
+            .route("/items/{id}", axum::routing::delete(|| async { "deleted" }))
+            .layer(cors_layer(None).unwrap())
+            .oneshot(preflight("http://localhost:5173"))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(cors_layer(Some("not a\norigin")).is_err());
+    }
+
+    #[test]
+    fn test_parse_bind_addr() {
+        assert_eq!(
+            parse_bind_addr(None).unwrap(),
+            "0.0.0.0:3000".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            parse_bind_addr(Some("127.0.0.1:8080")).unwrap().port(),
+            8080
+        );
+        assert_eq!(
+            parse_bind_addr(Some("[::1]:3001")).unwrap(),
+            "[::1]:3001".parse::<SocketAddr>().unwrap()
+        );
+        for invalid in ["3000", "localhost:3000", "0.0.0.0:http", ""] {
+            let error = parse_bind_addr(Some(invalid)).unwrap_err();
+            assert!(error.contains("BIND_ADDR"), "{error}");
+        }
+    }
+
+    #[test]
+    fn test_security_headers_config() {
+        let defaults = security_headers(|_| None);
+        let names: Vec<_> = defaults.iter().map(|(name, _)| name.clone()).collect();
+        assert_eq!(
+            names,
+            [header::X_CONTENT_TYPE_OPTIONS, header::X_FRAME_OPTIONS]
+        );
+
+        let configured = security_headers(|key| match key {
+            "X_FRAME_OPTIONS" => Some(String::new()),
+            "CONTENT_SECURITY_POLICY" => Some("default-src 'none'".to_string()),
+            _ => None,
+        });
+        assert_eq!(
+            configured,
+            [
+                (
+                    header::X_CONTENT_TYPE_OPTIONS,
+                    HeaderValue::from_static("nosniff")
+                ),
+                (
+                    header::CONTENT_SECURITY_POLICY,
+                    HeaderValue::from_static("default-src 'none'")
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_security_headers_on_response() {
+        let headers = security_headers(|key| {
+            (key == "CONTENT_SECURITY_POLICY").then(|| "default-src 'none'".to_string())
+        });
+        let response = Router::new()
+            .route("/", axum::routing::get(root))
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(headers),
+                add_security_headers,
+            ))
+            .oneshot(
+                axum::http::Request::builder()
+                    .uri("/")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "DENY");
+        assert_eq!(
+            headers[header::CONTENT_SECURITY_POLICY],
+            "default-src 'none'"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_urls_total_count_header() {
+        let state = test_state().await;
+        for n in 0..3 {
+            seed_url(&state, &format!("https://example.com/{n}")).await;
+        }
+        for (offset, page_len) in [(0, 2), (2, 1)] {
+            let query = GetURLQuery {
+                limit: 2,
+                offset,
+                sort_by: UrlSortBy::default(),
+                order: SortOrder::default(),
+            };
+            let ([(name, total)], Json(urls)) = get_urls(Extension(state.clone()), Query(query))
+                .await
+                .unwrap();
+            assert_eq!(name, TOTAL_COUNT_HEADER);
+            assert_eq!(total, "3");
+            assert_eq!(urls.len(), page_len);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_urls_pagination_is_validated() {
+        let state = test_state().await;
+        seed_url(&state, "https://example.com/").await;
+        let query = |uri: &str| {
+            let uri: axum::http::Uri = uri.parse().unwrap();
+            Query::<GetURLQuery>::try_from_uri(&uri).unwrap()
+        };
+
+        // Both parameters may be left out
+        let Query(defaults) = query("/urls");
+        assert_eq!((defaults.limit, defaults.offset), (DEFAULT_PAGE_LIMIT, 0));
+        let (_, Json(urls)) = get_urls(Extension(state.clone()), Query(defaults))
+            .await
+            .unwrap();
+        assert_eq!(urls.len(), 1);
+        assert!(
+            get_urls(Extension(state.clone()), query("/urls?limit=500&offset=1"))
+                .await
+                .is_ok()
+        );
+
+        for uri in ["/urls?limit=0", "/urls?limit=501", "/urls?offset=-1"] {
+            let err = get_urls(Extension(state.clone()), query(uri))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, AppError::BadRequest(_)), "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_limit_concurrency_sheds_excess_requests() {
+        let release = Arc::new(Notify::new());
+        let slow = {
+            let release = release.clone();
+            move || async move {
+                release.notified().await;
+                "done"
+            }
+        };
+        let router = limit_concurrency(Router::new().route("/slow", axum::routing::get(slow)), 1);
+        let request = || {
+            axum::http::Request::builder()
+                .uri("/slow")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let first = tokio::spawn(router.clone().oneshot(request()));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let second = router.clone().oneshot(request()).await.unwrap();
+        assert_eq!(second.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        release.notify_one();
+        let first = first.await.unwrap().unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_wait_with_grace_period_times_out() {
+        let grace_period = Duration::from_millis(50);
+        let start = std::time::Instant::now();
+        let result = wait_with_grace_period(std::future::pending::<()>(), grace_period).await;
+        assert!(result.is_none());
+        assert!(start.elapsed() >= grace_period);
+    }
+
+    #[tokio::test]
+    async fn test_wait_with_grace_period_completes() {
+        let result = wait_with_grace_period(async { 42 }, Duration::from_secs(5)).await;
+        assert_eq!(result, Some(42));
+    }
+
+    #[test]
+    fn test_validate_url_allowed_scheme() {
+        let schemes = parse_allowed_schemes(None);
+        assert!(validate_url("https://example.com", &schemes).is_ok());
+        assert!(validate_url("HTTP://example.com", &schemes).is_ok());
+    }
+
+    #[test]
+    fn test_validate_url_disallowed_scheme() {
+        let schemes = parse_allowed_schemes(None);
+        let err = validate_url("ftp://example.com/file", &schemes).unwrap_err();
+        assert_eq!(err, "URL scheme must be one of: http, https");
+        assert!(validate_url("example.com", &schemes).is_err());
+        assert!(validate_url("http:example.com", &schemes).is_err());
+        assert!(validate_url("", &schemes).is_err());
+    }
 
-    match result {
-        Ok(count) => Ok(Json(count)),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    #[test]
+    fn test_validate_url_custom_allowlist() {
+        let schemes = parse_allowed_schemes(Some(" HTTPS, ftp ,mailto"));
+        assert_eq!(schemes, vec!["https", "ftp", "mailto"]);
+        assert!(validate_url("ftp://example.com/file", &schemes).is_ok());
+        assert!(validate_url("mailto:someone@example.com", &schemes).is_ok());
+        assert!(validate_url("http://example.com", &schemes).is_err());
+        assert_eq!(parse_allowed_schemes(Some(" , ")), vec!["http", "https"]);
     }
 }
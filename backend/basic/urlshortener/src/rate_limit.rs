@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Entries are pruned once the map holds this many, so idle clients don't pile up.
+const PRUNE_AT: usize = 4096;
+
+/// In-memory fixed-window limiter allowing each client `limit` requests per `window`.
+/// State is per process and resets on restart.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    /// Start of each client's current window and the requests seen in it
+    clients: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a request from `client` at `now`. Over the limit it isn't counted and the
+    /// error holds how long until the client's window resets.
+    pub fn check(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= PRUNE_AT {
+            clients.retain(|_, &mut (start, _)| now.duration_since(start) < self.window);
+        }
+        let (start, count) = clients.entry(client).or_insert((now, 0));
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= self.limit {
+            return Err(self.window - now.duration_since(*start));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_each_client_per_window() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let client: IpAddr = [10, 0, 0, 1].into();
+        let other: IpAddr = [10, 0, 0, 2].into();
+        let start = Instant::now();
+
+        assert!(limiter.check(client, start).is_ok());
+        assert!(
+            limiter
+                .check(client, start + Duration::from_secs(10))
+                .is_ok()
+        );
+        assert_eq!(
+            limiter.check(client, start + Duration::from_secs(15)),
+            Err(Duration::from_secs(45))
+        );
+        assert!(
+            limiter
+                .check(other, start + Duration::from_secs(15))
+                .is_ok()
+        );
+        // A new window starts once the old one has passed
+        assert!(
+            limiter
+                .check(client, start + Duration::from_secs(60))
+                .is_ok()
+        );
+    }
+}
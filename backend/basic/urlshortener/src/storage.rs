@@ -1,81 +1,490 @@
-use chrono::NaiveDateTime;
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use futures_util::stream::BoxStream;
 use serde::{Deserialize, Serialize};
 use sqlx::Sqlite;
 use sqlx::migrate::MigrateDatabase;
-use sqlx::{FromRow, SqlitePool};
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::{FromRow, QueryBuilder, SqliteConnection, SqlitePool};
 
 pub const DB_URL: &str = "sqlite://url.db";
 
-pub async fn init_db(db_url: &str) -> Result<sqlx::SqlitePool, sqlx::Error> {
+/// Connection pool tuning applied through `SqlitePoolOptions`.
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub min_connections: u32,
+    pub idle_timeout: Duration,
+    pub max_lifetime: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            min_connections: 1,
+            idle_timeout: Duration::from_secs(600),
+            max_lifetime: Duration::from_secs(1800),
+        }
+    }
+}
+
+pub async fn init_db(db_url: &str, config: &PoolConfig) -> Result<SqlitePool, sqlx::Error> {
     if !Sqlite::database_exists(db_url).await? {
         Sqlite::create_database(db_url).await?;
     }
-    let pool = sqlx::SqlitePool::connect(db_url).await?;
+    let pool = SqlitePoolOptions::new()
+        .min_connections(config.min_connections)
+        .idle_timeout(config.idle_timeout)
+        .max_lifetime(config.max_lifetime)
+        .connect(db_url)
+        .await?;
     sqlx::migrate!("./migrations").run(&pool).await?;
+    warm_up(&pool, config.min_connections).await?;
     Ok(pool)
 }
 
+/// Opens `min_connections` connections up front so the first requests don't pay for them.
+async fn warm_up(pool: &SqlitePool, min_connections: u32) -> Result<(), sqlx::Error> {
+    let mut connections = Vec::new();
+    for _ in 0..min_connections {
+        connections.push(pool.acquire().await?);
+    }
+    drop(connections);
+    Ok(())
+}
+
+/// Versions of the embedded migrations not yet applied to the database. Has to query the
+/// database, so an error also means it is unreachable.
+pub async fn pending_migrations(pool: &SqlitePool) -> Result<Vec<i64>, sqlx::Error> {
+    let applied: Vec<i64> =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success = 1")
+            .fetch_all(pool)
+            .await?;
+    let pending = sqlx::migrate!("./migrations")
+        .iter()
+        .map(|migration| migration.version)
+        .filter(|version| !applied.contains(version))
+        .collect();
+    Ok(pending)
+}
+
 #[derive(FromRow, Serialize, Deserialize, Debug, Clone)]
 pub struct Url {
     pub id: Option<i64>,
     pub original_url: String,
     pub short_url: String,
     pub click_count: i64,
+    #[serde(with = "rfc3339::option")]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339::option")]
     pub updated_at: Option<NaiveDateTime>,
+    /// Private label for the owner; never exposed on redirect
+    pub note: Option<String>,
+    /// Redirects answer `410 Gone` from this time on; `None` never expires
+    #[serde(with = "rfc3339::option")]
+    pub expires_at: Option<NaiveDateTime>,
 }
 
-pub async fn create_url(pool: &SqlitePool, original_url: String) -> Result<Url, sqlx::Error> {
+/// Serde helpers that write timestamps as RFC 3339 UTC strings, e.g. `2024-01-02T03:04:05Z`.
+/// Stored times are naive UTC; reading accepts any RFC 3339 offset as well as naive times.
+pub mod rfc3339 {
+    use chrono::{DateTime, NaiveDateTime, SecondsFormat};
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn format(time: &NaiveDateTime) -> String {
+        time.and_utc().to_rfc3339_opts(SecondsFormat::AutoSi, true)
+    }
+
+    pub fn parse(value: &str) -> Option<NaiveDateTime> {
+        DateTime::parse_from_rfc3339(value)
+            .map(|time| time.naive_utc())
+            .or_else(|_| value.parse::<NaiveDateTime>())
+            .ok()
+    }
+
+    pub fn serialize<S: Serializer>(
+        time: &NaiveDateTime,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format(time))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<NaiveDateTime, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        parse(&value).ok_or_else(|| D::Error::custom(format!("invalid timestamp '{value}'")))
+    }
+
+    /// The same for `Option<NaiveDateTime>`, writing `null` for `None`.
+    pub mod option {
+        use chrono::NaiveDateTime;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            time: &Option<NaiveDateTime>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match time {
+                Some(time) => super::serialize(time, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<NaiveDateTime>, D::Error> {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(with = "super")] NaiveDateTime);
+            let time = Option::<Wrapper>::deserialize(deserializer)?;
+            Ok(time.map(|Wrapper(time)| time))
+        }
+    }
+}
+
+/// Prefix of the random placeholder code a url holds until `create_url` assigns the real one.
+/// `~` never appears in generated codes or aliases, so no lookup can match a placeholder.
+const PENDING_CODE_PREFIX: &str = "~pending-";
+
+/// Inserts a url and assigns its short code in one transaction, so no other connection
+/// ever sees the row without a code. `short_urls_for` lists candidate codes for the new
+/// id; the first one not already stored is assigned, or the first one if all are taken,
+/// which fails with a unique violation.
+/// Until then the row holds a unique placeholder rather than an empty code.
+pub async fn create_url(
+    pool: &SqlitePool,
+    original_url: String,
+    note: Option<String>,
+    expires_at: Option<NaiveDateTime>,
+    short_urls_for: impl FnOnce(i64) -> Vec<String>,
+) -> Result<Url, sqlx::Error> {
     let now = chrono::Utc::now().naive_utc();
-    let url = sqlx::query_as!(
-        Url,
+    let mut tx = pool.begin().await?;
+    let id = sqlx::query_scalar!(
         r#"
-        INSERT INTO url (original_url, short_url, click_count, created_at, updated_at)
-        VALUES (?, '', ?, ?, ?)
-        RETURNING id, original_url, short_url, click_count, created_at, updated_at
+        INSERT INTO url (original_url, note, short_url, click_count, created_at, updated_at,
+            expires_at)
+        VALUES (?, ?, ? || lower(hex(randomblob(16))), ?, ?, ?, ?)
+        RETURNING id AS "id!"
         "#,
         original_url,
+        note,
+        PENDING_CODE_PREFIX,
         0,
         now,
-        now
+        now,
+        expires_at
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let candidates = short_urls_for(id);
+    let mut short_url = None;
+    for candidate in &candidates {
+        let taken = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM url WHERE short_url = ?) AS "taken!: bool""#,
+            candidate
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if !taken {
+            short_url = Some(candidate);
+            break;
+        }
+    }
+    let short_url = short_url
+        .or(candidates.first())
+        .ok_or_else(|| sqlx::Error::Protocol("no candidate short code".to_string()))?;
+    let url = sqlx::query_as!(
+        Url,
+        r#"
+        UPDATE url SET short_url = ? WHERE id = ?
+        RETURNING id, original_url, short_url, click_count, created_at, updated_at, note, expires_at
+        "#,
+        short_url,
+        id
     )
-    .fetch_one(pool)
+    .fetch_one(&mut *tx)
     .await?;
+    tx.commit().await?;
     Ok(url)
 }
 
-pub async fn get_urls(pool: &SqlitePool, limit: i64, offset: i64) -> Result<Vec<Url>, sqlx::Error> {
-    let urls = sqlx::query_as!(Url, "SELECT * FROM url LIMIT ? OFFSET ?", limit, offset)
+/// Adds or drops the UNIQUE index on `original_url`. With it in place a second insert of
+/// the same URL fails with a unique violation even when two creates race.
+pub async fn set_unique_original_url(pool: &SqlitePool, enabled: bool) -> Result<(), sqlx::Error> {
+    let statement = if enabled {
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_url_original_url_unique ON url(original_url)"
+    } else {
+        "DROP INDEX IF EXISTS idx_url_original_url_unique"
+    };
+    sqlx::query(statement).execute(pool).await?;
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UrlSortBy {
+    #[default]
+    CreatedAt,
+    ClickCount,
+    UpdatedAt,
+}
+
+impl UrlSortBy {
+    fn column(self) -> &'static str {
+        match self {
+            UrlSortBy::CreatedAt => "created_at",
+            UrlSortBy::ClickCount => "click_count",
+            UrlSortBy::UpdatedAt => "updated_at",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl SortOrder {
+    fn keyword(self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+pub async fn get_urls_sorted(
+    pool: &SqlitePool,
+    sort_by: UrlSortBy,
+    order: SortOrder,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<Url>, sqlx::Error> {
+    // Only whitelisted column names and keywords are interpolated, never user input
+    let sql = format!(
+        "SELECT * FROM url ORDER BY {column} {order}, id {order} LIMIT ? OFFSET ?",
+        column = sort_by.column(),
+        order = order.keyword()
+    );
+    let urls = sqlx::query_as::<_, Url>(&sql)
+        .bind(limit)
+        .bind(offset)
         .fetch_all(pool)
         .await?;
     Ok(urls)
 }
 
+/// One page of the urls created between `start` and `end` (both inclusive), oldest first.
+pub async fn get_urls_by_time_range(
+    pool: &SqlitePool,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<Url>, sqlx::Error> {
+    let urls = sqlx::query_as!(
+        Url,
+        r#"
+        SELECT * FROM url WHERE created_at BETWEEN ? AND ?
+        ORDER BY created_at, id LIMIT ? OFFSET ?
+        "#,
+        start,
+        end,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(urls)
+}
+
+pub async fn count_urls(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    let count = sqlx::query_scalar!("SELECT COUNT(*) FROM url")
+        .fetch_one(pool)
+        .await?;
+    Ok(count)
+}
+
+/// Streams every url row in id order without buffering the whole table.
+pub fn stream_urls(pool: &SqlitePool) -> BoxStream<'_, Result<Url, sqlx::Error>> {
+    sqlx::query_as!(Url, "SELECT * FROM url ORDER BY id").fetch(pool)
+}
+
+fn sql_text(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn sql_timestamp(value: Option<NaiveDateTime>) -> String {
+    value
+        .map(|time| sql_text(&time.format("%Y-%m-%d %H:%M:%S%.f").to_string()))
+        .unwrap_or_else(|| "NULL".to_string())
+}
+
+/// Renders a url row as a standalone INSERT statement with all text values escaped.
+pub fn url_insert_statement(url: &Url) -> String {
+    format!(
+        "INSERT INTO url (id, original_url, short_url, click_count, created_at, updated_at, note, \
+         expires_at) VALUES ({}, {}, {}, {}, {}, {}, {}, {});\n",
+        url.id
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| "NULL".to_string()),
+        sql_text(&url.original_url),
+        sql_text(&url.short_url),
+        url.click_count,
+        sql_timestamp(url.created_at),
+        sql_timestamp(url.updated_at),
+        url.note
+            .as_deref()
+            .map(sql_text)
+            .unwrap_or_else(|| "NULL".to_string()),
+        sql_timestamp(url.expires_at),
+    )
+}
+
 pub async fn get_url_by_short(
     pool: &SqlitePool,
     short_url: &str,
 ) -> Result<Option<Url>, sqlx::Error> {
+    if short_url.is_empty() || short_url.starts_with(PENDING_CODE_PREFIX) {
+        return Ok(None);
+    }
     let url = sqlx::query_as!(Url, "SELECT * FROM url WHERE short_url = ?", short_url)
         .fetch_optional(pool)
         .await?;
     Ok(url)
 }
 
-#[allow(dead_code)]
+/// The newest mapping of `original_url` that hasn't expired by `now`.
 pub async fn get_url_by_long(
     pool: &SqlitePool,
     original_url: &str,
+    now: NaiveDateTime,
 ) -> Result<Option<Url>, sqlx::Error> {
     let url = sqlx::query_as!(
         Url,
-        "SELECT * FROM url WHERE original_url = ?",
-        original_url
+        r#"
+        SELECT * FROM url
+        WHERE original_url = ? AND (expires_at IS NULL OR expires_at > ?)
+        ORDER BY id DESC
+        LIMIT 1
+        "#,
+        original_url,
+        now
     )
     .fetch_optional(pool)
     .await?;
     Ok(url)
 }
 
+/// Sets or clears (with `None`) a url's note. Returns `None` when the short code is unknown.
+pub async fn set_note(
+    pool: &SqlitePool,
+    short_url: &str,
+    note: Option<String>,
+) -> Result<Option<Url>, sqlx::Error> {
+    let now = chrono::Utc::now().naive_utc();
+    let url = sqlx::query_as!(
+        Url,
+        r#"
+        UPDATE url SET note = ?, updated_at = ?
+        WHERE short_url = ?
+        RETURNING id, original_url, short_url, click_count, created_at, updated_at, note, expires_at
+        "#,
+        note,
+        now,
+        short_url
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(url)
+}
+
+/// `UPDATE url SET ...` pushing `expires_at` to `days` after the later of `now` and the
+/// current expiry, up to `WHERE `, for the caller to finish.
+fn extend_expiry_update(days: i64, now: NaiveDateTime) -> QueryBuilder<'static, Sqlite> {
+    let mut query = QueryBuilder::new(
+        "UPDATE url SET expires_at = CASE WHEN expires_at IS NULL THEN NULL \
+            ELSE datetime(MAX(datetime(expires_at), datetime(",
+    );
+    query
+        .push_bind(now)
+        .push(")), ")
+        .push_bind(format!("+{days} days"))
+        .push(") END, updated_at = ")
+        .push_bind(now)
+        .push(" WHERE ");
+    query
+}
+
+/// Pushes `expires_at` to `days` after the later of `now` and the current expiry, so an
+/// expired link comes back to life for `days` from now. Links without an expiry keep none.
+pub async fn extend_expiry(
+    pool: &SqlitePool,
+    short_url: &str,
+    days: i64,
+    now: NaiveDateTime,
+) -> Result<Option<Url>, sqlx::Error> {
+    let mut query = extend_expiry_update(days, now);
+    query
+        .push("short_url = ")
+        .push_bind(short_url.to_string())
+        .push(
+            " RETURNING id, original_url, short_url, click_count, created_at, updated_at, note, \
+            expires_at",
+        );
+    let url = query.build_query_as::<Url>().fetch_optional(pool).await?;
+    Ok(url)
+}
+
+/// [`extend_expiry`] for every distinct code in `short_urls` in a single UPDATE, so a
+/// campaign's links are extended together or not at all. Unknown codes and links without
+/// an expiry are skipped; returns how many links were extended.
+pub async fn extend_expiry_bulk(
+    pool: &SqlitePool,
+    short_urls: &[String],
+    days: i64,
+    now: NaiveDateTime,
+) -> Result<u64, sqlx::Error> {
+    let unique: BTreeSet<&String> = short_urls.iter().collect();
+    if unique.is_empty() {
+        return Ok(0);
+    }
+    let mut query = extend_expiry_update(days, now);
+    query.push("expires_at IS NOT NULL AND short_url IN (");
+    let mut codes = query.separated(", ");
+    for short_url in unique {
+        codes.push_bind(short_url.clone());
+    }
+    query.push(")");
+    let result = query.build().execute(pool).await?;
+    Ok(result.rows_affected())
+}
+
+/// Click counts for the given short codes in one `IN (...)` query. Unknown codes are absent
+/// from the map.
+pub async fn get_click_counts(
+    pool: &SqlitePool,
+    short_urls: &[String],
+) -> Result<HashMap<String, i64>, sqlx::Error> {
+    if short_urls.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let mut query =
+        QueryBuilder::new("SELECT short_url, click_count FROM url WHERE short_url IN (");
+    let mut codes = query.separated(", ");
+    for short_url in short_urls {
+        codes.push_bind(short_url);
+    }
+    query.push(")");
+    let rows: Vec<(String, i64)> = query.build_query_as().fetch_all(pool).await?;
+    Ok(rows.into_iter().collect())
+}
+
 pub async fn increment_click_count(
     pool: &SqlitePool,
     short_url: &str,
@@ -86,7 +495,7 @@ pub async fn increment_click_count(
         UPDATE url
         SET click_count = click_count + 1
         WHERE short_url = ?
-        RETURNING id, original_url, short_url, click_count, created_at, updated_at
+        RETURNING id, original_url, short_url, click_count, created_at, updated_at, note, expires_at
         "#,
         short_url
     )
@@ -95,6 +504,163 @@ pub async fn increment_click_count(
     Ok(url)
 }
 
+pub async fn record_click(
+    pool: &SqlitePool,
+    url_id: i64,
+    timestamp: NaiveDateTime,
+    country: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO clicks (url_id, clicked_at, country) VALUES (?, ?, ?)",
+        url_id,
+        timestamp,
+        country
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CountryClicks {
+    /// `None` for clicks that arrived without a country header
+    pub country: Option<String>,
+    pub clicks: i64,
+}
+
+/// Recorded click events of one URL grouped by country, most clicks first.
+pub async fn get_clicks_by_country(
+    pool: &SqlitePool,
+    url_id: i64,
+) -> Result<Vec<CountryClicks>, sqlx::Error> {
+    let clicks = sqlx::query_as!(
+        CountryClicks,
+        r#"
+        SELECT country, COUNT(*) AS "clicks!: i64" FROM clicks
+        WHERE url_id = ?
+        GROUP BY country
+        ORDER BY COUNT(*) DESC, country
+        "#,
+        url_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(clicks)
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ClickEvent {
+    #[serde(with = "rfc3339")]
+    pub clicked_at: NaiveDateTime,
+    pub country: Option<String>,
+}
+
+/// The `limit` most recent recorded click events of one URL, newest first.
+pub async fn get_recent_clicks(
+    pool: &SqlitePool,
+    url_id: i64,
+    limit: i64,
+) -> Result<Vec<ClickEvent>, sqlx::Error> {
+    let clicks = sqlx::query_as!(
+        ClickEvent,
+        r#"
+        SELECT clicked_at, country FROM clicks
+        WHERE url_id = ?
+        ORDER BY clicked_at DESC, id DESC
+        LIMIT ?
+        "#,
+        url_id,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(clicks)
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DayClicks {
+    pub day: NaiveDate,
+    pub clicks: i64,
+}
+
+/// Recorded click events of one URL per UTC day from `from` through `to`, oldest first.
+/// Days without clicks are absent.
+pub async fn get_clicks_by_day(
+    pool: &SqlitePool,
+    url_id: i64,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<DayClicks>, sqlx::Error> {
+    // Half-open timestamp bounds keep the clicked_at index usable
+    let start = from.and_time(NaiveTime::MIN);
+    let end = (to + chrono::Duration::days(1)).and_time(NaiveTime::MIN);
+    let clicks = sqlx::query_as!(
+        DayClicks,
+        r#"
+        SELECT date(clicked_at) AS "day!: NaiveDate", COUNT(*) AS "clicks!: i64" FROM clicks
+        WHERE url_id = ? AND clicked_at >= ? AND clicked_at < ?
+        GROUP BY date(clicked_at)
+        ORDER BY date(clicked_at)
+        "#,
+        url_id,
+        start,
+        end
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(clicks)
+}
+
+/// Appends a pending click to the outbox; `fold_click_outbox` adds it to `click_count` later.
+pub async fn enqueue_click(
+    pool: &SqlitePool,
+    url_id: i64,
+    timestamp: NaiveDateTime,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO click_outbox (url_id, clicked_at) VALUES (?, ?)",
+        url_id,
+        timestamp
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Adds every pending outbox click to its url's `click_count` and prunes the folded rows,
+/// returning how many were folded. Both statements share one write transaction, so clicks
+/// enqueued concurrently wait for the commit and are never lost or counted twice.
+pub async fn fold_click_outbox(pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE url
+        SET click_count = click_count
+            + (SELECT COUNT(*) FROM click_outbox WHERE click_outbox.url_id = url.id)
+        WHERE id IN (SELECT url_id FROM click_outbox)
+        "#
+    )
+    .execute(&mut *tx)
+    .await?;
+    let result = sqlx::query!("DELETE FROM click_outbox")
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(result.rows_affected())
+}
+
+/// Deletes raw click events older than `days`; the aggregate `click_count` is left untouched.
+pub async fn purge_click_events_older_than(
+    pool: &SqlitePool,
+    days: i64,
+) -> Result<u64, sqlx::Error> {
+    let threshold = chrono::Utc::now().naive_utc() - chrono::Duration::days(days);
+    let result = sqlx::query!("DELETE FROM clicks WHERE clicked_at < ?", threshold)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
 pub async fn cleanup_not_used_urls(pool: &SqlitePool, days: i64) -> Result<u64, sqlx::Error> {
     let threshold = chrono::Utc::now().naive_utc() - chrono::Duration::days(days);
     let result = sqlx::query!("DELETE FROM url WHERE updated_at < ?", threshold)
@@ -111,13 +677,216 @@ pub async fn delete_url(pool: &SqlitePool, id: i64) -> Result<u64, sqlx::Error>
     Ok(result.rows_affected())
 }
 
-pub async fn update_short_url(
-    pool: &SqlitePool,
-    id: i64,
-    short_url: &str,
-) -> Result<u64, sqlx::Error> {
-    let result = sqlx::query!("UPDATE url SET short_url = ? WHERE id = ?", short_url, id)
-        .execute(pool)
+/// The highest id handed out so far, or 0 for an empty table.
+pub async fn max_url_id(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    let max_id = sqlx::query_scalar!(r#"SELECT COALESCE(MAX(id), 0) AS "max_id!: i64" FROM url"#)
+        .fetch_one(pool)
         .await?;
-    Ok(result.rows_affected())
+    Ok(max_id)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VacuumReport {
+    pub size_before: i64,
+    pub size_after: i64,
+}
+
+async fn database_size(conn: &mut SqliteConnection) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()")
+        .fetch_one(conn)
+        .await
+}
+
+/// Rebuilds the database file to reclaim space left by deletes, then refreshes the
+/// query planner statistics. VACUUM can't run inside a transaction, so everything is
+/// issued on one dedicated connection outside of any.
+pub async fn vacuum(pool: &SqlitePool) -> Result<VacuumReport, sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    let size_before = database_size(&mut conn).await?;
+    sqlx::query("VACUUM").execute(&mut *conn).await?;
+    sqlx::query("PRAGMA optimize").execute(&mut *conn).await?;
+    let size_after = database_size(&mut conn).await?;
+    Ok(VacuumReport {
+        size_before,
+        size_after,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn init_test_db() -> Result<SqlitePool, sqlx::Error> {
+        let db_url = "sqlite://test.db";
+        init_db(db_url, &PoolConfig::default()).await
+    }
+
+    async fn cleanup_test_db() -> Result<(), sqlx::Error> {
+        let db_url = "sqlite://test.db";
+        if Sqlite::database_exists(db_url).await? {
+            Sqlite::drop_database(db_url).await?;
+        }
+        Ok(())
+    }
+
+    async fn create_test_url(pool: &SqlitePool, original_url: &str, short_url: &str) -> Url {
+        create_url(pool, original_url.to_string(), None, None, |_| {
+            vec![short_url.to_string()]
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn test_purge_click_events(pool: &SqlitePool) {
+        let url = create_test_url(pool, "https://example.com/purge", "purge").await;
+        let url_id = url.id.unwrap();
+        let now = chrono::Utc::now().naive_utc();
+        for clicked_at in [now - chrono::Duration::days(10), now, now] {
+            increment_click_count(pool, "purge").await.unwrap();
+            record_click(pool, url_id, clicked_at, None).await.unwrap();
+        }
+
+        let purged = purge_click_events_older_than(pool, 5).await;
+        assert!(purged.is_ok());
+        assert_eq!(purged.unwrap(), 1);
+
+        let remaining = sqlx::query_scalar!("SELECT COUNT(*) FROM clicks WHERE url_id = ?", url_id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 2);
+        let url = get_url_by_short(pool, "purge").await.unwrap().unwrap();
+        assert_eq!(url.click_count, 3);
+    }
+
+    async fn test_fold_click_outbox(pool: &SqlitePool) {
+        let busy = create_test_url(pool, "https://example.com/outbox", "outbox-a").await;
+        let quiet = create_test_url(pool, "https://example.com/outbox", "outbox-b").await;
+        let now = chrono::Utc::now().naive_utc();
+        increment_click_count(pool, "outbox-a").await.unwrap();
+        for url in [&busy, &busy, &busy, &quiet] {
+            enqueue_click(pool, url.id.unwrap(), now).await.unwrap();
+        }
+
+        assert_eq!(fold_click_outbox(pool).await.unwrap(), 4);
+        let busy = get_url_by_short(pool, "outbox-a").await.unwrap().unwrap();
+        assert_eq!(busy.click_count, 4);
+        let quiet = get_url_by_short(pool, "outbox-b").await.unwrap().unwrap();
+        assert_eq!(quiet.click_count, 1);
+
+        let pending = sqlx::query_scalar!("SELECT COUNT(*) FROM click_outbox")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!(pending, 0);
+        // Folding again with nothing pending leaves the aggregates alone
+        assert_eq!(fold_click_outbox(pool).await.unwrap(), 0);
+        let busy = get_url_by_short(pool, "outbox-a").await.unwrap().unwrap();
+        assert_eq!(busy.click_count, 4);
+    }
+
+    async fn test_create_url_never_exposes_pending_code(pool: &SqlitePool) {
+        create_test_url(pool, "https://example.com/pending", "pending").await;
+        // The code is already taken, so assigning it fails and the insert is rolled back
+        let failed = create_url(
+            pool,
+            "https://example.com/pending-again".to_string(),
+            None,
+            None,
+            |_| vec!["pending".to_string()],
+        )
+        .await;
+        assert!(failed.is_err());
+
+        assert!(get_url_by_short(pool, "").await.unwrap().is_none());
+        assert!(
+            get_url_by_short(pool, PENDING_CODE_PREFIX)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let pattern = format!("{PENDING_CODE_PREFIX}%");
+        let half_created = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM url WHERE short_url = '' OR short_url LIKE ?",
+            pattern
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        assert_eq!(half_created, 0);
+        let rows = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM url WHERE original_url = 'https://example.com/pending-again'"
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        assert_eq!(rows, 0);
+    }
+
+    async fn test_get_urls_sorted(pool: &SqlitePool) {
+        for (short_url, clicks) in [("sort-a", 2), ("sort-b", 5), ("sort-c", 0)] {
+            create_test_url(pool, "https://example.com/sort", short_url).await;
+            for _ in 0..clicks {
+                increment_click_count(pool, short_url).await.unwrap();
+            }
+        }
+
+        let urls = get_urls_sorted(pool, UrlSortBy::ClickCount, SortOrder::Desc, 100, 0).await;
+        assert!(urls.is_ok());
+        let urls = urls.unwrap();
+        assert!(
+            urls.windows(2)
+                .all(|w| w[0].click_count >= w[1].click_count)
+        );
+        let position = |code: &str| urls.iter().position(|u| u.short_url == code).unwrap();
+        assert!(position("sort-b") < position("sort-a"));
+        assert!(position("sort-a") < position("sort-c"));
+
+        let urls = get_urls_sorted(pool, UrlSortBy::default(), SortOrder::default(), 100, 0)
+            .await
+            .unwrap();
+        assert_eq!(urls[0].short_url, "sort-c");
+        assert!(urls.windows(2).all(|w| w[0].created_at >= w[1].created_at));
+
+        let page = get_urls_sorted(pool, UrlSortBy::ClickCount, SortOrder::Asc, 1, 0)
+            .await
+            .unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].click_count, 0);
+        assert_eq!(count_urls(pool).await.unwrap(), urls.len() as i64);
+    }
+
+    #[tokio::test]
+    async fn test_pool_warm_up() {
+        let config = PoolConfig {
+            min_connections: 3,
+            ..PoolConfig::default()
+        };
+        let pool = init_db("sqlite::memory:", &config).await.unwrap();
+        for _ in 0..50 {
+            if pool.num_idle() >= 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(pool.num_idle() >= 3);
+    }
+
+    #[tokio::test]
+    async fn run_tests() {
+        let pool = init_test_db().await;
+
+        assert!(pool.is_ok());
+
+        let pool = pool.unwrap();
+
+        test_purge_click_events(&pool).await;
+        test_fold_click_outbox(&pool).await;
+        test_get_urls_sorted(&pool).await;
+        test_create_url_never_exposes_pending_code(&pool).await;
+
+        cleanup_test_db()
+            .await
+            .expect("Failed to clean up test database");
+    }
 }